@@ -0,0 +1,51 @@
+//! Compares reading the tiles of a cold cache one at a time against reading them in parallel the
+//! way `TileCache` does. Decompressing a snappy compressed tile stands in for reading one from the
+//! map file.
+#![feature(test)]
+
+extern crate test;
+
+use terra::bench::{read_tiles_with, LayerType};
+use terra::VNode;
+use test::Bencher;
+
+/// Tiles for every node in the first levels of the quadtree, in each of the three layers that are
+/// read from disk.
+fn cold_cache_pending() -> Vec<(LayerType, usize, VNode)> {
+    let mut nodes = Vec::new();
+    VNode::breadth_first(|node| {
+        nodes.push(node);
+        node.level() < 2
+    });
+    let mut pending = Vec::new();
+    for &ty in &[LayerType::Heightmaps, LayerType::Normals, LayerType::Albedo] {
+        pending.extend(nodes.iter().enumerate().map(|(slot, &node)| (ty, slot, node)));
+    }
+    pending
+}
+
+fn compressed_tile() -> Vec<u8> {
+    let data: Vec<u8> = (0..521 * 521 * 4).map(|i| (i % 251) as u8).collect();
+    snap::raw::Encoder::new().compress_vec(&data).unwrap()
+}
+
+fn decompress_tile(compressed: &[u8]) -> Option<Vec<u8>> {
+    snap::raw::Decoder::new().decompress_vec(compressed).ok()
+}
+
+#[bench]
+fn read_tiles_sequential(b: &mut Bencher) {
+    let compressed = compressed_tile();
+    b.iter(|| {
+        cold_cache_pending()
+            .into_iter()
+            .map(|(ty, slot, _)| (ty, slot, decompress_tile(&compressed).unwrap()))
+            .collect::<Vec<_>>()
+    });
+}
+
+#[bench]
+fn read_tiles_parallel(b: &mut Bencher) {
+    let compressed = compressed_tile();
+    b.iter(|| read_tiles_with(cold_cache_pending(), |_, _| decompress_tile(&compressed)));
+}
@@ -4,7 +4,7 @@ use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, Read};
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver};
 use std::time::{Duration, Instant};
 
@@ -16,6 +16,7 @@ pub struct ShaderDirectoryWatcher {
     watcher_rx: Receiver<DebouncedEvent>,
 
     last_modifications: HashMap<PathBuf, Instant>,
+    paused: bool,
 }
 impl ShaderDirectoryWatcher {
     pub fn new<P>(directory: P) -> Result<Self, notify::Error>
@@ -27,7 +28,46 @@ impl ShaderDirectoryWatcher {
         let mut watcher = notify::watcher(tx, Duration::from_millis(50))?;
         watcher.watch(&directory, RecursiveMode::Recursive)?;
 
-        Ok(Self { directory, _watcher: watcher, watcher_rx, last_modifications: HashMap::new() })
+        Ok(Self {
+            directory,
+            _watcher: watcher,
+            watcher_rx,
+            last_modifications: HashMap::new(),
+            paused: false,
+        })
+    }
+
+    /// Stops shaders from being refreshed. Changes made while paused are still recorded, and
+    /// trigger a single refresh of each affected shader once `resume` is called.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Allows shaders to be refreshed again after a call to `pause`.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns whether `filename`, relative to the watched directory, was written after `since`.
+    /// Always returns false while paused.
+    pub fn modified_since(&mut self, filename: &Path, since: Instant) -> bool {
+        self.detect_changes();
+        if self.paused {
+            return false;
+        }
+
+        // Events may report either the path as watched or its canonical form.
+        let path = self.directory.join(filename);
+        let modified = |p: &Path| self.last_modifications.get(p).map_or(false, |&t| t > since);
+        modified(&path) || fs::canonicalize(&path).map_or(false, |p| modified(&p))
     }
 
     fn detect_changes(&mut self) {
@@ -79,6 +119,9 @@ impl ShaderSet {
     /// Refreshes the shader if necessary. Returns whether a refresh happened.
     pub fn refresh(&mut self, directory_watcher: &mut ShaderDirectoryWatcher) -> bool {
         directory_watcher.detect_changes();
+        if directory_watcher.paused {
+            return false;
+        }
 
         if ![&self.vertex_source, &self.fragment_source, &self.compute_source]
             .into_iter()
@@ -131,3 +174,36 @@ impl ShaderSet {
         self.inner.compute.as_ref().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_coalesces_changes() {
+        let directory = std::env::temp_dir().join(format!("rshader-pause-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let directory = fs::canonicalize(directory).unwrap();
+        let filename = directory.join("a.comp");
+        fs::write(&filename, "#version 450\nlayout(local_size_x = 1) in;\nvoid main() {}\n")
+            .unwrap();
+
+        let mut watcher = ShaderDirectoryWatcher::new(directory.clone()).unwrap();
+        let source =
+            ShaderSource { source: None, filenames: Some(vec![filename.clone()]), defines: vec![] };
+        let mut shader = ShaderSet::compute_only(&mut watcher, source).unwrap();
+
+        watcher.pause();
+        for _ in 0..2 {
+            std::thread::sleep(Duration::from_millis(10));
+            watcher.last_modifications.insert(filename.clone(), Instant::now());
+            assert!(!shader.refresh(&mut watcher));
+        }
+
+        watcher.resume();
+        assert!(shader.refresh(&mut watcher));
+        assert!(!shader.refresh(&mut watcher));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
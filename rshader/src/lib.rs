@@ -20,11 +20,19 @@ pub use static_shaders::*;
 pub struct ShaderSource {
     pub source: Option<String>,
     pub filenames: Option<Vec<PathBuf>>,
+    /// Preprocessor macros to define, inserted directly after the `#version` directive.
+    pub defines: Vec<(String, String)>,
 }
 impl ShaderSource {
+    /// Adds a `#define name value` to the shader.
+    pub fn define<V: ToString>(mut self, name: &str, value: V) -> Self {
+        self.defines.push((name.to_owned(), value.to_string()));
+        self
+    }
+
     pub fn load(&self, base_directory: &Path) -> Result<String, anyhow::Error> {
         if let Some(ref src) = self.source {
-            return Ok(src.clone());
+            return Ok(self.insert_defines(src.clone()));
         }
 
         let mut contents = String::new();
@@ -32,7 +40,28 @@ impl ShaderSource {
             File::open(fs::canonicalize(base_directory.join(filename))?)?
                 .read_to_string(&mut contents)?;
         }
-        Ok(contents)
+        Ok(self.insert_defines(contents))
+    }
+
+    fn insert_defines(&self, source: String) -> String {
+        if self.defines.is_empty() {
+            return source;
+        }
+
+        let split = if source.starts_with("#version") {
+            source.find('\n').map(|i| i + 1).unwrap_or(source.len())
+        } else {
+            0
+        };
+        let mut output = source[..split].to_owned();
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        for (name, value) in &self.defines {
+            output.push_str(&format!("#define {} {}\n", name, value));
+        }
+        output.push_str(&source[split..]);
+        output
     }
 }
 
@@ -96,6 +125,7 @@ macro_rules! shader_source {
                 tmp
             }),
             filenames: None,
+            defines: Vec::new(),
         }
     };
 }
@@ -111,6 +141,7 @@ macro_rules! shader_source {
                 $( tmp_vec.push(std::path::Path::from($filename)); )*
                     tmp_vec
             }),
+            defines: Vec::new(),
         }
     };
 }
@@ -137,6 +168,42 @@ fn create_compute_shader(source: &str) -> Result<Vec<u32>, anyhow::Error> {
         .to_vec())
 }
 
+/// Returns the binding number and name of all image descriptors (sampled or storage) used by a
+/// SPIR-V module.
+pub fn image_descriptor_names(spirv: &[u32]) -> Result<Vec<(u32, String)>, anyhow::Error> {
+    let spv: SpirvBinary = spirv.to_vec().into();
+    let entries = spv.reflect()?;
+    let mut names = Vec::new();
+    for entry in entries.iter() {
+        let manifest = &entry.manifest;
+        for desc in manifest.descs() {
+            if let DescriptorType::Image(..) = desc.desc_ty {
+                if let Some(name) = manifest.get_desc_name(desc.desc_bind) {
+                    let (_, binding) = desc.desc_bind.into_inner();
+                    names.push((binding, name.to_string()));
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Returns the binding number of all sampler descriptors used by a SPIR-V module.
+pub fn sampler_descriptor_bindings(spirv: &[u32]) -> Result<Vec<u32>, anyhow::Error> {
+    let spv: SpirvBinary = spirv.to_vec().into();
+    let entries = spv.reflect()?;
+    let mut bindings = Vec::new();
+    for entry in entries.iter() {
+        for desc in entry.manifest.descs() {
+            if let DescriptorType::Sampler(..) = desc.desc_ty {
+                let (_, binding) = desc.desc_bind.into_inner();
+                bindings.push(binding);
+            }
+        }
+    }
+    Ok(bindings)
+}
+
 fn reflect(
     stages: &[&[u32]],
 ) -> Result<
@@ -1,16 +1,38 @@
 use notify;
 use sha2::{Digest, Sha256};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use super::*;
 
-pub struct ShaderDirectoryWatcher {}
+pub struct ShaderDirectoryWatcher {
+    directory: PathBuf,
+}
 impl ShaderDirectoryWatcher {
-    pub fn new<P>(_: P) -> Result<Self, notify::Error>
+    pub fn new<P>(directory: P) -> Result<Self, notify::Error>
     where
         PathBuf: From<P>,
     {
-        Ok(Self {})
+        Ok(Self { directory: PathBuf::from(directory) })
+    }
+
+    /// Shaders are never refreshed without the `dynamic_shaders` feature, so this does nothing.
+    pub fn pause(&mut self) {}
+
+    /// Shaders are never refreshed without the `dynamic_shaders` feature, so this does nothing.
+    pub fn resume(&mut self) {}
+
+    pub fn is_paused(&self) -> bool {
+        false
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Files are not watched without the `dynamic_shaders` feature, so this always returns false.
+    pub fn modified_since(&mut self, _: &Path, _: Instant) -> bool {
+        false
     }
 }
 
@@ -25,8 +47,8 @@ impl ShaderSet {
     ) -> Result<Self, anyhow::Error> {
         Ok(Self {
             inner: ShaderSetInner::simple(
-                vertex_source.source.unwrap(),
-                fragment_source.source.unwrap(),
+                vertex_source.load(Path::new(""))?,
+                fragment_source.load(Path::new(""))?,
             )?,
         })
     }
@@ -34,7 +56,7 @@ impl ShaderSet {
         _: &mut ShaderDirectoryWatcher,
         compute_source: ShaderSource,
     ) -> Result<Self, anyhow::Error> {
-        Ok(Self { inner: ShaderSetInner::compute_only(compute_source.source.unwrap())? })
+        Ok(Self { inner: ShaderSetInner::compute_only(compute_source.load(Path::new(""))?)? })
     }
     pub fn refresh(&mut self, _: &mut ShaderDirectoryWatcher) -> bool {
         false
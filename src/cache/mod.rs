@@ -1,26 +1,56 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{BufWriter, Cursor, Read, Stdout, Write};
 use std::ops::Drop;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use std::{mem, thread};
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use bincode;
 use dirs;
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::Future;
 use memmap::MmapMut;
 use num::ToPrimitive;
 use pbr::{MultiBar, Pipe, ProgressBar, Units};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// How long downloads wait to connect, and how long they wait for more data once connected,
+/// unless a `WebAsset` overrides `timeout`.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most files that `WebAsset::load_async` reads or downloads at once. Further loads wait for one
+/// of these to finish.
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+#[derive(Debug, Error)]
+pub(crate) enum DownloadError {
+    #[error("timed out downloading {0}")]
+    TimedOut(String),
+    #[error("{0} does not exist")]
+    NotFound(String),
+}
 
 lazy_static! {
     pub(crate) static ref TERRA_DIRECTORY: PathBuf =
         dirs::cache_dir().unwrap_or(PathBuf::from(".")).join("terra");
+    static ref FETCH_POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .num_threads(MAX_CONCURRENT_FETCHES)
+        .thread_name(|i| format!("terra-fetch-{}", i))
+        .build()
+        .expect("failed to start download threads");
+    static ref KNOWN_CHECKSUMS: HashMap<&'static str, Checksum> =
+        parse_checksums(include_str!("../../checksums.sha256")).expect("invalid checksums.sha256");
 }
 
 pub(crate) struct AssetLoadContextBuf {
     bars: Vec<ProgressBar<Pipe>>,
+    progress_callback: Box<dyn FnMut(u64, u64)>,
 }
 impl AssetLoadContextBuf {
     pub fn new() -> Self {
@@ -40,40 +70,70 @@ impl AssetLoadContextBuf {
             mb.listen();
         });
 
-        Self { bars }
+        Self { bars, progress_callback: Box::new(|_, _| {}) }
+    }
+    /// Creates a context that doesn't display or report any progress, for loads that happen in
+    /// the background.
+    pub fn silent() -> Self {
+        Self { bars: Vec::new(), progress_callback: Box::new(|_, _| {}) }
+    }
+    /// Install a callback that is passed `(bytes_done, bytes_total)` while memory mapped assets
+    /// are being built. Replaces any previously installed callback.
+    pub fn set_progress_callback<F: FnMut(u64, u64) + 'static>(&mut self, callback: F) {
+        self.progress_callback = Box::new(callback);
     }
     pub fn context<N: ToPrimitive>(&mut self, message: &str, total: N) -> AssetLoadContext {
-        self.bars[0].message(message);
-        self.bars[0].total = total.to_u64().unwrap();
-        self.bars[0].set(0);
-        self.bars[0].set_units(Units::Default);
-        self.bars[0].is_visible = true;
-        AssetLoadContext { bars: &mut self.bars[..] }
+        if let Some(bar) = self.bars.first_mut() {
+            bar.message(message);
+            bar.total = total.to_u64().unwrap();
+            bar.set(0);
+            bar.set_units(Units::Default);
+            bar.is_visible = true;
+        }
+        AssetLoadContext {
+            bars: &mut self.bars[..],
+            progress_callback: &mut *self.progress_callback,
+        }
     }
 }
 
 pub(crate) struct AssetLoadContext<'a> {
+    /// Progress bars for this level and the ones below it, which is empty for silent contexts.
     bars: &'a mut [ProgressBar<Pipe>],
+    progress_callback: &'a mut dyn FnMut(u64, u64),
 }
 impl<'a> AssetLoadContext<'a> {
     pub fn set_progress<N: ToPrimitive>(&mut self, value: N) {
-        self.bars[0].set(value.to_u64().unwrap());
+        if let Some(bar) = self.bars.first_mut() {
+            bar.set(value.to_u64().unwrap());
+        }
     }
 
     pub fn set_progress_and_total<N: ToPrimitive, M: ToPrimitive>(&mut self, value: N, total: M) {
-        self.bars[0].total = total.to_u64().unwrap();
-        self.bars[0].set(value.to_u64().unwrap());
+        if let Some(bar) = self.bars.first_mut() {
+            bar.total = total.to_u64().unwrap();
+            bar.set(value.to_u64().unwrap());
+        }
     }
 
     pub fn reset<N: ToPrimitive>(&mut self, message: &str, total: N) {
         self.bytes_display_enabled(false);
-        self.bars[0].total = total.to_u64().unwrap();
-        self.bars[0].message(message);
-        self.bars[0].set(0);
+        if let Some(bar) = self.bars.first_mut() {
+            bar.total = total.to_u64().unwrap();
+            bar.message(message);
+            bar.set(0);
+        }
+    }
+
+    /// Forward byte level progress to the callback installed on the `AssetLoadContextBuf`.
+    pub fn report_bytes(&mut self, done: u64, total: u64) {
+        (self.progress_callback)(done, total);
     }
 
     pub fn bytes_display_enabled(&mut self, enabled: bool) {
-        self.bars[0].set_units(if enabled { Units::Bytes } else { Units::Default });
+        if let Some(bar) = self.bars.first_mut() {
+            bar.set_units(if enabled { Units::Bytes } else { Units::Default });
+        }
     }
 
     pub fn increment_level<'b, N: ToPrimitive>(
@@ -84,17 +144,25 @@ impl<'a> AssetLoadContext<'a> {
     where
         'a: 'b,
     {
-        self.bars[1].total = total.to_u64().unwrap();
-        self.bars[1].message(message);
-        self.bars[1].set(0);
-        self.bars[1].set_units(Units::Default);
-        self.bars[1].is_visible = true;
-        AssetLoadContext { bars: &mut self.bars[1..] }
+        if let Some(bar) = self.bars.get_mut(1) {
+            bar.total = total.to_u64().unwrap();
+            bar.message(message);
+            bar.set(0);
+            bar.set_units(Units::Default);
+            bar.is_visible = true;
+        }
+        let level = self.bars.len().min(1);
+        AssetLoadContext {
+            bars: &mut self.bars[level..],
+            progress_callback: &mut *self.progress_callback,
+        }
     }
 }
 impl<'a> Drop for AssetLoadContext<'a> {
     fn drop(&mut self) {
-        self.bars[0].is_visible = false;
+        if let Some(bar) = self.bars.first_mut() {
+            bar.is_visible = false;
+        }
     }
 }
 
@@ -124,6 +192,66 @@ fn read_file(context: &mut AssetLoadContext, mut file: File) -> Result<Vec<u8>,
     ret
 }
 
+/// Expected digest of a downloaded file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Checksum {
+    Sha256([u8; 32]),
+}
+impl Checksum {
+    /// Returns an error describing the mismatch if `data` doesn't have this checksum.
+    pub fn verify(&self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Checksum::Sha256(expected) => {
+                let actual = Sha256::digest(data);
+                if actual.as_slice() != &expected[..] {
+                    return Err(anyhow!(
+                        "SHA-256 mismatch: expected {} but got {}",
+                        hex::encode(expected),
+                        hex::encode(actual.as_slice())
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses lines of `sha256sum` output into a map from file name to checksum. Blank lines and
+/// lines starting with `#` are skipped.
+fn parse_checksums(manifest: &str) -> Result<HashMap<&str, Checksum>, Error> {
+    let mut checksums = HashMap::new();
+    for line in manifest.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let (digest, name) = match (fields.next(), fields.next()) {
+            (Some(digest), Some(name)) => (digest, name.trim_start().trim_start_matches('*')),
+            _ => return Err(anyhow!("malformed checksum line '{}'", line)),
+        };
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(digest, &mut bytes)
+            .map_err(|e| anyhow!("bad digest for {}: {}", name, e))?;
+        checksums.insert(name, Checksum::Sha256(bytes));
+    }
+    Ok(checksums)
+}
+
+/// Returns the checksum listed in `checksums.sha256` for the file stored at `filename` within the
+/// cache directory, if there is one.
+pub(crate) fn known_checksum(filename: &str) -> Option<Checksum> {
+    KNOWN_CHECKSUMS.get(filename).copied()
+}
+
+/// Checks `data` against `checksum` if there is one, deleting `filename` on a mismatch so that a
+/// truncated or corrupt copy doesn't stay in the cache.
+fn verify_checksum(filename: &Path, data: &[u8], checksum: Option<Checksum>) -> Result<(), Error> {
+    if let Some(checksum) = checksum {
+        if let Err(e) = checksum.verify(data) {
+            let _ = fs::remove_file(filename);
+            return Err(anyhow!("{}: {}", filename.display(), e));
+        }
+    }
+    Ok(())
+}
+
 pub(crate) trait WebAsset {
     type Type;
 
@@ -137,6 +265,16 @@ pub(crate) trait WebAsset {
     fn credentials(&self) -> Option<(String, String)> {
         None
     }
+    /// Checksum of the uncompressed file, if known. Downloads that don't match it are rejected
+    /// rather than cached.
+    fn checksum(&self) -> Option<Checksum> {
+        None
+    }
+    /// Longest time to wait for the server to accept a connection, or to send more data once it
+    /// has. Downloads that exceed it fail with `DownloadError::TimedOut`.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
 
     fn load(&self, context: &mut AssetLoadContext) -> Result<Self::Type, Error> {
         let context =
@@ -151,61 +289,185 @@ pub(crate) trait WebAsset {
                     snap::read::FrameDecoder::new(Cursor::new(data)).read_to_end(&mut uncompressed)?;
                     data = uncompressed;
                 }
-                context.reset(&format!("Parsing {}... ", &self.filename()), 100);
-                if let Ok(asset) = self.parse(context, data) {
-                    return Ok(asset);
+                if verify_checksum(&filename, &data, self.checksum()).is_ok() {
+                    context.reset(&format!("Parsing {}... ", &self.filename()), 100);
+                    if let Ok(asset) = self.parse(context, data) {
+                        return Ok(asset);
+                    }
                 }
             }
         }
 
-        let mut data = Vec::<u8>::new();
-        {
-            context.reset(&format!("Downloading {}... ", &self.filename()), 100);
-            // Bytes display will be disabled by the reset() below, or in the event of an error,
-            // by the decrement_level() call in the outer scope.
-            context.bytes_display_enabled(true);
-
-            use curl::easy::Easy;
-            let mut easy = Easy::new();
-            easy.url(&self.url())?;
-            easy.progress(true)?;
-            easy.follow_location(true)?;
-            easy.fail_on_error(true)?;
-            if let Some((username, password)) = self.credentials() {
-                easy.cookie_file("")?;
-                easy.unrestricted_auth(true)?;
-                easy.username(&username)?;
-                easy.password(&password)?;
-            }
-            let mut transfer = easy.transfer();
-            transfer.write_function(|d| {
-                let len = d.len();
-                data.extend(d);
-                Ok(len)
-            })?;
-            transfer.progress_function(|t, c, _, _| {
-                if t > 0.0 {
-                    context.set_progress_and_total(c, t);
-                }
-                true
-            })?;
-            transfer.perform()?;
-        }
+        context.reset(&format!("Downloading {}... ", &self.filename()), 100);
+        // Bytes display will be disabled by the reset() below, or in the event of an error,
+        // by the decrement_level() call in the outer scope.
+        context.bytes_display_enabled(true);
+        let data = download(&self.url(), self.credentials(), self.timeout(), |c, t| {
+            context.set_progress_and_total(c, t)
+        })?;
+        verify_checksum(&filename, &data, self.checksum())?;
 
         context.reset(&format!("Saving {}... ", &self.filename()), 100);
-        if let Some(parent) = filename.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        let mut file = File::create(&filename)?;
-        if self.compressed() {
-            snap::write::FrameEncoder::new(&mut file).write_all(&data)?;
-        } else {
-            file.write_all(&data)?;
-        }
-        file.sync_all()?;
+        save(&filename, &data, self.compressed())?;
         context.reset(&format!("Parsing {}... ", &self.filename()), 100);
         Ok(self.parse(context, data)?)
     }
+
+    /// Like `load`, but reads or downloads the file on a background thread so that many assets
+    /// can be fetched concurrently from any executor. At most `MAX_CONCURRENT_FETCHES` files are
+    /// fetched at a time. Only parsing happens on the polling thread, and it reports no progress.
+    fn load_async<'a>(&'a self) -> BoxFuture<'a, Result<Self::Type, Error>>
+    where
+        Self: Sync,
+        Self::Type: Send + 'a,
+    {
+        let url = self.url();
+        let filename = TERRA_DIRECTORY.join(self.filename());
+        let compressed = self.compressed();
+        let credentials = self.credentials();
+        let checksum = self.checksum();
+        let timeout = self.timeout();
+
+        Box::pin(async move {
+            let parse = |data| {
+                let mut context = AssetLoadContextBuf::silent();
+                let mut context = context.context(&format!("Parsing {}... ", &url), 100);
+                self.parse(&mut context, data)
+            };
+
+            let fetch = |use_cache| {
+                let request = Request {
+                    url: url.clone(),
+                    credentials: credentials.clone(),
+                    timeout,
+                    compressed,
+                    checksum,
+                };
+                spawn_fetch(request, filename.clone(), use_cache)
+            };
+            let (data, cached) = fetch(true).await?;
+            match parse(data) {
+                // The cached copy may be truncated or corrupt, so download it again.
+                Err(_) if cached => parse(fetch(false).await?.0),
+                result => result,
+            }
+        })
+    }
+}
+
+/// Downloads `url`, calling `progress` with the number of bytes received so far and the total
+/// whenever the total is known. Fails with `DownloadError::TimedOut` if connecting takes longer
+/// than `timeout` or the transfer stalls for that long, and with `DownloadError::NotFound` if the
+/// server responds with a 404.
+fn download(
+    url: &str,
+    credentials: Option<(String, String)>,
+    timeout: Duration,
+    mut progress: impl FnMut(f64, f64),
+) -> Result<Vec<u8>, Error> {
+    use curl::easy::Easy;
+
+    let mut data = Vec::<u8>::new();
+    let mut easy = Easy::new();
+    easy.url(url)?;
+    easy.progress(true)?;
+    easy.follow_location(true)?;
+    easy.fail_on_error(true)?;
+    easy.connect_timeout(timeout)?;
+    easy.low_speed_limit(1)?;
+    easy.low_speed_time(timeout)?;
+    if let Some((username, password)) = credentials {
+        easy.cookie_file("")?;
+        easy.unrestricted_auth(true)?;
+        easy.username(&username)?;
+        easy.password(&password)?;
+    }
+    let result = {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|d| {
+            let len = d.len();
+            data.extend(d);
+            Ok(len)
+        })?;
+        transfer.progress_function(|t, c, _, _| {
+            if t > 0.0 {
+                progress(c, t);
+            }
+            true
+        })?;
+        transfer.perform()
+    };
+    match result {
+        Err(e) if e.is_operation_timedout() => {
+            return Err(DownloadError::TimedOut(url.to_owned()).into())
+        }
+        Err(e) if e.is_http_returned_error() && easy.response_code()? == 404 => {
+            return Err(DownloadError::NotFound(url.to_owned()).into())
+        }
+        result => result?,
+    }
+    Ok(data)
+}
+
+/// Writes downloaded `data` to `filename`, creating the parent directory if needed.
+fn save(filename: &Path, data: &[u8], compressed: bool) -> Result<(), Error> {
+    if let Some(parent) = filename.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(filename)?;
+    if compressed {
+        snap::write::FrameEncoder::new(&mut file).write_all(data)?;
+    } else {
+        file.write_all(data)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Everything `fetch` needs to know about a `WebAsset` to download it on another thread.
+struct Request {
+    url: String,
+    credentials: Option<(String, String)>,
+    timeout: Duration,
+    compressed: bool,
+    checksum: Option<Checksum>,
+}
+
+/// Returns the contents of `filename` if `use_cache` is set and it exists with the right
+/// checksum, otherwise downloads the requested url and saves it there. Also returns whether the
+/// data came from the cache.
+fn fetch(request: Request, filename: &Path, use_cache: bool) -> Result<(Vec<u8>, bool), Error> {
+    let Request { url, credentials, timeout, compressed, checksum } = request;
+    if use_cache {
+        if let Ok(mut data) = fs::read(filename) {
+            if compressed {
+                let mut uncompressed = Vec::new();
+                snap::read::FrameDecoder::new(Cursor::new(data)).read_to_end(&mut uncompressed)?;
+                data = uncompressed;
+            }
+            if verify_checksum(filename, &data, checksum).is_ok() {
+                return Ok((data, true));
+            }
+        }
+    }
+
+    let data = download(&url, credentials, timeout, |_, _| {})?;
+    verify_checksum(filename, &data, checksum)?;
+    save(filename, &data, compressed)?;
+    Ok((data, false))
+}
+
+/// Runs `fetch` on the download thread pool, returning a future that resolves once it completes.
+fn spawn_fetch(
+    request: Request,
+    filename: PathBuf,
+    use_cache: bool,
+) -> impl Future<Output = Result<(Vec<u8>, bool), Error>> {
+    let (sender, receiver) = oneshot::channel();
+    FETCH_POOL.spawn(move || {
+        let _ = sender.send(fetch(request, &filename, use_cache));
+    });
+    async move { receiver.await.map_err(|_| anyhow!("download thread panicked"))? }
 }
 
 pub(crate) trait GeneratedAsset {
@@ -260,32 +522,37 @@ pub(crate) trait MMappedAsset {
         ) {
             let mut contents = Vec::new();
             header.read_to_end(&mut contents)?;
-            let header = bincode::deserialize(&contents)?;
-            let mapping = unsafe { MmapMut::map_mut(&data)? };
-            Ok((header, mapping))
-        } else {
-            context.reset(&format!("Generating {}... ", &self.filename()), 100);
-            if let Some(parent) = data_filename.parent() {
-                fs::create_dir_all(parent)?;
+            // Headers written before a field was added fail to decode, in which case the asset is
+            // generated again.
+            if let Ok(header) = bincode::deserialize(&contents) {
+                let mapping = unsafe { MmapMut::map_mut(&data)? };
+                context.report_bytes(mapping.len() as u64, mapping.len() as u64);
+                return Ok((header, mapping));
             }
-            let mut data_file = File::create(&data_filename)?;
-            let header = self.generate(context, BufWriter::new(&mut data_file))?;
-            context.reset(&format!("Saving {}... ", &self.filename()), 100);
-            data_file.sync_all()?;
+        }
 
-            let mut header_file = File::create(&header_filename)?;
-            {
-                let mut writer = BufWriter::new(&mut header_file);
-                bincode::serialize_into(&mut writer, &header)?;
-            }
-            header_file.sync_all()?;
+        context.reset(&format!("Generating {}... ", &self.filename()), 100);
+        if let Some(parent) = data_filename.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut data_file = File::create(&data_filename)?;
+        let header = self.generate(context, BufWriter::new(&mut data_file))?;
+        context.reset(&format!("Saving {}... ", &self.filename()), 100);
+        data_file.sync_all()?;
 
-            // Open for reading this time
-            context.reset(&format!("Loading {}... ", &self.filename()), 100);
-            let data_file = File::with_options().read(true).write(true).open(&data_filename)?;
-            let mapping = unsafe { MmapMut::map_mut(&data_file)? };
-            Ok((header, mapping))
+        let mut header_file = File::create(&header_filename)?;
+        {
+            let mut writer = BufWriter::new(&mut header_file);
+            bincode::serialize_into(&mut writer, &header)?;
         }
+        header_file.sync_all()?;
+
+        // Open for reading this time
+        context.reset(&format!("Loading {}... ", &self.filename()), 100);
+        let data_file = File::with_options().read(true).write(true).open(&data_filename)?;
+        let mapping = unsafe { MmapMut::map_mut(&data_file)? };
+        context.report_bytes(mapping.len() as u64, mapping.len() as u64);
+        Ok((header, mapping))
     }
 }
 
@@ -302,7 +569,185 @@ impl<H: Serialize + DeserializeOwned, A: WebAsset<Type = (H, Vec<u8>)>> MMappedA
         mut w: W,
     ) -> Result<Self::Header, Error> {
         let (header, data) = WebAsset::load(self, context)?;
-        w.write_all(&data[..])?;
+        write_with_progress(context, &mut w, &data[..])?;
         Ok(header)
     }
 }
+
+/// Write `data` in chunks, reporting the number of bytes written after each one.
+fn write_with_progress<W: Write>(
+    context: &mut AssetLoadContext,
+    w: &mut W,
+    data: &[u8],
+) -> Result<(), Error> {
+    let total = data.len() as u64;
+    let mut written = 0;
+    context.report_bytes(0, total);
+    for chunk in data.chunks(1024 * 1024) {
+        w.write_all(chunk)?;
+        written += chunk.len() as u64;
+        context.report_bytes(written, total);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn progress_callback() {
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let mut context = AssetLoadContextBuf::new();
+        let r = reports.clone();
+        context.set_progress_callback(move |done, total| r.borrow_mut().push((done, total)));
+        let mut context = context.context("", 1);
+
+        let data = vec![7u8; 2 * 1024 * 1024 + 5];
+        let mut out = Vec::new();
+        write_with_progress(&mut context.increment_level("", 1), &mut out, &data).unwrap();
+        assert_eq!(out, data);
+
+        let reports = reports.borrow();
+        let total = data.len() as u64;
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports[0], (0, total));
+        assert_eq!(*reports.last().unwrap(), (total, total));
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn progress_callback_default() {
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+        write_with_progress(&mut context, &mut Vec::new(), &[]).unwrap();
+    }
+
+    #[test]
+    fn silent_context() {
+        let mut context = AssetLoadContextBuf::silent();
+        let mut context = context.context("", 1);
+        let mut context = context.increment_level("", 1);
+        context.reset("", 2);
+        context.set_progress(1);
+        write_with_progress(&mut context, &mut Vec::new(), &[0; 4]).unwrap();
+    }
+
+    struct TextAsset(PathBuf, Option<Checksum>);
+    impl WebAsset for TextAsset {
+        type Type = String;
+
+        fn url(&self) -> String {
+            "http://localhost:1/unreachable".to_owned()
+        }
+        fn filename(&self) -> String {
+            // Joining an absolute path onto the cache directory replaces it.
+            self.0.to_str().unwrap().to_owned()
+        }
+        fn compressed(&self) -> bool {
+            true
+        }
+        fn checksum(&self) -> Option<Checksum> {
+            self.1
+        }
+        fn parse(&self, _: &mut AssetLoadContext, data: Vec<u8>) -> Result<String, Error> {
+            Ok(String::from_utf8(data)?)
+        }
+    }
+
+    #[test]
+    fn load_async_from_cache() {
+        let path = std::env::temp_dir().join(format!("terra-load-async-{}", std::process::id()));
+        let asset = TextAsset(path.clone(), None);
+
+        save(&path, b"cached", true).unwrap();
+        assert_eq!(futures::executor::block_on(asset.load_async()).unwrap(), "cached");
+
+        // With nothing cached the download is attempted, and fails.
+        fs::remove_file(&path).unwrap();
+        assert!(futures::executor::block_on(asset.load_async()).is_err());
+    }
+
+    #[test]
+    fn load_async_many() {
+        // More loads than there are download threads still all complete.
+        let assets: Vec<_> = (0..2 * MAX_CONCURRENT_FETCHES)
+            .map(|i| {
+                let filename = format!("terra-load-async-{}-{}", std::process::id(), i);
+                let path = std::env::temp_dir().join(filename);
+                save(&path, b"cached", true).unwrap();
+                TextAsset(path, None)
+            })
+            .collect();
+        let loads = futures::future::join_all(assets.iter().map(|asset| asset.load_async()));
+        for result in futures::executor::block_on(loads) {
+            assert_eq!(result.unwrap(), "cached");
+        }
+        for asset in &assets {
+            fs::remove_file(&asset.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn download_timeout() {
+        // The listener accepts connections into its backlog but never responds.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let error = download(&url, None, Duration::from_secs(1), |_, _| {}).unwrap_err();
+        assert!(error.is::<DownloadError>());
+    }
+
+    #[test]
+    fn download_not_found() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/missing.zip", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            assert!(stream.read(&mut [0; 1024]).unwrap() > 0);
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").unwrap();
+        });
+        let error = download(&url, None, Duration::from_secs(5), |_, _| {}).unwrap_err();
+        server.join().unwrap();
+        match error.downcast::<DownloadError>() {
+            Ok(DownloadError::NotFound(missing)) => assert_eq!(missing, url),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        let checksum = Checksum::Sha256(Sha256::digest(b"cached").into());
+        assert!(checksum.verify(b"cached").is_ok());
+        assert!(checksum.verify(b"cache").unwrap_err().to_string().contains("mismatch"));
+
+        let path = std::env::temp_dir().join(format!("terra-checksum-{}", std::process::id()));
+        save(&path, b"cached", true).unwrap();
+        let asset = TextAsset(path.clone(), Some(checksum));
+        assert_eq!(futures::executor::block_on(asset.load_async()).unwrap(), "cached");
+
+        // A truncated copy is thrown out, and the download that replaces it fails.
+        save(&path, b"cach", true).unwrap();
+        assert!(futures::executor::block_on(asset.load_async()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn checksum_manifest() {
+        let digest = Sha256::digest(b"cached");
+        let manifest = format!(
+            "# comment\n\n{}  dems/a.zip\n{} *dems/b.zip\n",
+            hex::encode(digest),
+            hex::encode(digest)
+        );
+        let checksums = parse_checksums(&manifest).unwrap();
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums["dems/b.zip"], Checksum::Sha256(digest.into()));
+        assert!(parse_checksums("abc  dems/a.zip").is_err());
+        assert!(parse_checksums("dems/a.zip").is_err());
+
+        // The manifest shipped with the crate parses.
+        assert_eq!(known_checksum("dems/missing.zip"), None);
+    }
+}
@@ -1,6 +1,12 @@
+use crate::gpu_state::BindingError;
 use crate::GpuState;
+use futures::FutureExt;
+use std::future::Future;
 use std::mem;
+use std::pin::Pin;
+use thiserror::Error;
 
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub(crate) struct GenHeightmapsUniforms {
     pub position: [i32; 2],
@@ -9,16 +15,22 @@ pub(crate) struct GenHeightmapsUniforms {
     pub in_slot: i32,
     pub out_slot: i32,
     pub level_resolution: i32,
+    /// Offset in workgroups of this dispatch, see `ComputeShader::run_split`.
+    pub workgroup_offset: [u32; 2],
 }
 unsafe impl bytemuck::Zeroable for GenHeightmapsUniforms {}
 unsafe impl bytemuck::Pod for GenHeightmapsUniforms {}
 
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub(crate) struct GenDisplacementsUniforms {
     pub origin: [i32; 2],
     pub stride: i32,
     pub heightmaps_slot: i32,
     pub displacements_slot: i32,
+    pub padding: i32,
+    /// Offset in workgroups of this dispatch, see `ComputeShader::run_split`.
+    pub workgroup_offset: [u32; 2],
 }
 unsafe impl bytemuck::Zeroable for GenDisplacementsUniforms {}
 unsafe impl bytemuck::Pod for GenDisplacementsUniforms {}
@@ -36,20 +48,169 @@ pub(crate) struct GenNormalsUniforms {
     pub albedo_slot: i32,
     pub parent_slot: i32,
     pub spacing: f32,
+    pub heightmaps_border: i32,
+    /// A `NormalSpace` selecting how normals are encoded.
+    pub normal_space: i32,
     pub padding: i32,
+    /// Heightmap slots of the neighbors in the -x, +x, -y and +y directions, or -1 if absent.
+    pub neighbor_slots: [i32; 4],
+    /// Offset in workgroups of this dispatch, see `ComputeShader::run_split`.
+    pub workgroup_offset: [u32; 2],
 }
 unsafe impl bytemuck::Zeroable for GenNormalsUniforms {}
 unsafe impl bytemuck::Pod for GenNormalsUniforms {}
 
+/// Number of workgroups needed to cover `width` x `height` invocations with the given local size.
+fn dispatch_size(local_size: (u32, u32), width: u32, height: u32) -> (u32, u32, u32) {
+    ((width + local_size.0 - 1) / local_size.0, (height + local_size.1 - 1) / local_size.1, 1)
+}
+
+/// Largest number of workgroups that may be dispatched along any one dimension. wgpu 0.6's
+/// `Limits` doesn't report the device's own limit, so this is the minimum every WebGPU backend
+/// guarantees.
+const MAX_DISPATCH_SIZE: u32 = 65535;
+
+#[derive(Debug, Error)]
+pub(crate) enum ComputeError {
+    #[error("invalid dispatch size {size} along {axis}: must be between 1 and {limit}")]
+    InvalidDispatch { axis: &'static str, size: u32, limit: u32 },
+    #[error("failed to bind shader resources")]
+    Binding(#[from] BindingError),
+}
+
+/// Checks that a dispatch of `dimensions` workgroups is within the limits of the device.
+fn validate_dispatch(dimensions: (u32, u32, u32)) -> Result<(), ComputeError> {
+    let (x, y, z) = dimensions;
+    for &(axis, size) in &[("x", x), ("y", y), ("z", z)] {
+        if size == 0 || size > MAX_DISPATCH_SIZE {
+            return Err(ComputeError::InvalidDispatch { axis, size, limit: MAX_DISPATCH_SIZE });
+        }
+    }
+    Ok(())
+}
+
+/// Splits a dispatch of `dimensions` workgroups into dispatches that are each within the device
+/// limits. Returns the offset of each piece in workgroups along with its size.
+fn split_dispatch(dimensions: (u32, u32, u32)) -> Vec<((u32, u32, u32), (u32, u32, u32))> {
+    let chunks = |size: u32| {
+        (0..size)
+            .step_by(MAX_DISPATCH_SIZE as usize)
+            .map(move |offset| (offset, (size - offset).min(MAX_DISPATCH_SIZE)))
+    };
+
+    let mut pieces = Vec::new();
+    for (z, depth) in chunks(dimensions.2) {
+        for (y, height) in chunks(dimensions.1) {
+            for (x, width) in chunks(dimensions.0) {
+                pieces.push(((x, y, z), (width, height, depth)));
+            }
+        }
+    }
+    pieces
+}
+
+/// Maximum number of staging buffers each `ComputeShader` keeps around for reuse. Dispatches
+/// beyond this many per submission still work, but their staging buffers are freed afterwards.
+const MAX_STAGING_BUFFERS: usize = 32;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+enum StagingState {
+    /// Mapped and ready to have uniforms written into it.
+    Mapped,
+    /// Used by a command encoder that may not have been submitted yet.
+    InFlight,
+    /// Waiting for the GPU to finish with the buffer so it can be mapped again.
+    Mapping(MapFuture),
+}
+
+struct StagingBuffer<B> {
+    buffer: B,
+    state: StagingState,
+}
+
+/// Staging buffers of type `B`, which is `wgpu::Buffer` outside of tests, that are reused once
+/// the GPU is done with them.
+struct StagingPool<B> {
+    buffers: Vec<StagingBuffer<B>>,
+}
+impl<B> StagingPool<B> {
+    fn new() -> Self {
+        Self { buffers: Vec::new() }
+    }
+
+    /// Returns the index of a mapped staging buffer, adding one made by `create` if all of the
+    /// existing buffers are still in use by the GPU. Calls `poll` first if any buffer is waiting
+    /// to be mapped.
+    fn acquire(&mut self, poll: impl FnOnce(), create: impl FnOnce() -> B) -> usize {
+        if self.buffers.iter().any(|s| matches!(s.state, StagingState::Mapping(_))) {
+            poll();
+        }
+
+        let mut i = 0;
+        while i < self.buffers.len() {
+            if let StagingState::Mapping(ref mut future) = self.buffers[i].state {
+                match future.now_or_never() {
+                    Some(Ok(())) => self.buffers[i].state = StagingState::Mapped,
+                    Some(Err(_)) => {
+                        self.buffers.swap_remove(i);
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            if let StagingState::Mapped = self.buffers[i].state {
+                return i;
+            }
+            i += 1;
+        }
+
+        self.buffers.push(StagingBuffer { buffer: create(), state: StagingState::Mapped });
+        self.buffers.len() - 1
+    }
+
+    /// Starts mapping every in flight buffer with `map`.
+    fn recycle(&mut self, mut map: impl FnMut(&B) -> MapFuture) {
+        for staging in &mut self.buffers {
+            if let StagingState::InFlight = staging.state {
+                staging.state = StagingState::Mapping(map(&staging.buffer));
+            }
+        }
+    }
+}
+
+/// Shows that a command encoder has been submitted, which `ComputeShader::recycle_staging`
+/// requires so that it can't reuse staging buffers before the GPU has been handed their contents.
+pub(crate) struct Submitted(());
+
+/// Finishes `encoder` and submits it to `queue`.
+pub(crate) fn submit(queue: &wgpu::Queue, encoder: wgpu::CommandEncoder) -> Submitted {
+    queue.submit(Some(encoder.finish()));
+    Submitted(())
+}
+
 pub(crate) struct ComputeShader<U> {
     shader: rshader::ShaderSet,
     bindgroup_pipeline: Option<(wgpu::BindGroup, wgpu::ComputePipeline)>,
     uniforms: wgpu::Buffer,
+    staging: StagingPool<wgpu::Buffer>,
+    local_size: (u32, u32),
     _phantom: std::marker::PhantomData<U>,
 }
 impl<U: bytemuck::Pod> ComputeShader<U> {
-    pub fn new(device: &wgpu::Device, shader: rshader::ShaderSet) -> Self {
-        Self {
+    /// Compiles `source` with `LOCAL_SIZE_X` and `LOCAL_SIZE_Y` defined to `local_size`. Shaders
+    /// should use these in their `layout(local_size_x = ...)` declaration so that it always
+    /// matches the dispatch sizes computed by `dispatch_size`.
+    pub fn new(
+        device: &wgpu::Device,
+        watcher: &mut rshader::ShaderDirectoryWatcher,
+        source: rshader::ShaderSource,
+        local_size: (u32, u32),
+    ) -> Result<Self, anyhow::Error> {
+        let source =
+            source.define("LOCAL_SIZE_X", local_size.0).define("LOCAL_SIZE_Y", local_size.1);
+        let shader = rshader::ShaderSet::compute_only(watcher, source)?;
+        Ok(Self {
             shader,
             bindgroup_pipeline: None,
             uniforms: device.create_buffer(&wgpu::BufferDescriptor {
@@ -58,8 +219,15 @@ impl<U: bytemuck::Pod> ComputeShader<U> {
                 mapped_at_creation: false,
                 label: None,
             }),
+            staging: StagingPool::new(),
+            local_size,
             _phantom: std::marker::PhantomData,
-        }
+        })
+    }
+
+    /// Returns the number of workgroups to dispatch to cover a `width` x `height` image.
+    pub fn dispatch_size(&self, width: u32, height: u32) -> (u32, u32, u32) {
+        dispatch_size(self.local_size, width, height)
     }
 
     pub fn refresh(&mut self, watcher: &mut rshader::ShaderDirectoryWatcher) -> bool {
@@ -71,6 +239,10 @@ impl<U: bytemuck::Pod> ComputeShader<U> {
         }
     }
 
+    /// Records a dispatch of the shader into `encoder`.
+    ///
+    /// Binding and dispatch size problems are reported as errors. wgpu 0.6 has no error scope API,
+    /// so failures detected inside the device itself (e.g. shader module validation) still panic.
     pub fn run(
         &mut self,
         device: &wgpu::Device,
@@ -78,13 +250,16 @@ impl<U: bytemuck::Pod> ComputeShader<U> {
         state: &GpuState,
         dimensions: (u32, u32, u32),
         uniforms: &U,
-    ) {
+    ) -> Result<(), ComputeError> {
+        validate_dispatch(dimensions)?;
+
         if self.bindgroup_pipeline.is_none() {
             let (bind_group, bind_group_layout) = state.bind_group_for_shader(
                 device,
                 &self.shader,
                 Some(self.uniforms.slice(..mem::size_of::<U>() as u64)),
-            );
+                1,
+            )?;
             self.bindgroup_pipeline = Some((
                 bind_group,
                 device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -104,23 +279,440 @@ impl<U: bytemuck::Pod> ComputeShader<U> {
             ));
         }
 
-        let staging = device
-            .create_buffer(&wgpu::BufferDescriptor {
-                size: mem::size_of::<U>() as u64,
-                usage: wgpu::BufferUsage::COPY_SRC,
-                label: None,
-                mapped_at_creation: true,
-            });
-        let mut buffer_view = staging.slice(..).get_mapped_range_mut();
+        let index = self.staging.acquire(
+            || device.poll(wgpu::Maintain::Poll),
+            || {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    size: mem::size_of::<U>() as u64,
+                    usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::MAP_WRITE,
+                    label: Some("compute_shader.staging".into()),
+                    mapped_at_creation: true,
+                })
+            },
+        );
+        let staging = &mut self.staging.buffers[index];
+        let mut buffer_view = staging.buffer.slice(..).get_mapped_range_mut();
         bytemuck::cast_slice_mut(&mut *buffer_view)[0] = *uniforms;
         drop(buffer_view);
-        staging.unmap();
+        staging.buffer.unmap();
+        staging.state = StagingState::InFlight;
 
-        encoder.copy_buffer_to_buffer(&staging, 0, &self.uniforms, 0, mem::size_of::<U>() as u64);
+        let size = mem::size_of::<U>() as u64;
+        encoder.copy_buffer_to_buffer(&staging.buffer, 0, &self.uniforms, 0, size);
+        if index >= MAX_STAGING_BUFFERS {
+            // The copy keeps the buffer alive until the GPU is done with it.
+            self.staging.buffers.pop();
+        }
 
         let mut cpass = encoder.begin_compute_pass();
         cpass.set_pipeline(&self.bindgroup_pipeline.as_ref().unwrap().1);
         cpass.set_bind_group(0, &self.bindgroup_pipeline.as_ref().unwrap().0, &[]);
         cpass.dispatch(dimensions.0, dimensions.1, dimensions.2);
+        Ok(())
+    }
+
+    /// Starts mapping the staging buffers used by previous calls to `run` so that later
+    /// dispatches can reuse them. Takes the result of submitting the command encoder passed to
+    /// those calls.
+    pub fn recycle_staging(&mut self, _: &Submitted) {
+        self.staging.recycle(|buffer| Box::pin(buffer.slice(..).map_async(wgpu::MapMode::Write)));
+    }
+
+    /// Like `run`, but splits dispatches that are too large for the device into several smaller
+    /// ones. `uniforms` is called with the offset in workgroups of each piece, which the shader
+    /// must add to `gl_WorkGroupID` to find the part of the output it is responsible for.
+    pub fn run_split(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        state: &GpuState,
+        dimensions: (u32, u32, u32),
+        uniforms: impl Fn((u32, u32, u32)) -> U,
+    ) -> Result<(), ComputeError> {
+        for (offset, size) in split_dispatch(dimensions) {
+            self.run(device, encoder, state, size, &uniforms(offset))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::dem::decode_f32le;
+    use crate::terrain::raster::Raster;
+    use anyhow::Error;
+    use futures::executor;
+
+    /// Copies the first `len` bytes of `buffer`, such as a storage buffer written by a compute
+    /// shader, back to the CPU. The buffer must have been created with `COPY_SRC` usage and hold at
+    /// least `len` bytes, and `len` must be a nonzero multiple of four since buffer copies can't be
+    /// any other size. Meant for checking shader output against CPU implementations.
+    ///
+    /// This blocks until the GPU has finished all previously submitted work.
+    fn readback(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        len: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let size = readback_size(len)?;
+        let download = device.create_buffer(&wgpu::BufferDescriptor {
+            size,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            label: Some("readback".into()),
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &download, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = download.slice(..);
+        let future = buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        executor::block_on(future)?;
+
+        let data = buffer_slice.get_mapped_range().to_vec();
+        Ok(data)
+    }
+
+    /// Returns the size of the copy `readback` makes for `len` bytes, or an error if no copy can be
+    /// exactly that long.
+    fn readback_size(len: usize) -> Result<u64, Error> {
+        anyhow::ensure!(
+            len > 0 && len % 4 == 0,
+            "readback length must be a nonzero multiple of four bytes, got {}",
+            len
+        );
+        Ok(len as u64)
+    }
+
+    /// Copies a `resolution` x `resolution` layer of an `R32Float` texture written by a compute
+    /// shader back to the CPU and wraps it in a `Raster`, so the output can be checked with the CPU
+    /// raster tools. Rows are assumed to be stored from north to south, as in `Raster`, with the
+    /// lower left corner of the raster at `corner` (latitude, longitude) and cells `cell_size`
+    /// degrees apart.
+    ///
+    /// This blocks until the GPU has finished all previously submitted work.
+    fn read_output_as_raster(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        layer: u32,
+        resolution: u32,
+        corner: (f64, f64),
+        cell_size: f64,
+    ) -> Result<Raster<f32>, Error> {
+        let row_bytes = resolution as usize * 4;
+        let row_pitch = (row_bytes + 255) & !255;
+        let size = (row_pitch * resolution as usize) as u64;
+
+        let download = device.create_buffer(&wgpu::BufferDescriptor {
+            size,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            label: Some("read_output_as_raster".into()),
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+            },
+            wgpu::BufferCopyView {
+                buffer: &download,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: row_pitch as u32,
+                    rows_per_image: 0,
+                },
+            },
+            wgpu::Extent3d { width: resolution, height: resolution, depth: 1 },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = download.slice(..);
+        let future = buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        executor::block_on(future)?;
+
+        let data = unpad_rows(&*buffer_slice.get_mapped_range(), row_bytes, row_pitch);
+        Ok(raster_from_texels(&data, resolution as usize, corner, cell_size))
+    }
+
+    /// Strips the padding from the end of each `row_pitch` byte row of `data`, keeping the first
+    /// `row_bytes` bytes of each.
+    fn unpad_rows(data: &[u8], row_bytes: usize, row_pitch: usize) -> Vec<u8> {
+        data.chunks(row_pitch).flat_map(|row| &row[..row_bytes]).copied().collect()
+    }
+
+    /// Builds a single band raster from tightly packed little endian f32 texels.
+    fn raster_from_texels(
+        data: &[u8],
+        resolution: usize,
+        corner: (f64, f64),
+        cell_size: f64,
+    ) -> Raster<f32> {
+        let values = decode_f32le(data);
+        assert_eq!(values.len(), resolution * resolution);
+        Raster::new(resolution, resolution, 1, cell_size, corner.0, corner.1, values)
+    }
+
+    #[test]
+    fn local_size_matches_dispatch() {
+        let source = rshader::ShaderSource {
+            source: Some("#version 450 core\nlayout(local_size_x = LOCAL_SIZE_X) in;\n".into()),
+            filenames: None,
+            defines: Vec::new(),
+        }
+        .define("LOCAL_SIZE_X", 8)
+        .define("LOCAL_SIZE_Y", 4);
+        let glsl = source.load(std::path::Path::new("")).unwrap();
+        assert!(glsl
+            .starts_with("#version 450 core\n#define LOCAL_SIZE_X 8\n#define LOCAL_SIZE_Y 4\n"));
+
+        assert_eq!(dispatch_size((8, 4), 64, 64), (8, 16, 1));
+        assert_eq!(dispatch_size((8, 4), 65, 65), (9, 17, 1));
+        assert_eq!(dispatch_size((4, 4), 4, 1), (1, 1, 1));
+    }
+
+    #[test]
+    fn readback_sizes() {
+        assert_eq!(readback_size(4).unwrap(), 4);
+        assert_eq!(readback_size(65 * 65 * 4).unwrap(), 65 * 65 * 4);
+        assert!(readback_size(0).is_err());
+        assert!(readback_size(6).is_err());
+    }
+
+    /// Mirrors `load_height` in gen-normals.comp: returns the position and slot of the texel read
+    /// for position `p` of the heightmap tile in `slot`.
+    fn normals_source_texel(
+        p: (i32, i32),
+        resolution: i32,
+        border: i32,
+        slot: i32,
+        neighbor_slots: [i32; 4],
+    ) -> ((i32, i32), i32) {
+        let stride = resolution - 2 * border - 1;
+        let (mut q, mut source) = (p, slot);
+        if p.0 < border && neighbor_slots[0] >= 0 {
+            q.0 += stride;
+            source = neighbor_slots[0];
+        } else if p.0 >= resolution - border && neighbor_slots[1] >= 0 {
+            q.0 -= stride;
+            source = neighbor_slots[1];
+        }
+        if p.1 < border && neighbor_slots[2] >= 0 && source == slot {
+            q.1 += stride;
+            source = neighbor_slots[2];
+        } else if p.1 >= resolution - border && neighbor_slots[3] >= 0 && source == slot {
+            q.1 -= stride;
+            source = neighbor_slots[3];
+        }
+        (q, source)
+    }
+
+    #[test]
+    fn normals_match_across_edges() {
+        const RESOLUTION: i32 = 9;
+        const BORDER: i32 = 2;
+        let stride = RESOLUTION - 2 * BORDER - 1;
+        let height = |x: i32, y: i32| 100.0 + 50.0 * ((x * x + 3 * y) as f32 * 0.1).sin();
+
+        // Two tiles side by side in slots 0 and 1. Their interiors hold the same height field, but
+        // their west and east borders don't, as when a border was filled in from a coarser parent.
+        let tiles: Vec<Vec<f32>> = (0..2)
+            .map(|tile| {
+                (0..RESOLUTION * RESOLUTION)
+                    .map(|i| {
+                        let (x, y) = (i % RESOLUTION, i / RESOLUTION);
+                        if x < BORDER || x >= RESOLUTION - BORDER {
+                            -1000.0
+                        } else {
+                            height(tile * stride + x - BORDER, y)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Horizontal components of the normal computed by gen-normals.comp at `p`.
+        let normal = |slot: i32, neighbor_slots: [i32; 4], p: (i32, i32)| {
+            let h = |dx: i32, dy: i32| {
+                let q = (p.0 + dx, p.1 + dy);
+                let ((x, y), source) =
+                    normals_source_texel(q, RESOLUTION, BORDER, slot, neighbor_slots);
+                tiles[source as usize][(x + y * RESOLUTION) as usize].max(0.0)
+            };
+            (h(1, 0) + h(1, 1) - h(0, 0) - h(0, 1), h(0, 1) + h(1, 1) - h(0, 0) - h(1, 0))
+        };
+
+        // The last column of the west tile and the first of the east one are the same texels.
+        let edge = RESOLUTION - BORDER - 1;
+        for y in BORDER..RESOLUTION - BORDER - 1 {
+            let west = normal(0, [-1, 1, -1, -1], (edge, y));
+            let east = normal(1, [0, -1, -1, -1], (BORDER, y));
+            assert_eq!(west, east);
+
+            // Without its neighbor, the west tile reads its own border and the seam comes back.
+            assert_ne!(normal(0, [-1; 4], (edge, y)), east);
+        }
+    }
+
+    #[test]
+    fn staging_pool_reuse() {
+        let mut pool = StagingPool::new();
+        let mut created = 0;
+        let mut dispatch = |pool: &mut StagingPool<()>| {
+            let index = pool.acquire(|| {}, || created += 1);
+            pool.buffers[index].state = StagingState::InFlight;
+            index
+        };
+        let mapped = |_: &()| -> MapFuture { Box::pin(futures::future::ready(Ok(()))) };
+        let pending = |_: &()| -> MapFuture { Box::pin(futures::future::pending()) };
+
+        // Without recycling, every dispatch needs a new buffer.
+        assert_eq!(dispatch(&mut pool), 0);
+        assert_eq!(dispatch(&mut pool), 1);
+
+        // Once mapped again, buffers are reused for any number of dispatches.
+        pool.recycle(mapped);
+        for _ in 0..10_000 {
+            dispatch(&mut pool);
+            pool.recycle(mapped);
+        }
+
+        // A buffer the GPU still holds is skipped rather than waited for.
+        assert_eq!(dispatch(&mut pool), 0);
+        pool.recycle(pending);
+        assert_eq!(dispatch(&mut pool), 1);
+        pool.recycle(pending);
+        assert_eq!(dispatch(&mut pool), 2);
+        assert_eq!(created, 3);
+    }
+
+    #[test]
+    fn invalid_dispatch() {
+        assert!(validate_dispatch((8, 8, 1)).is_ok());
+        assert!(validate_dispatch((MAX_DISPATCH_SIZE, 1, 1)).is_ok());
+        assert!(matches!(
+            validate_dispatch((0, 8, 1)),
+            Err(ComputeError::InvalidDispatch { axis: "x", size: 0, .. })
+        ));
+        let error = validate_dispatch((8, MAX_DISPATCH_SIZE + 1, 1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid dispatch size 65536 along y: must be between 1 and 65535"
+        );
+    }
+
+    #[test]
+    fn split_oversized_dispatch() {
+        assert_eq!(split_dispatch((8, 8, 1)), vec![((0, 0, 0), (8, 8, 1))]);
+
+        let pieces = split_dispatch((2 * MAX_DISPATCH_SIZE + 1, 3, 1));
+        assert_eq!(
+            pieces,
+            vec![
+                ((0, 0, 0), (MAX_DISPATCH_SIZE, 3, 1)),
+                ((MAX_DISPATCH_SIZE, 0, 0), (MAX_DISPATCH_SIZE, 3, 1)),
+                ((2 * MAX_DISPATCH_SIZE, 0, 0), (1, 3, 1)),
+            ]
+        );
+        assert!(pieces.iter().all(|&(_, size)| validate_dispatch(size).is_ok()));
+    }
+
+    #[test]
+    fn uniform_sizes() {
+        // `workgroup_offset` is a uvec2, so std140 puts it at the next multiple of eight bytes.
+        assert_eq!(mem::size_of::<GenHeightmapsUniforms>(), 40);
+        assert_eq!(mem::size_of::<GenDisplacementsUniforms>(), 32);
+        assert_eq!(mem::size_of::<GenNormalsUniforms>(), 168);
+    }
+    /// Creates a device for tests that need a GPU, or returns `None` if no adapter is available.
+    fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::Default,
+            compatible_surface: None,
+        }))?;
+        executor::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+                shader_validation: true,
+            },
+            None,
+        ))
+        .ok()
+    }
+
+    #[test]
+    fn readback_from_gpu() {
+        let (device, queue) = match test_device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        // 64 texels of four bytes make rows that need no padding when copied to a texture.
+        let resolution = 64;
+        let values: Vec<f32> = (0..resolution * resolution).map(|i| i as f32).collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&values);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: bytes.len() as u64,
+            usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            label: None,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, bytes);
+        assert_eq!(readback(&device, &queue, &buffer, bytes.len()).unwrap(), bytes);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d { width: resolution, height: resolution, depth: 2 },
+            format: wgpu::TextureFormat::R32Float,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            usage: wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::COPY_DST,
+            label: None,
+        });
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: 1 },
+            },
+            bytes,
+            wgpu::TextureDataLayout { offset: 0, bytes_per_row: resolution * 4, rows_per_image: 0 },
+            wgpu::Extent3d { width: resolution, height: resolution, depth: 1 },
+        );
+        let raster =
+            read_output_as_raster(&device, &queue, &texture, 1, resolution, (10.0, 20.0), 0.25)
+                .unwrap();
+        assert_eq!(raster.values, values);
+    }
+
+    #[test]
+    fn padded_texels_to_raster() {
+        // A plane sloping up to the east, laid out as a texture download with 256 byte rows.
+        let resolution = 5;
+        let heights: Vec<f32> = (0..25).map(|i| (i % 5) as f32 * 10.0).collect();
+        let mut download = Vec::new();
+        for row in heights.chunks(resolution) {
+            for h in row {
+                download.extend_from_slice(&h.to_le_bytes());
+            }
+            download.resize(download.len() + 256 - resolution * 4, 0xff);
+        }
+
+        let data = unpad_rows(&download, resolution * 4, 256);
+        let raster = raster_from_texels(&data, resolution, (10.0, 20.0), 0.25);
+        assert_eq!(raster.values, heights);
+        assert_eq!(raster.interpolate(10.5, 20.5, 0), Some(20.0));
+        assert!((raster.interpolate(10.5, 20.125, 0).unwrap() - 5.0).abs() < 1e-9);
     }
 }
@@ -2,11 +2,11 @@ use crate::cache::{AssetLoadContext, AssetLoadContextBuf, WebAsset};
 use crate::coordinates::CoordinateSystem;
 use crate::mapfile::{MapFile, TextureDescriptor};
 use crate::srgb::SRGB_TO_LINEAR;
-use crate::terrain::dem::GlobalDem;
+use crate::terrain::dem::{GlobalDem, LocalDemSource};
 use crate::terrain::heightmap;
 use crate::terrain::landcover::{BlueMarble, BlueMarbleTileSource};
 use crate::terrain::quadtree::VNode;
-use crate::terrain::raster::RasterCache;
+use crate::terrain::raster::{CompositeRasterCache, RasterCache, SourceTag};
 // use crate::terrain::reprojected_raster::{
 //     DataType, RasterSource, ReprojectedDemDef, ReprojectedRaster, ReprojectedRasterDef,
 // };
@@ -23,6 +23,8 @@ use std::f64::consts::PI;
 // use std::rc::Rc;
 use rayon::prelude::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 use vec_map::VecMap;
 
 mod gpu;
@@ -32,6 +34,14 @@ pub(crate) use gpu::*;
 pub(crate) const EARTH_RADIUS: f64 = 6371000.0;
 pub(crate) const EARTH_CIRCUMFERENCE: f64 = 2.0 * PI * EARTH_RADIUS;
 
+/// Seed for the noise texture used by `MapFileBuilder::build`, so that regenerating a map file
+/// produces identical detail.
+const DEFAULT_NOISE_SEED: u64 = 0;
+
+/// How long `MapFileBuilder::build_with_local_dems` waits before checking again for a DEM tile
+/// that was missing from the directory.
+const LOCAL_DEM_RETRY: Duration = Duration::from_secs(60);
+
 // Mapping from side length to level number.
 #[allow(unused)]
 mod levels {
@@ -80,6 +90,30 @@ impl MapFileBuilder {
     /// minutes, even in release builds (you *really* don't want to wait for generation in debug
     /// mode...).
     pub fn build() -> Result<MapFile, Error> {
+        Self::build_with_seed(DEFAULT_NOISE_SEED)
+    }
+
+    /// Like `build`, but seeds the procedural noise texture with `seed`. The texture is generated
+    /// again if it was last generated with a different seed.
+    pub fn build_with_seed(seed: u64) -> Result<MapFile, Error> {
+        Self::build_with_dems(seed, CompositeRasterCache::new(Vec::new()))
+    }
+
+    /// Like `build_with_seed`, but heightmaps are generated from the USGS GridFloat DEMs in
+    /// `directory` wherever they have coverage, and only from the coarser global DEM elsewhere.
+    /// Files are named like downloaded tiles, so the tile with its southwest corner at 37°N 122°W
+    /// is read from `directory/n37_w122.zip`. Heightmaps that were already generated are kept.
+    /// Tiles missing from the directory are looked for again every `LOCAL_DEM_RETRY`, so files
+    /// copied in while generation is running are still used.
+    pub fn build_with_local_dems(seed: u64, directory: PathBuf) -> Result<MapFile, Error> {
+        let mut local = RasterCache::new(Box::new(LocalDemSource { root: directory }), 16);
+        local.set_hole_ttl(Some(LOCAL_DEM_RETRY));
+        Self::build_with_dems(seed, CompositeRasterCache::new(vec![(SourceTag("local"), local)]))
+    }
+
+    /// Like `build_with_seed`, but heightmaps sample `dems` wherever it has coverage and only fall
+    /// back to the global DEM elsewhere.
+    fn build_with_dems(seed: u64, mut dems: CompositeRasterCache) -> Result<MapFile, Error> {
         let layers: VecMap<LayerParams> = hashmap![
             LayerType::Heightmaps.index() => LayerParams {
                     layer_type: LayerType::Heightmaps,
@@ -131,13 +165,13 @@ impl MapFileBuilder {
 
         let mut context = AssetLoadContextBuf::new();
         let mut context = context.context("Generating mapfile...", 5);
-        generate_heightmaps(&mut mapfile, &mut context)?;
+        generate_heightmaps(&mut mapfile, &mut context, &mut dems)?;
         context.set_progress(1);
         generate_albedo(&mut mapfile, &mut context)?;
         context.set_progress(2);
         generate_roughness(&mut mapfile, &mut context)?;
         context.set_progress(3);
-        generate_noise(&mut mapfile)?;
+        generate_noise(&mut mapfile, seed)?;
         context.set_progress(4);
         generate_sky(&mut mapfile, &mut context)?;
         context.set_progress(5);
@@ -157,7 +191,11 @@ impl MapFileBuilder {
     }
 }
 
-fn generate_heightmaps(mapfile: &mut MapFile, context: &mut AssetLoadContext) -> Result<(), Error> {
+fn generate_heightmaps(
+    mapfile: &mut MapFile,
+    context: &mut AssetLoadContext,
+    dems: &mut CompositeRasterCache,
+) -> Result<(), Error> {
     let missing = mapfile.get_missing_base(LayerType::Heightmaps)?;
     if missing.is_empty() {
         return Ok(());
@@ -186,9 +224,21 @@ fn generate_heightmaps(mapfile: &mut MapFile, context: &mut AssetLoadContext) ->
             })
             .collect();
 
+        let elevations = dems.get_elevations_with_source(context, &coordinates);
+        let mut sources: HashMap<&str, usize> = HashMap::new();
         let mut heightmap = Vec::new();
-        for (lat, long) in coordinates {
-            heightmap.write_f32::<LittleEndian>(global_dem.interpolate(lat, long, 0) as f32)?;
+        for ((lat, long), elevation) in coordinates.into_iter().zip(elevations) {
+            let elevation = match elevation {
+                Some((elevation, source)) => {
+                    *sources.entry(source.0).or_default() += 1;
+                    elevation
+                }
+                None => global_dem.interpolate(lat, long, 0) as f32,
+            };
+            heightmap.write_f32::<LittleEndian>(elevation)?;
+        }
+        for (source, samples) in sources {
+            log::debug!("{:?}: {} heights from {}", n, samples, source);
         }
         mapfile.write_tile(LayerType::Heightmaps, n, &heightmap, true)?;
     }
@@ -314,8 +364,8 @@ fn generate_roughness(mapfile: &mut MapFile, context: &mut AssetLoadContext) ->
     Ok(())
 }
 
-fn generate_noise(mapfile: &mut MapFile) -> Result<(), Error> {
-    if !mapfile.reload_texture("noise") {
+fn generate_noise(mapfile: &mut MapFile, seed: u64) -> Result<(), Error> {
+    if !mapfile.reload_texture("noise") || mapfile.noise_seed()? != Some(seed) {
         // wavelength = 1.0 / 256.0;
         let noise_desc = TextureDescriptor {
             width: 2048,
@@ -325,8 +375,9 @@ fn generate_noise(mapfile: &mut MapFile) -> Result<(), Error> {
             bytes: 4 * 2048 * 2048,
         };
 
-        let noise_heightmaps: Vec<_> =
-            (0..4).map(|i| heightmap::wavelet_noise(64 << i, 32 >> i)).collect();
+        let noise_heightmaps: Vec<_> = (0..4)
+            .map(|i| heightmap::wavelet_noise(64 << i, 32 >> i, seed.wrapping_add(i as u64)))
+            .collect();
 
         let len = noise_heightmaps[0].heights.len();
         let mut heights = vec![0u8; len * 4];
@@ -339,6 +390,7 @@ fn generate_noise(mapfile: &mut MapFile) -> Result<(), Error> {
         }
 
         mapfile.write_texture("noise", noise_desc, &heights[..])?;
+        mapfile.set_noise_seed(seed)?;
     }
     Ok(())
 }
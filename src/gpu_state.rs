@@ -1,6 +1,19 @@
 use crate::terrain::tile_cache::LayerType;
+use std::num::NonZeroU8;
+use thiserror::Error;
 use vec_map::VecMap;
 
+/// Reasons a shader's bindings can't be satisfied from the resources in `GpuState`.
+#[derive(Debug, Error)]
+pub(crate) enum BindingError {
+    #[error("shader references unrecognized {kind} '{name}'")]
+    UnrecognizedResource { kind: &'static str, name: String },
+    #[error("shader expects a uniform buffer but none was provided")]
+    MissingUniformBuffer,
+    #[error("storage buffer bindings are not supported")]
+    StorageBuffer,
+}
+
 pub(crate) struct GpuState {
     pub noise: wgpu::Texture,
     pub sky: wgpu::Texture,
@@ -18,7 +31,9 @@ impl GpuState {
         device: &wgpu::Device,
         shader: &rshader::ShaderSet,
         ubo: Option<wgpu::BufferSlice>,
-    ) -> (wgpu::BindGroup, wgpu::BindGroupLayout) {
+        anisotropy: u8,
+    ) -> Result<(wgpu::BindGroup, wgpu::BindGroupLayout), BindingError> {
+        let anisotropy_clamp = NonZeroU8::new(anisotropy).filter(|&a| a.get() > 1);
         let linear = &device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -26,6 +41,7 @@ impl GpuState {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy_clamp,
             label: Some("linear".into()),
             ..Default::default()
         });
@@ -36,6 +52,7 @@ impl GpuState {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy_clamp,
             label: Some("linear_wrap".into()),
             ..Default::default()
         });
@@ -65,7 +82,12 @@ impl GpuState {
                         wgpu::BindingResource::Sampler(match name {
                             "linear" => &linear,
                             "linear_wrap" => &linear_wrap,
-                            _ => unreachable!("unrecognized sampler: {}", name),
+                            _ => {
+                                return Err(BindingError::UnrecognizedResource {
+                                    kind: "sampler",
+                                    name: name.to_owned(),
+                                })
+                            }
                         })
                     }
                     wgpu::BindingType::StorageTexture { .. }
@@ -82,13 +104,20 @@ impl GpuState {
                             "heightmaps" => &tile_cache_views[LayerType::Heightmaps],
                             "bc4_staging" => &bc4_staging,
                             "bc5_staging" => &bc5_staging,
-                            _ => unreachable!("unrecognized image: {}", name),
+                            _ => {
+                                return Err(BindingError::UnrecognizedResource {
+                                    kind: "image",
+                                    name: name.to_owned(),
+                                })
+                            }
                         })
                     }
-                    wgpu::BindingType::UniformBuffer { .. } => {
-                        wgpu::BindingResource::Buffer(ubo.unwrap().clone())
+                    wgpu::BindingType::UniformBuffer { .. } => wgpu::BindingResource::Buffer(
+                        ubo.clone().ok_or(BindingError::MissingUniformBuffer)?,
+                    ),
+                    wgpu::BindingType::StorageBuffer { .. } => {
+                        return Err(BindingError::StorageBuffer)
                     }
-                    wgpu::BindingType::StorageBuffer { .. } => unimplemented!(),
                 },
             });
         }
@@ -99,6 +128,6 @@ impl GpuState {
             label: None,
         });
 
-        (bind_group, bind_group_layout)
+        Ok((bind_group, bind_group_layout))
     }
 }
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use super::description::{TextureFormat, DatasetFormat, Projection};
-use super::TileCache;
+use super::{Sector, TileCache};
 use std::{fs};
 use std::io::{Cursor, Read};
 use std::str::FromStr;
@@ -26,12 +26,14 @@ pub struct Dataset<B: Backend> {
     pub license: Option<String>,
 
     pub directory: PathBuf,
-    pub tile_cache: TileCache<(i16, i16), B>,
+    /// Data for the sectors of the map, assembled from the tiles overlapping each of them.
+    pub tile_cache: TileCache<Sector, B>,
 }
 impl<B: Backend> Dataset<B> {
     fn parse(&mut self, data: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
         match self.desc.file_format {
             DatasetFormat::ZippedGridFloat => parse_ned_zip(data),
+            DatasetFormat::GeoTiff => parse_geotiff(data),
         }
     }
 
@@ -81,6 +83,12 @@ impl<B: Backend> Dataset<B> {
 }
 
 
+/// Decodes a TIFF image into RGBA texels, filling in an opaque alpha band for RGB images.
+fn parse_geotiff(data: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    let image = image::load_from_memory_with_format(&data, image::ImageFormat::Tiff)?;
+    Ok(image.into_rgba().into_raw())
+}
+
 /// Load a zip file in the format for the USGS's National Elevation Dataset.
 #[allow(unused)]
 fn parse_ned_zip(data: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
@@ -11,12 +11,19 @@ pub enum Registration {
 pub enum TextureFormat {
     R32F,
     Rgba8,
+    /// Unsigned 16-bit integers, such as heights stored with a fixed offset and scale.
+    R16U,
+    /// Signed 16-bit integers.
+    R16S,
+    Rg16F,
 }
 impl TextureFormat {
     pub fn bytes_per_pixel(&self) -> u32 {
         match self {
             TextureFormat::R32F => 4,
             TextureFormat::Rgba8 => 4,
+            TextureFormat::R16U | TextureFormat::R16S => 2,
+            TextureFormat::Rg16F => 4,
         }
     }
 }
@@ -31,6 +38,39 @@ pub enum OutputKind {
     F32,
 }
 
+/// How texels are combined when a tile is sampled between them.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+/// What is returned when sampling outside of a tile.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressMode {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+/// How a generated layer's tiles are sampled when rendering. Fields left out of the config keep
+/// their defaults of linear filtering, clamped addressing and no anisotropic filtering.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplerDesc {
+    pub filter: FilterMode,
+    /// Largest number of samples taken by anisotropic filtering, or 1 to disable it.
+    pub max_anisotropy: u8,
+    pub address_mode: AddressMode,
+}
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self { filter: FilterMode::Linear, max_anisotropy: 1, address_mode: AddressMode::Clamp }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub enum Projection {
     #[serde(alias = "NAD83")]
@@ -41,6 +81,22 @@ pub enum Projection {
 pub enum DatasetFormat {
     #[serde(alias = "GridFloat+zip")]
     ZippedGridFloat,
+    /// TIFF images with 8-bit RGB or RGBA bands, such as satellite imagery.
+    GeoTiff,
+}
+impl DatasetFormat {
+    /// Returns the texture format tiles are decoded into when the node doesn't pick one.
+    pub fn default_texture_format(&self) -> TextureFormat {
+        match self {
+            DatasetFormat::ZippedGridFloat => TextureFormat::R32F,
+            DatasetFormat::GeoTiff => TextureFormat::Rgba8,
+        }
+    }
+
+    /// Whether tiles in this format can be decoded into textures of the given format.
+    pub fn supports(&self, format: TextureFormat) -> bool {
+        format == self.default_texture_format()
+    }
 }
 
 // #[derive(Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
@@ -65,12 +121,31 @@ pub enum Node {
 
         #[serde(default)]
         corner_registration: bool,
+
+        /// Whether an `Rgba8` output holds sRGB encoded colors rather than linear values. Shaders
+        /// reading this layer are told so they can linearize before blending.
+        #[serde(default)]
+        srgb: bool,
+
+        /// Store sectors whose contents exactly match an earlier sector as references to it rather
+        /// than writing the same bytes again. Useful for layers with large uniform regions.
+        #[serde(default)]
+        dedup_sectors: bool,
+
+        /// Sampler used when rendering this layer. Doesn't affect the generated data, so changing
+        /// it doesn't change the layer's id.
+        #[serde(default)]
+        sampler: SamplerDesc,
     },
     Dataset {
         url: String,
         projection: Projection,
         resolution: u32,
         format: DatasetFormat,
+        /// Format of the tiles once uploaded, which also determines their number of bands.
+        /// Defaults to the one `format` decodes into.
+        #[serde(default)]
+        texture_format: Option<TextureFormat>,
         bib: Option<String>,
         license: Option<String>,
         cache_size: u16,
@@ -82,6 +157,23 @@ pub struct GraphFile {
     pub center: String,
     /// Map will be a square with this many sectors on each side.
     pub side_length_sectors: u16,
+    /// Seed for any procedural noise used while generating layers. Changing it changes the id of
+    /// every generated layer.
+    #[serde(default)]
+    pub seed: u64,
+    pub nodes: BTreeMap<String, Node>,
+    pub shaders: BTreeMap<String, String>,
+}
+
+/// Part of a graph description spread across several files. Fields that are missing from one file
+/// must be provided by another.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GraphFileFragment {
+    pub center: Option<String>,
+    pub side_length_sectors: Option<u16>,
+    pub seed: Option<u64>,
+    #[serde(default)]
     pub nodes: BTreeMap<String, Node>,
+    #[serde(default)]
     pub shaders: BTreeMap<String, String>,
 }
@@ -0,0 +1,187 @@
+//! Minimal writer for tiled, uncompressed GeoTIFF files.
+//!
+//! The `tiff` crate can't currently write tiled images or floating point samples, so this module
+//! assembles the handful of tags needed by hand.
+
+use super::description::TextureFormat;
+use byteorder::{LittleEndian, WriteBytesExt};
+use failure::{ensure, Error};
+use std::io::Write;
+
+/// Width and height of each tile in the output file.
+pub const TILE_SIZE: u32 = 256;
+
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const DOUBLE: u16 = 12;
+
+/// Placement of a raster in geographic (WGS84) coordinates.
+#[derive(Copy, Clone, Debug)]
+pub struct GeoReference {
+    /// Longitude and latitude of the upper left corner of the upper left pixel.
+    pub origin: (f64, f64),
+    /// Size of a single pixel in degrees.
+    pub pixel_size: f64,
+    /// Whether pixel values are samples at a point rather than averages over an area.
+    pub point_registration: bool,
+}
+
+struct Entry {
+    tag: u16,
+    ty: u16,
+    count: u32,
+    bytes: Vec<u8>,
+}
+impl Entry {
+    fn shorts(tag: u16, values: &[u16]) -> Self {
+        let mut bytes = Vec::new();
+        for &v in values {
+            bytes.write_u16::<LittleEndian>(v).unwrap();
+        }
+        Self { tag, ty: SHORT, count: values.len() as u32, bytes }
+    }
+    fn longs(tag: u16, values: &[u32]) -> Self {
+        let mut bytes = Vec::new();
+        for &v in values {
+            bytes.write_u32::<LittleEndian>(v).unwrap();
+        }
+        Self { tag, ty: LONG, count: values.len() as u32, bytes }
+    }
+    fn doubles(tag: u16, values: &[f64]) -> Self {
+        let mut bytes = Vec::new();
+        for &v in values {
+            bytes.write_f64::<LittleEndian>(v).unwrap();
+        }
+        Self { tag, ty: DOUBLE, count: values.len() as u32, bytes }
+    }
+}
+
+/// Writes `data`, a row-major `width` x `height` image in `format`, as a little endian tiled
+/// GeoTIFF. Partial tiles along the right and bottom edges are padded with zeros.
+pub fn write_tiled<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    georeference: &GeoReference,
+    data: &[u8],
+) -> Result<(), Error> {
+    let bytes_per_pixel = format.bytes_per_pixel() as usize;
+    ensure!(
+        data.len() == width as usize * height as usize * bytes_per_pixel,
+        "image data has wrong size"
+    );
+
+    let tiles_across = (width + TILE_SIZE - 1) / TILE_SIZE;
+    let tiles_down = (height + TILE_SIZE - 1) / TILE_SIZE;
+    let tile_bytes = (TILE_SIZE * TILE_SIZE) as usize * bytes_per_pixel;
+    let row_bytes = width as usize * bytes_per_pixel;
+
+    // Tile data goes directly after the 8 byte header.
+    let mut body = Vec::with_capacity((tiles_across * tiles_down) as usize * tile_bytes);
+    let mut tile_offsets = Vec::new();
+    for ty in 0..tiles_down {
+        for tx in 0..tiles_across {
+            tile_offsets.push(8 + body.len() as u32);
+            let x0 = (tx * TILE_SIZE) as usize * bytes_per_pixel;
+            let x1 = row_bytes.min(x0 + TILE_SIZE as usize * bytes_per_pixel);
+            for y in (ty * TILE_SIZE)..((ty + 1) * TILE_SIZE) {
+                let start = body.len();
+                if y < height {
+                    let row = y as usize * row_bytes;
+                    body.extend_from_slice(&data[row + x0..row + x1]);
+                }
+                body.resize(start + TILE_SIZE as usize * bytes_per_pixel, 0);
+            }
+        }
+    }
+    let tile_byte_counts = vec![tile_bytes as u32; tile_offsets.len()];
+
+    let (photometric, samples, sample_format) = match format {
+        TextureFormat::R32F => (1, 1, 3),
+        TextureFormat::Rgba8 => (2, 4, 1),
+        TextureFormat::R16U => (1, 1, 1),
+        TextureFormat::R16S => (1, 1, 2),
+        TextureFormat::Rg16F => (1, 2, 3),
+    };
+    let bits_per_sample = (8 * bytes_per_pixel / samples) as u16;
+
+    let mut entries = vec![
+        Entry::longs(256, &[width]),
+        Entry::longs(257, &[height]),
+        Entry::shorts(258, &vec![bits_per_sample; samples]),
+        Entry::shorts(259, &[1]),
+        Entry::shorts(262, &[photometric]),
+        Entry::shorts(277, &[samples as u16]),
+        Entry::shorts(284, &[1]),
+        Entry::longs(322, &[TILE_SIZE]),
+        Entry::longs(323, &[TILE_SIZE]),
+        Entry::longs(324, &tile_offsets),
+        Entry::longs(325, &tile_byte_counts),
+    ];
+    if samples == 4 {
+        // Unassociated alpha.
+        entries.push(Entry::shorts(338, &[2]));
+    } else if samples == 2 {
+        // The second channel has no defined meaning.
+        entries.push(Entry::shorts(338, &[0]));
+    }
+    entries.push(Entry::shorts(339, &vec![sample_format; samples]));
+    entries.push(Entry::doubles(
+        33550,
+        &[georeference.pixel_size, georeference.pixel_size, 0.0],
+    ));
+    entries.push(Entry::doubles(
+        33922,
+        &[0.0, 0.0, 0.0, georeference.origin.0, georeference.origin.1, 0.0],
+    ));
+    entries.push(Entry::shorts(
+        34735,
+        &[
+            1, 1, 0, 3, // version 1.1.0, three keys
+            1024, 0, 1, 2, // GTModelType = Geographic
+            1025, 0, 1, if georeference.point_registration { 2 } else { 1 }, // GTRasterType
+            2048, 0, 1, 4326, // GeographicType = WGS84
+        ],
+    ));
+
+    // Values that don't fit in an IFD entry are stored between the tile data and the IFD.
+    let mut extra = Vec::new();
+    let extra_start = 8 + body.len() as u32;
+    let mut offsets = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        if entry.bytes.len() > 4 {
+            offsets.push(Some(extra_start + extra.len() as u32));
+            extra.extend_from_slice(&entry.bytes);
+            if extra.len() % 2 == 1 {
+                extra.push(0);
+            }
+        } else {
+            offsets.push(None);
+        }
+    }
+    let ifd_offset = extra_start + extra.len() as u32;
+
+    writer.write_all(b"II")?;
+    writer.write_u16::<LittleEndian>(42)?;
+    writer.write_u32::<LittleEndian>(ifd_offset)?;
+    writer.write_all(&body)?;
+    writer.write_all(&extra)?;
+
+    writer.write_u16::<LittleEndian>(entries.len() as u16)?;
+    for (entry, offset) in entries.iter().zip(offsets) {
+        writer.write_u16::<LittleEndian>(entry.tag)?;
+        writer.write_u16::<LittleEndian>(entry.ty)?;
+        writer.write_u32::<LittleEndian>(entry.count)?;
+        match offset {
+            Some(offset) => writer.write_u32::<LittleEndian>(offset)?,
+            None => {
+                let mut value = entry.bytes.clone();
+                value.resize(4, 0);
+                writer.write_all(&value)?;
+            }
+        }
+    }
+    writer.write_u32::<LittleEndian>(0)?;
+    Ok(())
+}
@@ -1,25 +1,32 @@
-use failure::{bail, format_err, Error};
+use failure::{bail, ensure, format_err, Error};
 use generic_array::GenericArray;
 use linked_hash_map::LinkedHashMap;
 use memmap::MmapMut;
-use petgraph::visit::Walker;
-use petgraph::{graph::DiGraph, visit::Topo};
+use petgraph::{algo, graph::DiGraph, Direction};
 use rendy::command::QueueId;
+use rendy::resource::{Buffer, DescriptorSet, Escape, ImageView};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::fs::OpenOptions;
 use std::hash::Hash;
-use std::io::{Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use xdg::BaseDirectories;
 
 mod dataset;
 mod description;
+mod geotiff;
 
 use dataset::{Dataset, DatasetDesc};
-use description::{GraphFile, Node, OutputKind, TextureFormat};
+use description::{
+    AddressMode, FilterMode, GraphFile, GraphFileFragment, Node, OutputKind, SamplerDesc,
+    TextureFormat,
+};
+use geotiff::GeoReference;
 
 pub struct Fence<B: Backend>(Option<B::Fence>);
 impl<B: Backend> Fence<B> {
@@ -34,30 +41,239 @@ impl<B: Backend> Fence<B> {
     }
 }
 
+/// Number of sector generations allowed in flight unless changed with
+/// `Graph::set_max_concurrent_generations`.
+const DEFAULT_MAX_CONCURRENT_GENERATIONS: usize = 4;
+
+/// Workgroup size used when dispatching generation shaders, which is passed to each shader as
+/// `LOCAL_SIZE_X` and `LOCAL_SIZE_Y`.
+const GENERATE_LOCAL_SIZE: u32 = 8;
+
+/// Counting semaphore that bounds how many generations may be running on the GPU at once. Each
+/// permit is held by a submitted generation and is released once its fence signals and the
+/// generation is taken back out.
+struct GenerationLimit<F> {
+    max: usize,
+    /// Generations holding a permit, oldest first.
+    in_flight: Vec<F>,
+}
+impl<F> GenerationLimit<F> {
+    fn new(max: usize) -> Self {
+        Self { max, in_flight: Vec::new() }
+    }
+
+    /// Returns whether another generation may start.
+    fn try_acquire(&self) -> bool {
+        self.in_flight.len() < self.max
+    }
+
+    /// Records a generation that was started after a successful `try_acquire`.
+    fn hold(&mut self, generation: F) {
+        debug_assert!(self.in_flight.len() < self.max);
+        self.in_flight.push(generation);
+    }
+
+    /// Removes and returns the generations that have finished, releasing their permits.
+    fn take_finished(&mut self, mut is_done: impl FnMut(&mut F) -> bool) -> Vec<F> {
+        let mut finished = Vec::new();
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            if is_done(&mut self.in_flight[i]) {
+                finished.push(self.in_flight.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        finished
+    }
+
+    /// Removes and returns the generation that has been in flight the longest, releasing its
+    /// permit. The caller is responsible for waiting on it.
+    fn take_oldest(&mut self) -> Option<F> {
+        if self.in_flight.is_empty() {
+            None
+        } else {
+            Some(self.in_flight.remove(0))
+        }
+    }
+}
+
+/// A sector generation that was submitted to the GPU but whose output hasn't been stored yet.
+struct PendingGeneration<B: Backend> {
+    id: LayerId,
+    sector: Sector,
+    input_hash: String,
+    dispatch: PendingDispatch<B>,
+}
+
+/// GPU work submitted by `Layer::submit`, along with every resource it uses. They are kept alive
+/// until the work's fence signals.
+struct PendingDispatch<B: Backend> {
+    fence: rendy::command::Fence<B>,
+    pool: rendy::command::CommandPool<B, rendy::command::QueueType>,
+    buffer: rendy::command::CommandBuffer<
+        B,
+        rendy::command::QueueType,
+        rendy::command::PendingOnceState,
+    >,
+    pipeline: B::ComputePipeline,
+    pipeline_layout: B::PipelineLayout,
+    download: Escape<Buffer<B>>,
+    bytes: u64,
+    /// The descriptor set read by the shader along with the views and sampler bound into it. The
+    /// views in turn keep the output image alive.
+    bindings: (Escape<DescriptorSet<B>>, Vec<(u32, Escape<ImageView<B>>)>, Handle<Sampler<B>>),
+}
+impl<B: Backend> PendingDispatch<B> {
+    /// Returns whether the GPU has finished the work, without blocking.
+    fn is_done(&mut self, factory: &Factory<B>) -> bool {
+        factory.wait_for_fence(&mut self.fence, 0).unwrap_or(false)
+    }
+
+    /// Waits for the work to finish, frees the resources it used and returns the contents of the
+    /// output image.
+    unsafe fn finish(self, factory: &mut Factory<B>) -> Result<Vec<u8>, Error> {
+        let PendingDispatch {
+            mut fence,
+            mut pool,
+            buffer,
+            pipeline,
+            pipeline_layout,
+            download,
+            bytes,
+            bindings,
+        } = self;
+        factory.wait_for_fence(&mut fence, !0)?;
+        factory.destroy_fence(fence);
+        pool.free_buffers(Some(buffer.mark_complete()));
+        factory.destroy_command_pool(pool);
+        factory.device().destroy_compute_pipeline(pipeline);
+        factory.device().destroy_pipeline_layout(pipeline_layout);
+        drop(bindings);
+
+        let mut mapped = download.map(factory.device(), 0..bytes)?;
+        let contents = mapped.read::<u8>(factory.device(), 0..bytes)?.to_vec();
+        Ok(contents)
+    }
+}
+
+/// How a full `TileCache` picks the tile to replace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Replace the least recently used entry, which is the one inserted or hit longest ago.
+    Lru,
+    /// Replace the entry with the smallest priority given to `TileCache::insert_with_priority`.
+    /// Entries inserted without a priority are replaced first.
+    LowestPriority,
+}
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Lru
+    }
+}
+
+/// Counts of how `TileCache` insertions were satisfied since the cache was created or the counts
+/// were last reset.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Insertions of keys that were already resident.
+    pub hits: u64,
+    /// Insertions that had to upload a tile, whether into a free slot or an evicted one.
+    pub misses: u64,
+    /// Insertions that replaced another tile.
+    pub evictions: u64,
+}
+
 pub struct TileCache<K: Eq + Hash + Copy, B: Backend> {
     image: Handle<Image<B>>,
     size: usize,
     contents: Vec<(K, Fence<B>)>,
     sector_indices: LinkedHashMap<K, usize>,
+    /// Slots below `contents.len()` whose tiles have been removed.
+    free_slots: Vec<usize>,
+    policy: EvictionPolicy,
+    priorities: HashMap<K, f32>,
+    stats: CacheStats,
 
     resolution: u32,
 }
 impl<K: Eq + Hash + Copy, B: Backend> TileCache<K, B> {
-    pub fn insert(&mut self, factory: &mut Factory<B>, queue: QueueId, key: K, data: &[u8]) -> usize {
-        if let Some(&index) = self.sector_indices.get(&key) {
-            return index;
+    #[allow(unused)]
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Zeroes the counts returned by `stats`, for instance at the start of each frame.
+    #[allow(unused)]
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Iterates over the keys of the resident tiles from least to most recently used, where both
+    /// inserting a tile and hitting it with `insert` or `preload` count as uses.
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.sector_indices.keys()
+    }
+
+    /// Removes the tile for `key` from the cache, freeing its slot for later insertions. Returns
+    /// the slot the tile occupied, or `None` if it wasn't resident.
+    #[allow(unused)]
+    pub fn remove(&mut self, key: K) -> Option<usize> {
+        release_slot(&mut self.sector_indices, &mut self.priorities, &mut self.free_slots, key)
+    }
+
+    #[allow(unused)]
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.policy = policy;
+    }
+
+    /// Like `insert`, but records `priority` for the tile so that caches using
+    /// `EvictionPolicy::LowestPriority` can decide what to evict. Fails without inserting anything
+    /// if `priority` is NaN.
+    #[allow(unused)]
+    pub fn insert_with_priority(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        key: K,
+        priority: f32,
+        data: &[u8],
+    ) -> Result<usize, Error> {
+        ensure!(!priority.is_nan(), "tile priority is NaN");
+        let index = self.insert(factory, queue, key, data)?;
+        self.priorities.insert(key, priority);
+        Ok(index)
+    }
+
+    /// Uploads `data` for `key` into a free slot, evicting the tile chosen by the cache's
+    /// `EvictionPolicy` if the cache is full. If `key` is already resident its slot is returned
+    /// without uploading anything, and it counts as a use. Fails if the upload fails or if the slot
+    /// can't be addressed as an image layer, which can only happen for caches with more than
+    /// `u16::MAX` slots. The cache is left unchanged on failure, so any evicted tile stays
+    /// resident.
+    pub fn insert(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        key: K,
+        data: &[u8],
+    ) -> Result<usize, Error> {
+        if let Some(&mut index) = self.sector_indices.get_refresh(&key) {
+            self.stats.hits += 1;
+            return Ok(index);
         }
 
-        let index = if self.sector_indices.len() == self.size {
-            let index = self.sector_indices.pop_back().unwrap().1;
-            self.contents[index] = (key, Fence(None));
-            index
+        // The bookkeeping is only updated once the upload has succeeded.
+        let victim = if self.sector_indices.len() == self.size {
+            Some(eviction_victim(&self.sector_indices, &self.priorities, self.policy))
         } else {
-            self.contents.push((key, Fence(None)));
-            self.contents.len() - 1
+            None
         };
-
-        self.sector_indices.insert(key, index);
+        let index = match victim {
+            Some(victim) => self.sector_indices[&victim],
+            None => self.free_slots.last().copied().unwrap_or(self.contents.len()),
+        };
+        let layers = slot_layers(index, 1)?;
 
         unsafe {
             factory.upload_image(
@@ -67,22 +283,261 @@ impl<K: Eq + Hash + Copy, B: Backend> TileCache<K, B> {
                 rendy::resource::SubresourceLayers {
                     aspects: gfx_hal::format::Aspects::COLOR,
                     level: 0,
-                    layers: (index as u16)..(index as u16 + 1),
+                    layers,
                 },
                 gfx_hal::image::Offset { x: 0, y: 0, z: index as i32 },
                 rendy::resource::Extent { width: self.resolution, height: self.resolution, depth: 1 },
                 data,
                 ImageState::new(queue, Layout::General),
                 ImageState::new(queue, Layout::General),
-            ).unwrap();
+            ).map_err(|e| format_err!("failed to upload tile: {:?}", e))?;
             // factory.flush_uploads();
         }
-        index
+
+        self.stats.misses += 1;
+        match victim {
+            Some(victim) => {
+                self.stats.evictions += 1;
+                self.priorities.remove(&victim);
+                self.sector_indices.remove(&victim);
+                self.contents[index] = (key, Fence(None));
+            }
+            None if index < self.contents.len() => {
+                self.free_slots.pop();
+                self.contents[index] = (key, Fence(None));
+            }
+            None => self.contents.push((key, Fence(None))),
+        }
+        self.sector_indices.insert(key, index);
+        Ok(index)
+    }
+
+    /// Fills free slots with `tiles` using a single upload. Meant for warming an empty or
+    /// partially-full cache; tiles that don't fit fall back to `insert` and may evict others.
+    /// Returns the slot of each tile.
+    ///
+    /// Slots freed by `remove` aren't contiguous with the rest, so while any are left the tiles
+    /// are inserted one at a time instead.
+    pub fn preload(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        tiles: Vec<(K, Vec<u8>)>,
+    ) -> Result<Vec<usize>, Error> {
+        if !self.free_slots.is_empty() {
+            return tiles
+                .into_iter()
+                .map(|(key, tile)| self.insert(factory, queue, key, &tile))
+                .collect();
+        }
+
+        let start = self.sector_indices.len();
+        let keys: Vec<K> = tiles.iter().map(|t| t.0).collect();
+        let slots = preload_slots(&mut self.sector_indices, self.size, &keys);
+
+        let mut data = Vec::new();
+        for ((key, tile), slot) in tiles.iter().zip(&slots) {
+            if let Some(slot) = *slot {
+                if slot == self.contents.len() {
+                    self.contents.push((*key, Fence(None)));
+                    data.extend_from_slice(tile);
+                }
+            }
+        }
+
+        let count = self.contents.len() - start;
+        if count > 0 {
+            let uploaded = slot_layers(start, count).and_then(|layers| unsafe {
+                factory.upload_image(
+                    self.image.clone(),
+                    self.resolution,
+                    self.resolution,
+                    rendy::resource::SubresourceLayers {
+                        aspects: gfx_hal::format::Aspects::COLOR,
+                        level: 0,
+                        layers,
+                    },
+                    gfx_hal::image::Offset { x: 0, y: 0, z: start as i32 },
+                    rendy::resource::Extent {
+                        width: self.resolution,
+                        height: self.resolution,
+                        depth: count as u32,
+                    },
+                    &data,
+                    ImageState::new(queue, Layout::General),
+                    ImageState::new(queue, Layout::General),
+                ).map_err(|e| format_err!("failed to upload tiles: {:?}", e))
+            });
+            if let Err(e) = uploaded {
+                // Forget the slots assigned above so they don't point at tiles that were never
+                // uploaded.
+                for (key, _) in self.contents.drain(start..) {
+                    self.sector_indices.remove(&key);
+                }
+                return Err(e);
+            }
+        }
+
+        for slot in slots.iter().flatten() {
+            // Keys that were already resident occupy slots before `start`.
+            if *slot < start {
+                self.stats.hits += 1;
+            } else {
+                self.stats.misses += 1;
+            }
+        }
+
+        tiles
+            .into_iter()
+            .zip(slots)
+            .map(|((key, tile), slot)| match slot {
+                Some(slot) => Ok(slot),
+                None => self.insert(factory, queue, key, &tile),
+            })
+            .collect()
+    }
+}
+
+/// Returns the format of images holding tiles stored as `format`.
+fn image_format(format: TextureFormat) -> gfx_hal::format::Format {
+    match format {
+        TextureFormat::R32F => gfx_hal::format::Format::R32Sfloat,
+        TextureFormat::Rgba8 => gfx_hal::format::Format::Rgba8Unorm,
+        TextureFormat::R16U => gfx_hal::format::Format::R16Uint,
+        TextureFormat::R16S => gfx_hal::format::Format::R16Sint,
+        TextureFormat::Rg16F => gfx_hal::format::Format::Rg16Sfloat,
+    }
+}
+
+/// Returns the sampler settings described by `desc`. Anisotropic filtering is left off unless the
+/// device supports it.
+fn sampler_info(desc: SamplerDesc, anisotropy_supported: bool) -> gfx_hal::image::SamplerDesc {
+    let filter = match desc.filter {
+        FilterMode::Nearest => gfx_hal::image::Filter::Nearest,
+        FilterMode::Linear => gfx_hal::image::Filter::Linear,
+    };
+    let wrap = match desc.address_mode {
+        AddressMode::Clamp => gfx_hal::image::WrapMode::Clamp,
+        AddressMode::Repeat => gfx_hal::image::WrapMode::Tile,
+        AddressMode::Mirror => gfx_hal::image::WrapMode::Mirror,
+    };
+    let mut info = gfx_hal::image::SamplerDesc::new(filter, wrap);
+    if desc.max_anisotropy > 1 && anisotropy_supported {
+        info.anisotropic = gfx_hal::image::Anisotropic::On(desc.max_anisotropy);
+    }
+    info
+}
+
+/// Returns the image layers covering `count` slots starting at `start`, or an error if any of them
+/// is beyond the range that image layers can address.
+fn slot_layers(start: usize, count: usize) -> Result<std::ops::Range<u16>, Error> {
+    ensure!(
+        start + count <= u16::MAX as usize,
+        "tile cache slots {}..{} exceed the maximum of {} image layers",
+        start,
+        start + count,
+        u16::MAX
+    );
+    Ok(start as u16..(start + count) as u16)
+}
+
+/// Removes `key` from the cache bookkeeping and records its slot as free. Does nothing if `key`
+/// isn't resident.
+fn release_slot<K: Eq + Hash + Copy>(
+    sector_indices: &mut LinkedHashMap<K, usize>,
+    priorities: &mut HashMap<K, f32>,
+    free_slots: &mut Vec<usize>,
+    key: K,
+) -> Option<usize> {
+    let index = sector_indices.remove(&key)?;
+    priorities.remove(&key);
+    free_slots.push(index);
+    Some(index)
+}
+
+/// Returns the entry that `policy` would evict from a full cache.
+fn eviction_victim<K: Eq + Hash + Copy>(
+    sector_indices: &LinkedHashMap<K, usize>,
+    priorities: &HashMap<K, f32>,
+    policy: EvictionPolicy,
+) -> K {
+    match policy {
+        EvictionPolicy::Lru => *sector_indices.front().unwrap().0,
+        EvictionPolicy::LowestPriority => {
+            // `insert_with_priority` rejects NaN, so the priorities are totally ordered.
+            let priority = |key: &K| priorities.get(key).copied().unwrap_or(f32::NEG_INFINITY);
+            *sector_indices
+                .keys()
+                .min_by(|a, b| priority(a).partial_cmp(&priority(b)).unwrap())
+                .unwrap()
+        }
     }
 }
 
+/// Returns the distinct `keys` that aren't in `sector_indices`, limited to the first `size`.
+fn prefetch_keys<K: Eq + Hash + Copy>(
+    sector_indices: &LinkedHashMap<K, usize>,
+    size: usize,
+    keys: &[K],
+) -> Vec<K> {
+    let mut seen = HashSet::new();
+    keys.iter()
+        .filter(|key| !sector_indices.contains_key(key) && seen.insert(**key))
+        .copied()
+        .take(size)
+        .collect()
+}
+
+/// Assigns consecutive free slots to the `keys` that aren't already in `sector_indices`, until all
+/// `size` slots are in use. Returns the slot for each key, or `None` for keys that didn't fit.
+fn preload_slots<K: Eq + Hash + Copy>(
+    sector_indices: &mut LinkedHashMap<K, usize>,
+    size: usize,
+    keys: &[K],
+) -> Vec<Option<usize>> {
+    keys.iter()
+        .map(|key| {
+            if let Some(&mut index) = sector_indices.get_refresh(key) {
+                Some(index)
+            } else if sector_indices.len() < size {
+                let index = sector_indices.len();
+                sector_indices.insert(*key, index);
+                Some(index)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Number of degrees of latitude and longitude spanned by each sector.
+const SECTOR_SIZE_DEGREES: f64 = 1.0;
+
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub struct Sector(i32, i32);
+impl Sector {
+    /// Returns the eight sectors surrounding this one, in row-major order starting from the
+    /// sector at -x, -y.
+    pub fn neighbors(&self) -> [Sector; 8] {
+        let Sector(x, y) = *self;
+        [
+            Sector(x - 1, y - 1),
+            Sector(x, y - 1),
+            Sector(x + 1, y - 1),
+            Sector(x - 1, y),
+            Sector(x + 1, y),
+            Sector(x - 1, y + 1),
+            Sector(x, y + 1),
+            Sector(x + 1, y + 1),
+        ]
+    }
+
+    /// Returns the four sectors sharing an edge with this one, in the order -x, +x, -y, +y.
+    pub fn neighbors4(&self) -> [Sector; 4] {
+        let Sector(x, y) = *self;
+        [Sector(x - 1, y), Sector(x + 1, y), Sector(x, y - 1), Sector(x, y + 1)]
+    }
+}
 
 /// A LayerId is the Sha256 hash of the layer's LayerHeader.
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, Debug)]
@@ -127,50 +582,571 @@ pub struct LayerDesc {
     resolution: u32,
     corner_registration: bool,
     format: TextureFormat,
+    #[serde(default)]
+    srgb: bool,
     sector_bytes: u64,
     shader: String,
     center: String,
+    #[serde(default)]
+    seed: u64,
+    /// Workgroup size the shader is compiled with, which makes it part of the layer id and so of
+    /// the name of the cached SPIR-V.
+    #[serde(default)]
+    local_size: u32,
+}
+impl fmt::Display for LayerDesc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parents: Vec<&str> = self.parents.keys().map(|name| &name[..]).collect();
+        writeln!(f, "resolution: {}x{}", self.resolution, self.resolution)?;
+        writeln!(f, "format: {:?}", self.format)?;
+        writeln!(f, "srgb: {}", self.srgb)?;
+        writeln!(f, "seed: {}", self.seed)?;
+        writeln!(f, "corner_registration: {}", self.corner_registration)?;
+        writeln!(f, "sector_bytes: {}", self.sector_bytes)?;
+        writeln!(f, "parents: [{}]", parents.join(", "))?;
+        write!(f, "shader: {} lines", self.shader.lines().count())
+    }
+}
+
+/// What produced a file or directory in the on-disk cache.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CacheEntryKind {
+    /// Downloaded tiles of a dataset.
+    Dataset,
+    /// Output of a generated layer.
+    Generated,
+}
+
+/// A layer stored in the on-disk cache.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CacheEntry {
+    pub kind: CacheEntryKind,
+    pub id: LayerId,
+    /// Total size of all files belonging to the entry.
+    pub bytes: u64,
+}
+
+/// Side index recording which sectors of a layer have identical contents, so that each distinct
+/// sector only has to be stored once.
+#[derive(Default, Serialize, Deserialize)]
+struct SectorDedup {
+    /// Hex encoded SHA-256 of each distinct sector's contents, mapped to the sector storing them.
+    hashes: HashMap<String, Sector>,
+    /// Sectors whose contents are stored in another sector, keyed by sector index.
+    references: HashMap<u64, Sector>,
+}
+impl SectorDedup {
+    /// Returns the sector whose stored data should be read in place of `sector`.
+    fn resolve(&self, sector: Sector) -> Sector {
+        self.references.get(&sector_index(sector)).copied().unwrap_or(sector)
+    }
 }
 
 pub struct Layer<B: Backend> {
     desc: LayerDesc,
     filename: PathBuf,
     shader: ShaderSet<B>,
+    /// Binding numbers and names of the images used by the shader.
+    images: Vec<(u32, String)>,
+    /// Binding numbers of the samplers used by the shader, which are all bound to `sampler`.
+    sampler_bindings: Vec<u32>,
     data: MmapMut,
     sector_cache: TileCache<Sector, B>,
+    /// Sampler for reading the tiles in `sector_cache`, configured by the node's `sampler` table.
+    sampler: Handle<Sampler<B>>,
+    /// Present if the layer deduplicates sectors, along with the file the index is saved to.
+    dedup: Option<(PathBuf, SectorDedup)>,
+    /// Hex encoded hash of the inputs each stored sector was generated from, keyed by sector
+    /// index, along with the file they are saved to.
+    input_hashes: (PathBuf, HashMap<u64, String>),
 }
 impl<B: Backend> Layer<B> {
-    fn compute_sector_index(sector: Sector) -> u64 {
-        let ax = if sector.0 >= 0 {
-            sector.0
-        } else {
-            -sector.0 - 1
-        } as u64;
-        let ay = if sector.1 >= 0 {
-            sector.1
-        } else {
-            -sector.1 - 1
-        } as u64;
-        let q = match (sector.0 >= 0, sector.1 >= 0) {
-            (true, true) => 0,
-            (false, true) => 1,
-            (true, false) => 2,
-            (false, false) => 3,
+    /// Compiles the shader and opens or creates the data file for the generated layer `name`.
+    /// Compilation is skipped if SPIR-V for the layer is already cached.
+    fn build(
+        config: &GraphFile,
+        cache_dir: &CacheDir,
+        factory: &mut Factory<B>,
+        glsl_compiler: &mut Option<shaderc::Compiler>,
+        name: &str,
+        id: LayerId,
+        desc: LayerDesc,
+    ) -> Result<Self, Error> {
+        let hash = hex::encode(id.0.as_slice());
+        let header_filename = cache_dir.place_file(format!("generated/{}.header", &hash))?;
+        let data_filename = cache_dir.place_file(format!("generated/{}.data", &hash))?;
+        let spirv_filename = cache_dir.place_file(format!("generated/{}.spv", &hash))?;
+
+        let (ref shader_name, cache_size, dedup_sectors, sampler) = match config.nodes[name] {
+            Node::Generated {
+                ref shader,
+                cache_size,
+                dedup_sectors,
+                sampler,
+                ..
+            } => (shader, cache_size, dedup_sectors, sampler),
+            _ => unreachable!(),
         };
-        if ax > ay {
-            (ax * ax + 2 * ay + 1) * 4 + q
+        let dedup = if dedup_sectors {
+            let filename = cache_dir.place_file(format!("generated/{}.refs", &hash))?;
+            let dedup = match fs::read(&filename) {
+                Ok(contents) => serde_json::from_slice(&contents)?,
+                Err(_) => SectorDedup::default(),
+            };
+            Some((filename, dedup))
         } else {
-            (ay * ay + 2 * ax) * 4 + q
+            None
+        };
+        let input_hashes_filename = cache_dir.place_file(format!("generated/{}.inputs", &hash))?;
+        let input_hashes = match fs::read(&input_hashes_filename) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(_) => HashMap::new(),
+        };
+
+        fs::write(header_filename, serde_json::to_string_pretty(&desc)?);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(&data_filename)?;
+
+        // TODO: Seeking past end is implementation defined. Use a cross platform option instead.
+        let target_size = desc.sector_bytes
+            * config.side_length_sectors as u64
+            * config.side_length_sectors as u64;
+        file.seek(SeekFrom::Start(target_size))?;
+        file.write_all(&[0u8])?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let data = unsafe { MmapMut::map_mut(&file)? };
+
+        // The shader source and every input to the macros below are covered by the layer id,
+        // so a cached module is never stale.
+        let spirv = match read_cached_spirv(&spirv_filename) {
+            Some(spirv) => spirv,
+            None => {
+                if glsl_compiler.is_none() {
+                    *glsl_compiler = Some(
+                        shaderc::Compiler::new()
+                            .ok_or(format_err!("Shader compiler init failed"))?,
+                    );
+                }
+                let mut options = shaderc::CompileOptions::new()
+                    .ok_or(format_err!("Shader compiler init failed"))?;
+                for (macro_name, value) in shader_defines(config, name) {
+                    options.add_macro_definition(&macro_name, Some(&value));
+                }
+                // Shaders that use procedural noise should derive it from `SEED`, a `uvec2` holding
+                // the low and high halves of the seed, so that output is reproducible. The seed is
+                // part of the layer id, so changing it invalidates the cache.
+                options.add_macro_definition("SEED", Some(&seed_define(config.seed)));
+                let local_size = desc.local_size.to_string();
+                options.add_macro_definition("LOCAL_SIZE_X", Some(&local_size));
+                options.add_macro_definition("LOCAL_SIZE_Y", Some(&local_size));
+                let artifact = glsl_compiler.as_mut().unwrap().compile_into_spirv(
+                    &desc.shader,
+                    shaderc::ShaderKind::Compute,
+                    shader_name,
+                    "main",
+                    Some(&options),
+                )?;
+                let spirv = artifact.as_binary().to_vec();
+                write_atomic(&spirv_filename, &spirv_to_bytes(&spirv))?;
+                spirv
+            }
+        };
+        let images = rshader::image_descriptor_names(&spirv).map_err(|e| format_err!("{}", e))?;
+        if let Node::Generated { ref inputs, .. } = config.nodes[name] {
+            validate_shader_bindings(name, &images, inputs)?;
+        }
+        let sampler_bindings =
+            rshader::sampler_descriptor_bindings(&spirv).map_err(|e| format_err!("{}", e))?;
+        let shader = SpirvShader::new(spirv_to_bytes(&spirv), ShaderStageFlags::COMPUTE, "main");
+        let shader = ShaderSetBuilder::default()
+            .with_compute(&shader)?
+            .build(&factory, Default::default())?;
+
+        let image = factory
+            .create_image(
+                ImageInfo {
+                    kind: resource::Kind::D2(desc.resolution, desc.resolution, cache_size, 1),
+                    levels: 1,
+                    format: image_format(desc.format),
+                    tiling: resource::Tiling::Optimal,
+                    view_caps: resource::ViewCapabilities::KIND_2D_ARRAY,
+                    usage: Usage::TRANSFER_SRC
+                        | Usage::TRANSFER_DST
+                        | Usage::SAMPLED
+                        | Usage::COLOR_ATTACHMENT
+                        | Usage::INPUT_ATTACHMENT,
+                },
+                memory::Data,
+            )?
+            .into();
+        let anisotropy_supported =
+            factory.features().contains(gfx_hal::Features::SAMPLER_ANISOTROPY);
+        let sampler = factory.get_sampler(sampler_info(sampler, anisotropy_supported))?;
+
+        Ok(Layer {
+            filename: data_filename,
+            shader,
+            images,
+            sampler_bindings,
+            data,
+            sector_cache: TileCache {
+                image,
+                size: cache_size as usize,
+                contents: Vec::new(),
+                sector_indices: LinkedHashMap::new(),
+                free_slots: Vec::new(),
+                policy: EvictionPolicy::default(),
+                priorities: HashMap::new(),
+                stats: CacheStats::default(),
+                resolution: desc.resolution,
+            },
+            sampler,
+            dedup,
+            input_hashes: (input_hashes_filename, input_hashes),
+            desc,
+        })
+    }
+
+    /// Builds a half resolution overview of a stored sector by averaging each 2x2 block of
+    /// texels, for use at coarse levels of detail without running the layer's shader again. The
+    /// result is uploaded to a new image of the layer's format.
+    #[allow(unused)]
+    pub fn generate_mip(
+        &self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        sector: Sector,
+    ) -> Result<Handle<Image<B>>, Error> {
+        let resolution = self.desc.resolution;
+        let data = downsample_sector(
+            self.read_sector(sector),
+            resolution as usize,
+            self.desc.format,
+            self.desc.srgb,
+        );
+        let half = (resolution + 1) / 2;
+
+        let image: Handle<Image<B>> = factory
+            .create_image(
+                ImageInfo {
+                    kind: resource::Kind::D2(half, half, 1, 1),
+                    levels: 1,
+                    format: image_format(self.desc.format),
+                    tiling: resource::Tiling::Optimal,
+                    view_caps: resource::ViewCapabilities::empty(),
+                    usage: Usage::TRANSFER_DST | Usage::SAMPLED,
+                },
+                memory::Data,
+            )?
+            .into();
+        unsafe {
+            factory
+                .upload_image(
+                    image.clone(),
+                    half,
+                    half,
+                    rendy::resource::SubresourceLayers {
+                        aspects: gfx_hal::format::Aspects::COLOR,
+                        level: 0,
+                        layers: 0..1,
+                    },
+                    gfx_hal::image::Offset { x: 0, y: 0, z: 0 },
+                    rendy::resource::Extent { width: half, height: half, depth: 1 },
+                    &data,
+                    ImageState::new(queue, Layout::General),
+                    ImageState::new(queue, Layout::General),
+                )
+                .map_err(|e| format_err!("failed to upload overview: {:?}", e))?;
         }
+        Ok(image)
+    }
+
+    /// Returns the sampler to use when reading this layer's cached tiles.
+    #[allow(unused)]
+    pub fn sampler(&self) -> &Handle<Sampler<B>> {
+        &self.sampler
+    }
+
+    /// Returns the sectors currently held in the layer's sector cache, with the most recently
+    /// used one last.
+    #[allow(unused)]
+    pub fn resident_sectors(&self) -> Vec<Sector> {
+        self.sector_cache.keys().copied().collect()
+    }
+
+    /// Drops `sector` from the layer's sector cache so that the next access uploads it again from
+    /// the data file, for instance after the stored sector has been edited. Sectors that aren't
+    /// resident are ignored.
+    #[allow(unused)]
+    pub fn invalidate_sector(&mut self, sector: Sector) {
+        self.sector_cache.remove(sector);
+    }
+
+    /// Uploads stored sectors into the layer's sector cache ahead of when they are needed. Sectors
+    /// that are already resident are skipped, and at most as many sectors as the cache can hold
+    /// are loaded, so earlier entries in `sectors` take precedence. Uploads aren't waited on.
+    /// Returns the sectors that were loaded.
+    #[allow(unused)]
+    pub fn prefetch(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        sectors: &[Sector],
+    ) -> Result<Vec<Sector>, Error> {
+        let sectors =
+            prefetch_keys(&self.sector_cache.sector_indices, self.sector_cache.size, sectors);
+        let tiles = sectors.iter().map(|&s| (s, self.read_sector(s).to_vec())).collect();
+        self.sector_cache.preload(factory, queue, tiles)?;
+        Ok(sectors)
+    }
+
+    fn compute_sector_index(sector: Sector) -> u64 {
+        sector_index(sector)
+    }
+    /// Inverse of `compute_sector_index`, for working out which sector is stored at a given
+    /// position in a layer's data file.
+    #[allow(unused)]
+    pub fn sector_from_index(index: u64) -> Sector {
+        sector_from_index(index)
     }
     fn compute_sector_offset(&self, sector: Sector) -> u64 {
         self.desc.sector_bytes * Self::compute_sector_index(sector)
     }
+
+    /// Stores the contents of a sector, writing a reference instead if deduplication is enabled
+    /// and an identical sector has already been stored.
+    fn write_sector(&mut self, sector: Sector, bytes: &[u8]) -> Result<(), Error> {
+        let dedup = self.dedup.as_mut().map(|(_, dedup)| dedup);
+        let stored = write_sector(&mut self.data, self.desc.sector_bytes, dedup, sector, bytes)?;
+        if let Some((ref filename, ref dedup)) = self.dedup {
+            fs::write(filename, serde_json::to_vec(dedup)?)?;
+        }
+        if stored {
+            self.data.flush_range(
+                self.compute_sector_offset(sector) as usize,
+                self.desc.sector_bytes as usize,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Records that `sector` was generated from inputs with the given hash. The record only
+    /// reaches the disk once `save_input_hashes` is called.
+    fn set_input_hash(&mut self, sector: Sector, hash: String) {
+        self.input_hashes.1.insert(sector_index(sector), hash);
+    }
+
+    /// Writes the recorded input hashes to the layer's `.inputs` file.
+    fn save_input_hashes(&self) -> Result<(), Error> {
+        let (ref filename, ref hashes) = self.input_hashes;
+        fs::write(filename, serde_json::to_vec(hashes)?)?;
+        Ok(())
+    }
+
+    /// Returns the contents of a sector, following references to deduplicated sectors.
+    fn read_sector(&self, sector: Sector) -> &[u8] {
+        let dedup = self.dedup.as_ref().map(|(_, dedup)| dedup);
+        read_sector(&self.data, self.desc.sector_bytes, dedup, sector)
+    }
+
+    /// Submits the layer's shader to run once with each of `inputs` (slot name, cache image and
+    /// layer) bound by name and a scratch image bound as `output`. The contents of the output image
+    /// are returned by `PendingDispatch::finish`.
+    unsafe fn submit(
+        &self,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+        queue: QueueId,
+        inputs: &[(String, Handle<Image<B>>, usize)],
+    ) -> Result<PendingDispatch<B>, Error> {
+        let resolution = self.desc.resolution;
+        let format = image_format(self.desc.format);
+        let output = factory.create_image(
+            ImageInfo {
+                kind: resource::Kind::D2(resolution, resolution, 1, 1),
+                levels: 1,
+                format,
+                tiling: resource::Tiling::Optimal,
+                view_caps: resource::ViewCapabilities::empty(),
+                usage: Usage::STORAGE | Usage::TRANSFER_SRC,
+            },
+            memory::Data,
+        )?;
+
+        let mut views = Vec::new();
+        for (binding, name) in &self.images {
+            let (image, layer) = if name == "output" {
+                (output.clone().into(), 0)
+            } else {
+                let (_, image, layer) = inputs
+                    .iter()
+                    .find(|input| &input.0 == name)
+                    .ok_or(format_err!("no input bound to '{}'", name))?;
+                (image.clone(), *layer as u16)
+            };
+            let view = factory.create_image_view(
+                image,
+                ImageViewInfo {
+                    view_kind: resource::ViewKind::D2,
+                    format,
+                    swizzle: gfx_hal::format::Swizzle::NO,
+                    range: gfx_hal::image::SubresourceRange {
+                        aspects: gfx_hal::format::Aspects::COLOR,
+                        levels: 0..1,
+                        layers: layer..layer + 1,
+                    },
+                },
+            )?;
+            views.push((*binding, view));
+        }
+
+        let set_layout = factory.create_descriptor_set_layout(
+            views
+                .iter()
+                .map(|&(binding, _)| (binding, gfx_hal::pso::DescriptorType::StorageImage))
+                .chain(
+                    self.sampler_bindings
+                        .iter()
+                        .map(|&binding| (binding, gfx_hal::pso::DescriptorType::Sampler)),
+                )
+                .map(|(binding, ty)| gfx_hal::pso::DescriptorSetLayoutBinding {
+                    binding,
+                    ty,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        )?;
+        let set = factory.create_descriptor_set(set_layout.clone())?;
+        factory.write_descriptor_sets(views.iter().map(|(binding, view)| {
+            gfx_hal::pso::DescriptorSetWrite {
+                set: set.raw(),
+                binding: *binding,
+                array_offset: 0,
+                descriptors: Some(gfx_hal::pso::Descriptor::Image(view.raw(), Layout::General)),
+            }
+        }));
+        factory.write_descriptor_sets(self.sampler_bindings.iter().map(|binding| {
+            gfx_hal::pso::DescriptorSetWrite {
+                set: set.raw(),
+                binding: *binding,
+                array_offset: 0,
+                descriptors: Some(gfx_hal::pso::Descriptor::Sampler(self.sampler.raw())),
+            }
+        }));
+
+        let pipeline_layout = factory
+            .device()
+            .create_pipeline_layout(Some(set_layout.raw()), std::iter::empty::<(_, _)>())?;
+        let pipeline = factory.device().create_compute_pipeline(
+            &gfx_hal::pso::ComputePipelineDesc::new(
+                self.shader.raw_compute_stage()?,
+                &pipeline_layout,
+            ),
+            None,
+        )?;
+
+        let bytes = self.desc.sector_bytes;
+        let download = factory.create_buffer(
+            BufferInfo { size: bytes, usage: gfx_hal::buffer::Usage::TRANSFER_DST },
+            memory::Download,
+        )?;
+
+        let family = families.family_by_index_mut(queue.family);
+        let mut pool = factory.create_command_pool(family)?;
+        let mut encoder = pool.allocate_buffers(1).pop().unwrap().begin(OneShot, ());
+        encoder.bind_compute_pipeline(&pipeline);
+        encoder.bind_compute_descriptor_sets(
+            &pipeline_layout,
+            0,
+            Some(set.raw()),
+            std::iter::empty(),
+        );
+        let local_size = self.desc.local_size;
+        let groups = (resolution + local_size - 1) / local_size;
+        encoder.dispatch(groups, groups, 1);
+        encoder.copy_image_to_buffer(
+            output.raw(),
+            Layout::General,
+            download.raw(),
+            Some(gfx_hal::command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: 0,
+                buffer_height: 0,
+                image_layers: gfx_hal::image::SubresourceLayers {
+                    aspects: gfx_hal::format::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: gfx_hal::image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: gfx_hal::image::Extent {
+                    width: resolution,
+                    height: resolution,
+                    depth: 1,
+                },
+            }),
+        );
+        let (submit, buffer) = encoder.finish().submit_once();
+
+        let mut fence = factory.create_fence(false)?;
+        family.queue_mut(queue.index).submit(
+            Some(Submission::new().submits(Some(submit))),
+            Some(&mut fence),
+        );
+        Ok(PendingDispatch {
+            fence,
+            pool,
+            buffer,
+            pipeline,
+            pipeline_layout,
+            download,
+            bytes,
+            bindings: (set, views, self.sampler.clone()),
+        })
+    }
+}
+
+/// Directory holding downloaded datasets and generated layers.
+pub struct CacheDir {
+    root: PathBuf,
+}
+impl CacheDir {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Creates the directory `path` under the cache root, along with any missing parents.
+    pub fn create_directory<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        let directory = self.root.join(path);
+        fs::create_dir_all(&directory)?;
+        Ok(directory)
+    }
+
+    /// Returns where to put the file `path` under the cache root, creating its parent directory
+    /// if needed.
+    pub fn place_file<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        let file = self.root.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(file)
+    }
 }
 
 pub struct Graph<B: Backend> {
     config: GraphFile,
-    xdg_dirs: BaseDirectories,
+    cache_dir: CacheDir,
+    /// Longitude and latitude of the center of the map, decoded from `config.center`.
+    center: (f64, f64),
 
     layer_ids: HashMap<String, LayerId>,
     generated_layers: HashMap<LayerId, Layer<B>>,
@@ -178,6 +1154,12 @@ pub struct Graph<B: Backend> {
 
     order: Vec<LayerId>,
     priorities: HashMap<LayerType, Vec<LayerId>>,
+
+    generations: GenerationLimit<PendingGeneration<B>>,
+
+    /// Directory that shader sources are reloaded from, if any, and when that last happened.
+    shader_watcher: Option<rshader::ShaderDirectoryWatcher>,
+    shaders_refreshed: Instant,
 }
 
 impl<B: Backend> Graph<B> {
@@ -186,277 +1168,1420 @@ impl<B: Backend> Graph<B> {
         config_string: &str,
         xdg_dirs: BaseDirectories,
         factory: &mut Factory<B>,
+    ) -> Result<Graph<B>, Error> {
+        Self::from_file_with_cache_dir(config_string, xdg_dirs.get_cache_home(), factory)
+    }
+
+    /// Like `from_file`, but keeps the cache under `cache_root` instead of the XDG cache
+    /// directory.
+    #[allow(unused)]
+    pub fn from_file_with_cache_dir(
+        config_string: &str,
+        cache_root: PathBuf,
+        factory: &mut Factory<B>,
     ) -> Result<Graph<B>, Error> {
         let config: GraphFile = toml::from_str(&config_string)?;
+        Self::from_config(config, CacheDir::new(cache_root), factory)
+    }
+
+    /// Checks that `config_string` describes a valid graph without creating any GPU resources or
+    /// touching the cache directory. Reports the same configuration errors as `from_file`, but
+    /// can't catch problems that only show up when shaders are compiled.
+    #[allow(unused)]
+    pub fn validate_config(config_string: &str) -> Result<(), Error> {
+        validate_config(config_string)
+    }
+
+    /// Loads a graph whose description is split across several files. Nodes and shaders from all
+    /// the files are combined, and it is an error for two files to define the same name or to
+    /// disagree about the center or size of the map.
+    #[allow(unused)]
+    pub fn from_files(
+        paths: &[PathBuf],
+        xdg_dirs: BaseDirectories,
+        factory: &mut Factory<B>,
+    ) -> Result<Graph<B>, Error> {
+        let mut fragments = Vec::new();
+        for path in paths {
+            let contents = fs::read_to_string(path)?;
+            let fragment: GraphFileFragment = toml::from_str(&contents)
+                .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            fragments.push(fragment);
+        }
+        let cache_dir = CacheDir::new(xdg_dirs.get_cache_home());
+        Self::from_config(merge_graph_files(fragments)?, cache_dir, factory)
+    }
+
+    #[allow(unused)]
+    fn from_config(
+        config: GraphFile,
+        cache_dir: CacheDir,
+        factory: &mut Factory<B>,
+    ) -> Result<Graph<B>, Error> {
         let center = open_location_code::decode(&config.center)
             .map_err(|e| format_err!("{}", e))?
             .center;
         let center = (center.x(), center.y());
 
-        let order: Vec<String> = {
-            let mut ids = HashMap::new();
-            let mut g = DiGraph::new();
-            for name in config.nodes.keys() {
-                ids.insert(name.to_owned(), g.add_node(name));
-            }
-            for (name, node) in config.nodes.iter() {
-                if let description::Node::Generated { ref inputs, .. } = node {
-                    let child = ids[name];
-                    for parent in inputs.values() {
-                        match ids.get(parent) {
-                            Some(p) => g.add_edge(p.clone(), child, ()),
-                            None => bail!("node.{} not found", parent),
-                        };
-                    }
-                }
-            }
+        let order = compute_order(&config)?;
+        check_resolutions(&config)?;
+        check_samplers(&config)?;
 
-            Topo::new(&g)
-                .iter(&g)
-                .map(|id| g.node_weight(id).unwrap().to_string())
-                .collect()
-        };
+        let (layer_ids, dataset_descs, layer_descriptors) = describe_layers(&config, &order)?;
 
-        let mut layer_ids = HashMap::new();
-        let mut layer_descriptors = BTreeMap::new();
         let mut dataset_layers = HashMap::new();
         for name in &order {
-            match &config.nodes[name] {
-                Node::Dataset {
-                    url,
-                    resolution,
-                    format,
-                    bib,
-                    license,
-                    projection,
-                    cache_size,
-                    ..
-                } => {
-                    let desc = DatasetDesc {
-                        url: url.to_owned(),
-                        credentials: None,
-                        projection: *projection,
-                        resolution: *resolution,
-                        file_format: *format,
-                        texture_format: TextureFormat::R32F,
-                    };
-                    let desc_bytes = bincode::serialize(&desc)?;
-                    let id = LayerId(Sha256::digest(&desc_bytes));
-                    layer_ids.insert(name.to_owned(), id.clone());
-                    let directory = xdg_dirs.create_cache_directory(format!(
-                        "datasets/{}",
-                        hex::encode(id.0.as_slice())
-                    ))?;
-                    fs::write(
-                        directory.join("header.json"),
-                        serde_json::to_string_pretty(&desc)?,
-                    )?;
-
-                    let image = factory
-                        .create_image(
-                            ImageInfo {
-                                kind: resource::Kind::D2(
-                                    *resolution as u32,
-                                    *resolution as u32,
-                                    *cache_size,
-                                    1,
-                                ),
-                                levels: 1,
-                                format: gfx_hal::format::Format::R32Sfloat,
-                                tiling: resource::Tiling::Optimal,
-                                view_caps: resource::ViewCapabilities::KIND_2D_ARRAY,
-                                usage: Usage::TRANSFER_SRC
-                                    | Usage::TRANSFER_DST
-                                    | Usage::SAMPLED
-                                    | Usage::COLOR_ATTACHMENT
-                                    | Usage::INPUT_ATTACHMENT,
-                            },
-                            memory::Data,
-                        )?
-                        .into();
-
-                    dataset_layers.insert(
-                        id,
-                        Dataset {
-                            bib: bib.to_owned(),
-                            license: license.to_owned(),
-                            directory,
-                            tile_cache: TileCache {
-                                image,
-                                size: *cache_size as usize,
-                                contents: Vec::new(),
-                                sector_indices: LinkedHashMap::new(),
-                                resolution: desc.resolution,
-                            },
-                            desc,
+            let node = &config.nodes[name];
+            if let Node::Dataset { bib, license, resolution, cache_size, .. } = node {
+                let desc = dataset_descs[name].clone();
+                let id = layer_ids[name];
+                let directory = cache_dir.create_directory(format!(
+                    "datasets/{}",
+                    hex::encode(id.0.as_slice())
+                ))?;
+                fs::write(directory.join("header.json"), serde_json::to_string_pretty(&desc)?)?;
+
+                let image = factory
+                    .create_image(
+                        ImageInfo {
+                            kind: resource::Kind::D2(
+                                *resolution as u32,
+                                *resolution as u32,
+                                *cache_size,
+                                1,
+                            ),
+                            levels: 1,
+                            format: image_format(desc.texture_format),
+                            tiling: resource::Tiling::Optimal,
+                            view_caps: resource::ViewCapabilities::KIND_2D_ARRAY,
+                            usage: Usage::TRANSFER_SRC
+                                | Usage::TRANSFER_DST
+                                | Usage::SAMPLED
+                                | Usage::COLOR_ATTACHMENT
+                                | Usage::INPUT_ATTACHMENT,
                         },
-                    );
-                }
-                Node::Generated {
-                    ref inputs,
-                    resolution,
-                    corner_registration,
-                    format,
-                    ref shader,
-                    ..
-                } => {
-                    let desc = LayerDesc {
-                        parents: {
-                            let mut parents = BTreeMap::new();
-                            for input in inputs.values() {
-                                let id = layer_ids[input];
-                                parents.insert(input.to_owned(), id);
-                            }
-                            parents
+                        memory::Data,
+                    )?
+                    .into();
+
+                dataset_layers.insert(
+                    id,
+                    Dataset {
+                        bib: bib.to_owned(),
+                        license: license.to_owned(),
+                        directory,
+                        tile_cache: TileCache {
+                            image,
+                            size: *cache_size as usize,
+                            contents: Vec::new(),
+                            sector_indices: LinkedHashMap::new(),
+                            free_slots: Vec::new(),
+                            policy: EvictionPolicy::default(),
+                            priorities: HashMap::new(),
+                            stats: CacheStats::default(),
+                            resolution: desc.resolution,
                         },
-                        resolution: *resolution,
-                        corner_registration: *corner_registration,
-                        format: *format,
-                        sector_bytes: (resolution * resolution * format.bytes_per_pixel()) as u64,
-                        shader: config
-                            .shaders
-                            .get(shader)
-                            .ok_or(format_err!("Missing shader '{}'", shader))?
-                            .to_owned(),
-                        center: config.center.clone(),
-                    };
-                    let desc_bytes = bincode::serialize(&desc)?;
-                    let id = LayerId(Sha256::digest(&desc_bytes));
-                    layer_ids.insert(name.to_owned(), id);
-                    layer_descriptors.insert(name.to_owned(), desc);
-                }
-            };
+                        desc,
+                    },
+                );
+            }
         }
 
-        let mut priorities = HashMap::new();
-        for name in order.iter().rev() {
-            let ty = match config.nodes[name] {
-                Node::Generated {
-                    kind: OutputKind::HeightMap,
-                    ..
-                } => LayerType::Heightmap,
-                Node::Generated {
-                    kind: OutputKind::NormalMap,
-                    ..
-                } => LayerType::Heightmap,
-                Node::Generated {
-                    kind: OutputKind::AlbedoMap,
-                    ..
-                } => LayerType::Albedo,
-                _ => continue,
-            };
-
-            priorities.entry(ty).or_insert(vec![]).push(layer_ids[name]);
-        }
+        let priorities = compute_priorities(&config, &order)
+            .into_iter()
+            .map(|(ty, names)| (ty, names.iter().map(|name| layer_ids[name]).collect()))
+            .collect();
 
-        let mut glsl_compiler =
-            shaderc::Compiler::new().ok_or(format_err!("Shader compiler init failed"))?;
+        // Only created once a shader is missing from the cache.
+        let mut glsl_compiler = None;
 
         let mut generated_layers = HashMap::new();
         for (name, desc) in layer_descriptors {
-            let id = layer_ids[&name].to_owned();
-            let hash = hex::encode(id.0.as_slice());
-            let header_filename =
-                xdg_dirs.place_cache_file(format!("generated/{}.header", &hash))?;
-            let data_filename = xdg_dirs.place_cache_file(format!("generated/{}.data", &hash))?;
-
-            let (ref shader_name, cache_size) = match config.nodes[&name] {
-                Node::Generated {
-                    ref shader,
-                    cache_size,
-                    ..
-                } => (shader, cache_size),
-                _ => unreachable!(),
-            };
+            let id = layer_ids[&name];
+            let layer =
+                Layer::build(&config, &cache_dir, factory, &mut glsl_compiler, &name, id, desc)?;
+            generated_layers.insert(id, layer);
+        }
 
-            fs::write(header_filename, serde_json::to_string_pretty(&desc)?);
-            let mut file = OpenOptions::new()
-                .create(true)
-                .read(true)
-                .write(true)
-                .open(&data_filename)?;
-
-            // TODO: Seeking past end is implementation defined. Use a cross platform option instead.
-            let target_size = desc.sector_bytes
-                * config.side_length_sectors as u64
-                * config.side_length_sectors as u64;
-            file.seek(SeekFrom::Start(target_size))?;
-            file.write_all(&[0u8])?;
-            file.seek(SeekFrom::Start(0))?;
-
-            let data = unsafe { MmapMut::map_mut(&file)? };
-
-            let spirv = glsl_compiler
-                .compile_into_spirv(
-                    &desc.shader,
-                    shaderc::ShaderKind::Compute,
-                    shader_name,
-                    "main",
-                    None,
-                )?
-                .as_binary_u8()
-                .to_vec();
-            let shader = SpirvShader::new(spirv, ShaderStageFlags::COMPUTE, "main");
-            let shader = ShaderSetBuilder::default()
-                .with_compute(&shader)?
-                .build(&factory, Default::default())?;
-
-            let image = factory
-                .create_image(
-                    ImageInfo {
-                        kind: resource::Kind::D2(desc.resolution, desc.resolution, cache_size, 1),
-                        levels: 1,
-                        format: match desc.format {
-                            TextureFormat::R32F => gfx_hal::format::Format::R32Sfloat,
-                            TextureFormat::Rgba8 => gfx_hal::format::Format::Rgba8Unorm,
-                        },
-                        tiling: resource::Tiling::Optimal,
-                        view_caps: resource::ViewCapabilities::KIND_2D_ARRAY,
-                        usage: Usage::TRANSFER_SRC
-                            | Usage::TRANSFER_DST
-                            | Usage::SAMPLED
-                            | Usage::COLOR_ATTACHMENT
-                            | Usage::INPUT_ATTACHMENT,
-                    },
-                    memory::Data,
-                )?
-                .into();
-
-            generated_layers.insert(
-                id,
-                Layer {
-                    filename: data_filename,
-                    shader,
-                    data,
-                    sector_cache: TileCache {
-                        image,
-                        size: cache_size as usize,
-                        contents: Vec::new(),
-                        sector_indices: LinkedHashMap::new(),
-                        resolution: desc.resolution,
-                    },
-                    desc,
-                },
+        Ok(Graph {
+            config,
+            cache_dir,
+            center,
+            order: order.iter().map(|name| layer_ids[name]).collect(),
+            layer_ids,
+            priorities,
+            generated_layers,
+            dataset_layers,
+            generations: GenerationLimit::new(DEFAULT_MAX_CONCURRENT_GENERATIONS),
+            shader_watcher: None,
+            shaders_refreshed: Instant::now(),
+        })
+    }
+
+    /// Starts watching `directory` for edited shaders. Once a file named after one of the graph's
+    /// shaders with a `.comp` extension is written, `refresh_shaders` replaces that shader with the
+    /// file's contents.
+    #[allow(unused)]
+    pub fn watch_shaders(&mut self, directory: PathBuf) -> Result<(), Error> {
+        let watcher =
+            rshader::ShaderDirectoryWatcher::new(directory).map_err(|e| format_err!("{}", e))?;
+        self.shader_watcher = Some(watcher);
+        self.shaders_refreshed = Instant::now();
+        Ok(())
+    }
+
+    /// Reloads every shader whose file in the watched directory changed since the last refresh,
+    /// and rebuilds the generated layers affected by them as described in `set_shaders`. Returns
+    /// the names of the rebuilt layers, which is empty if `watch_shaders` was never called.
+    #[allow(unused)]
+    pub fn refresh_shaders(&mut self, factory: &mut Factory<B>) -> Result<Vec<String>, Error> {
+        let watcher = match self.shader_watcher {
+            Some(ref mut watcher) => watcher,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut shaders = self.config.shaders.clone();
+        let mut changed = false;
+        for (name, source) in &mut shaders {
+            let filename = PathBuf::from(format!("{}.comp", name));
+            if watcher.modified_since(&filename, self.shaders_refreshed) {
+                *source = fs::read_to_string(watcher.directory().join(&filename))?;
+                changed = true;
+            }
+        }
+        // A shader that fails to compile isn't retried until it is edited again.
+        self.shaders_refreshed = Instant::now();
+
+        if !changed {
+            return Ok(Vec::new());
+        }
+        self.set_shaders(factory, shaders)
+    }
+
+    /// Replaces the graph's shader sources with `shaders` and rebuilds each generated layer whose
+    /// id changes as a result, meaning those whose shader changed along with everything downstream
+    /// of them. Rebuilt layers start out with empty sector caches so their sectors get regenerated,
+    /// while all other layers keep their caches. Returns the names of the rebuilt layers.
+    #[allow(unused)]
+    pub fn set_shaders(
+        &mut self,
+        factory: &mut Factory<B>,
+        shaders: BTreeMap<String, String>,
+    ) -> Result<Vec<String>, Error> {
+        let refresh = plan_shader_refresh(&self.config, &self.layer_ids, shaders)?;
+        let ShaderRefresh { config, order, layer_ids, rebuild } = refresh;
+
+        // Build every affected layer before touching `self`, so that a shader which fails to
+        // compile leaves the graph as it was.
+        let mut glsl_compiler = None;
+        let mut layers = Vec::new();
+        for (name, desc) in rebuild {
+            let id = layer_ids[&name];
+            let cache_dir = &self.cache_dir;
+            let layer =
+                Layer::build(&config, cache_dir, factory, &mut glsl_compiler, &name, id, desc)?;
+            layers.push((name, layer));
+        }
+
+        let mut rebuilt = Vec::new();
+        for (name, layer) in layers {
+            self.generated_layers.remove(&self.layer_ids[&name]);
+            self.generated_layers.insert(layer_ids[&name], layer);
+            rebuilt.push(name);
+        }
+        self.priorities = compute_priorities(&config, &order)
+            .into_iter()
+            .map(|(ty, names)| (ty, names.iter().map(|name| layer_ids[name]).collect()))
+            .collect();
+        self.order = order.iter().map(|name| layer_ids[name]).collect();
+        self.layer_ids = layer_ids;
+        self.config = config;
+        Ok(rebuilt)
+    }
+
+    /// Sets how many sector generations may be in flight at once. Generations that are already
+    /// running are unaffected, but no new ones start until the count drops below `max`.
+    pub fn set_max_concurrent_generations(&mut self, max: usize) {
+        assert!(max > 0);
+        self.generations.max = max;
+    }
+
+    /// Stores the output of every generation whose fence has signaled in its layer's data file and
+    /// sector cache, releasing its permit. Returns the sectors that were stored.
+    pub fn finish_generations(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+    ) -> Result<Vec<(LayerId, Sector)>, Error> {
+        let finished = self.generations.take_finished(|g| g.dispatch.is_done(factory));
+        self.store_generations(factory, queue, finished)
+    }
+
+    /// Like `finish_generations`, but first waits for every generation in flight to finish.
+    pub fn wait_for_generations(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+    ) -> Result<Vec<(LayerId, Sector)>, Error> {
+        let generations = std::iter::from_fn(|| self.generations.take_oldest()).collect();
+        self.store_generations(factory, queue, generations)
+    }
+
+    /// Stores each of `generations` in turn, then saves the input hashes of the layers they belong
+    /// to.
+    fn store_generations(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        generations: Vec<PendingGeneration<B>>,
+    ) -> Result<Vec<(LayerId, Sector)>, Error> {
+        let mut stored = Vec::new();
+        for generation in generations {
+            stored.push((generation.id, generation.sector));
+            self.store_generation(factory, queue, generation)?;
+        }
+        let layers: HashSet<LayerId> = stored.iter().map(|&(id, _)| id).collect();
+        for id in layers {
+            self.generated_layers[&id].save_input_hashes()?;
+        }
+        Ok(stored)
+    }
+
+    /// Waits for `generation` to finish and stores its output. Its input hash is only recorded in
+    /// memory; see `store_generations`.
+    fn store_generation(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        generation: PendingGeneration<B>,
+    ) -> Result<(), Error> {
+        let output = unsafe { generation.dispatch.finish(factory)? };
+        let layer = self.generated_layers.get_mut(&generation.id).unwrap();
+        layer.write_sector(generation.sector, &output)?;
+        layer.set_input_hash(generation.sector, generation.input_hash);
+        layer.sector_cache.insert(factory, queue, generation.sector, &output)?;
+        Ok(())
+    }
+
+    /// Returns the id of the node called `name` in the graph's config, if there is one. This is the
+    /// id used to name the node's files in the cache directory.
+    pub fn layer_id(&self, name: &str) -> Option<LayerId> {
+        self.layer_ids.get(name).copied()
+    }
+
+    /// Returns the depth of each layer in the dependency graph. Layers without inputs have depth
+    /// zero, and every other layer is one deeper than its deepest input, so all layers at the same
+    /// depth can be generated concurrently once the shallower ones are done.
+    pub fn layer_depths(&self) -> HashMap<LayerId, usize> {
+        let names: HashMap<LayerId, &String> =
+            self.layer_ids.iter().map(|(name, &id)| (id, name)).collect();
+        let order: Vec<String> = self.order.iter().map(|id| names[id].clone()).collect();
+        compute_depths(&self.config, &order)
+            .into_iter()
+            .map(|(name, depth)| (self.layer_ids[&name], depth))
+            .collect()
+    }
+
+    /// Loads the given sectors of the generated layer `id` into its sector cache. See
+    /// `Layer::prefetch`.
+    #[allow(unused)]
+    pub fn prefetch(
+        &mut self,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        id: LayerId,
+        sectors: &[Sector],
+    ) -> Result<Vec<Sector>, Error> {
+        self.generated_layers
+            .get_mut(&id)
+            .ok_or(format_err!("no generated layer with id {}", hex::encode(id.0.as_slice())))?
+            .prefetch(factory, queue, sectors)
+    }
+
+    /// Returns the description of the generated layer with the given id, if there is one.
+    pub fn layer_desc(&self, id: LayerId) -> Option<&LayerDesc> {
+        self.generated_layers.get(&id).map(|layer| &layer.desc)
+    }
+
+    /// Writes the generated layer with the given id to `path` as a tiled GeoTIFF covering the
+    /// whole map.
+    pub fn export_layer_geotiff<P: AsRef<Path>>(&self, id: LayerId, path: P) -> Result<(), Error> {
+        let layer = self
+            .generated_layers
+            .get(&id)
+            .ok_or(format_err!("No generated layer with id {}", hex::encode(id.0.as_slice())))?;
+        let mut file = fs::File::create(path)?;
+        write_layer_geotiff(
+            &mut file,
+            &layer.desc,
+            &layer.data,
+            layer.dedup.as_ref().map(|(_, dedup)| dedup),
+            self.config.side_length_sectors,
+            self.center,
+        )
+    }
+
+    /// Returns every sector overlapping the given rectangle, ordered by sector index.
+    pub fn sectors_in_rect(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> impl Iterator<Item = Sector> {
+        sectors_in_rect(self.center, min_lat, min_lon, max_lat, max_lon)
+    }
+
+    /// Returns the name and license of every dataset in the graph that declares a license, ordered
+    /// by name. Some sources require this to be shown to users.
+    pub fn attributions(&self) -> Vec<(String, String)> {
+        dataset_attributions(&self.config)
+    }
+
+    /// Returns the distinct bibliography entries of the graph's datasets, ordered by the name of
+    /// the first dataset citing each.
+    pub fn bibliography(&self) -> Vec<String> {
+        dataset_bibliography(&self.config)
+    }
+
+    /// Lists the datasets and generated layers stored in the cache directory, including those
+    /// that don't belong to this graph.
+    pub fn cache_entries(&self) -> Result<Vec<CacheEntry>, Error> {
+        scan_cache(self.cache_dir.root())
+    }
+
+    /// Deletes all cached files for the layer with the given id. Returns false if nothing was
+    /// cached for it.
+    pub fn clear_cache_entry(&self, id: LayerId) -> Result<bool, Error> {
+        remove_cache_entry(self.cache_dir.root(), id)
+    }
+
+    /// Starts generating `sector` of the layer with the given id by running the layer's shader
+    /// over the same sector of each of its inputs. Inputs that are themselves generated must
+    /// already have the sector stored. Once the GPU is done, `finish_generations` or
+    /// `wait_for_generations` store the result in the layer's data file and upload it to its
+    /// sector cache.
+    ///
+    /// Returns `false` without starting anything if the maximum number of generations set by
+    /// `set_max_concurrent_generations` are already in flight.
+    #[allow(unused)]
+    fn generate(
+        &mut self,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+        queue: QueueId,
+        sector: Sector,
+        id: LayerId,
+    ) -> Result<bool, Error> {
+        self.finish_generations(factory, queue)?;
+        if !self.generations.try_acquire() {
+            return Ok(false);
+        }
+
+        let inputs = self.read_inputs(id, sector)?;
+        self.submit_generation(factory, families, queue, sector, id, inputs)?;
+        Ok(true)
+    }
+
+    /// Starts generating `sector` of the layer `id` from `inputs`, as returned by `read_inputs`.
+    /// The caller must have checked that a generation may start.
+    fn submit_generation(
+        &mut self,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+        queue: QueueId,
+        sector: Sector,
+        id: LayerId,
+        inputs: Vec<(String, LayerId, Vec<u8>)>,
+    ) -> Result<(), Error> {
+        let input_hash = hash_inputs(&inputs);
+
+        // Make sure the sector of every input is resident in that input's tile cache.
+        let mut bindings = Vec::new();
+        for (slot, input_id, data) in inputs {
+            let binding = if let Some(parent) = self.generated_layers.get_mut(&input_id) {
+                let index = parent.sector_cache.insert(factory, queue, sector, &data)?;
+                (slot, parent.sector_cache.image.clone(), index)
+            } else {
+                let dataset = self.dataset_layers.get_mut(&input_id).unwrap();
+                let index = dataset.tile_cache.insert(factory, queue, sector, &data)?;
+                (slot, dataset.tile_cache.image.clone(), index)
+            };
+            bindings.push(binding);
+        }
+
+        let layer = &self.generated_layers[&id];
+        let dispatch = unsafe { layer.submit(factory, families, queue, &bindings)? };
+        self.generations.hold(PendingGeneration { id, sector, input_hash, dispatch });
+        Ok(())
+    }
+
+    /// Reads the data each input of the generated layer `id` provides for `sector`, returning the
+    /// slot it binds to, the input's id and the data.
+    fn read_inputs(
+        &mut self,
+        id: LayerId,
+        sector: Sector,
+    ) -> Result<Vec<(String, LayerId, Vec<u8>)>, Error> {
+        let name = self
+            .layer_ids
+            .iter()
+            .find(|&(_, &layer_id)| layer_id == id && self.generated_layers.contains_key(&id))
+            .map(|(name, _)| name.clone())
+            .ok_or(format_err!("no generated layer with id {}", hex::encode(id.0.as_slice())))?;
+        let inputs = match self.config.nodes[&name] {
+            Node::Generated { ref inputs, .. } => inputs.clone(),
+            Node::Dataset { .. } => unreachable!(),
+        };
+
+        let mut data = Vec::new();
+        for (slot, input) in inputs {
+            let input_id = self.layer_ids[&input];
+            let bytes = if let Some(parent) = self.generated_layers.get(&input_id) {
+                parent.read_sector(sector).to_vec()
+            } else {
+                let dataset = self.dataset_layers.get_mut(&input_id).unwrap();
+                let resolution = dataset.desc.resolution;
+                let texel_bytes = dataset.desc.texture_format.bytes_per_pixel() as usize;
+                mosaic_sector(self.center, sector, resolution, texel_bytes, |(lat, long)| {
+                    dataset.get_tile(lat, long).map_err(|e| format_err!("{}", e))
+                })?
+                .ok_or(format_err!("node.{} has no data for sector {:?}", input, sector))?
+            };
+            data.push((slot, input_id, bytes));
+        }
+        Ok(data)
+    }
+
+    /// Generates again every stored sector whose inputs have changed since it was last generated,
+    /// for instance because a dataset tile was replaced with a newer version. Layers are visited in
+    /// dependency order, so changes propagate through intermediate layers. Sectors generated
+    /// before input hashes were recorded are left alone. Returns the regenerated sectors.
+    #[allow(unused)]
+    pub fn regenerate_dirty(
+        &mut self,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+        queue: QueueId,
+    ) -> Result<Vec<(LayerId, Sector)>, Error> {
+        let mut regenerated = Vec::new();
+        // Layers with generations stored below whose input hashes haven't been saved yet.
+        let mut unsaved = HashSet::new();
+        for id in self.order.clone() {
+            let mut recorded: Vec<(u64, String)> = match self.generated_layers.get(&id) {
+                Some(layer) => layer.input_hashes.1.iter().map(|(&i, h)| (i, h.clone())).collect(),
+                None => continue,
+            };
+            recorded.sort();
+
+            for (index, hash) in recorded {
+                let sector = sector_from_index(index);
+                let inputs = self.read_inputs(id, sector)?;
+                if hash_inputs(&inputs) == hash {
+                    continue;
+                }
+
+                while !self.generations.try_acquire() {
+                    let oldest = self.generations.take_oldest().unwrap();
+                    unsaved.insert(oldest.id);
+                    self.store_generation(factory, queue, oldest)?;
+                }
+                self.submit_generation(factory, families, queue, sector, id, inputs)?;
+                regenerated.push((id, sector));
+            }
+
+            // Layers later in the order may read the sectors generated for this one.
+            self.wait_for_generations(factory, queue)?;
+        }
+        for id in unsaved {
+            self.generated_layers[&id].save_input_hashes()?;
+        }
+        Ok(regenerated)
+    }
+}
+
+/// Hex encoded SHA-256 over the slot names and contents of a sector's inputs.
+fn hash_inputs(inputs: &[(String, LayerId, Vec<u8>)]) -> String {
+    let mut hasher = Sha256::new();
+    for (slot, _, data) in inputs {
+        hasher.update(&(slot.len() as u64).to_le_bytes());
+        hasher.update(slot.as_bytes());
+        hasher.update(&(data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    }
+    hex::encode(hasher.finalize().as_slice())
+}
+
+/// Index of `sector` within a layer's data file. Sectors are stored in rings of increasing
+/// distance from the origin so that the map can grow without reordering existing data.
+fn sector_index(sector: Sector) -> u64 {
+    let ax = if sector.0 >= 0 {
+        sector.0
+    } else {
+        -sector.0 - 1
+    } as u64;
+    let ay = if sector.1 >= 0 {
+        sector.1
+    } else {
+        -sector.1 - 1
+    } as u64;
+    let q = match (sector.0 >= 0, sector.1 >= 0) {
+        (true, true) => 0,
+        (false, true) => 1,
+        (true, false) => 2,
+        (false, false) => 3,
+    };
+    if ax > ay {
+        (ax * ax + 2 * ay + 1) * 4 + q
+    } else {
+        (ay * ay + 2 * ax) * 4 + q
+    }
+}
+
+/// Inverse of `sector_index`.
+fn sector_from_index(index: u64) -> Sector {
+    let (n, q) = (index / 4, index % 4);
+
+    // Each quadrant is filled in shells of increasing `max(ax, ay)`, with shell `m` occupying
+    // indices `m^2..(m+1)^2`. Even offsets within a shell lie on the row `ay == m` and odd offsets
+    // on the column `ax == m`.
+    let mut m = (n as f64).sqrt() as u64;
+    while m * m > n {
+        m -= 1;
+    }
+    while (m + 1) * (m + 1) <= n {
+        m += 1;
+    }
+    let offset = n - m * m;
+    let (ax, ay) = if offset % 2 == 0 { (offset / 2, m) } else { (m, (offset - 1) / 2) };
+
+    let x = if q & 1 == 0 { ax as i32 } else { -(ax as i32) - 1 };
+    let y = if q & 2 == 0 { ay as i32 } else { -(ay as i32) - 1 };
+    Sector(x, y)
+}
+
+/// Halves the resolution of a `resolution` x `resolution` sector stored as `format` by averaging
+/// each 2x2 block of texels. A trailing odd row or column is averaged with itself. Colors marked
+/// `srgb` are averaged in linear space.
+fn downsample_sector(data: &[u8], resolution: usize, format: TextureFormat, srgb: bool) -> Vec<u8> {
+    let half = (resolution + 1) / 2;
+    let bytes_per_pixel = format.bytes_per_pixel() as usize;
+    let mut output = Vec::with_capacity(half * half * bytes_per_pixel);
+    for y in 0..half {
+        for x in 0..half {
+            let texels = [
+                (2 * x, 2 * y),
+                ((2 * x + 1).min(resolution - 1), 2 * y),
+                (2 * x, (2 * y + 1).min(resolution - 1)),
+                ((2 * x + 1).min(resolution - 1), (2 * y + 1).min(resolution - 1)),
+            ];
+            let texels = texels.iter().map(|&(x, y)| {
+                let start = (x + y * resolution) * bytes_per_pixel;
+                &data[start..start + bytes_per_pixel]
+            });
+            let texels: Vec<&[u8]> = texels.collect();
+            average_texels(&texels, format, srgb, &mut output);
+        }
+    }
+    output
+}
+
+/// Appends the average of `texels`, each stored as `format`, to `output`.
+fn average_texels(texels: &[&[u8]], format: TextureFormat, srgb: bool, output: &mut Vec<u8>) {
+    let n = texels.len() as f32;
+    match format {
+        TextureFormat::R32F => {
+            let sum: f32 =
+                texels.iter().map(|t| f32::from_le_bytes([t[0], t[1], t[2], t[3]])).sum();
+            output.extend_from_slice(&(sum / n).to_le_bytes());
+        }
+        TextureFormat::Rgba8 => {
+            for channel in 0..4 {
+                // Alpha is never sRGB encoded.
+                let linear = srgb && channel < 3;
+                let sum: f32 = texels
+                    .iter()
+                    .map(|t| t[channel] as f32 / 255.0)
+                    .map(|v| if linear { srgb_to_linear(v) } else { v })
+                    .sum();
+                let v = if linear { linear_to_srgb(sum / n) } else { sum / n };
+                output.push((v * 255.0).round() as u8);
+            }
+        }
+        TextureFormat::R16U => {
+            let sum: u32 = texels.iter().map(|t| u16::from_le_bytes([t[0], t[1]]) as u32).sum();
+            output.extend_from_slice(&((sum as f32 / n).round() as u16).to_le_bytes());
+        }
+        TextureFormat::R16S => {
+            let sum: i32 = texels.iter().map(|t| i16::from_le_bytes([t[0], t[1]]) as i32).sum();
+            output.extend_from_slice(&((sum as f32 / n).round() as i16).to_le_bytes());
+        }
+        TextureFormat::Rg16F => {
+            for channel in 0..2 {
+                let i = channel * 2;
+                let sum: f32 =
+                    texels.iter().map(|t| f16_to_f32(u16::from_le_bytes([t[i], t[i + 1]]))).sum();
+                output.extend_from_slice(&f32_to_f16(sum / n).to_le_bytes());
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts the bits of an IEEE 754 half precision float to an `f32`.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Converts an `f32` to the bits of the nearest IEEE 754 half precision float, saturating to
+/// infinity.
+fn f32_to_f16(value: f32) -> u16 {
+    let sign = if value.is_sign_negative() { 0x8000 } else { 0 };
+    let value = value.abs();
+    if value.is_nan() {
+        return 0x7e00;
+    }
+    if value < 2f32.powi(-14) {
+        // Subnormal, or zero.
+        return sign | (value / 2f32.powi(-24)).round() as u16;
+    }
+    let exponent = value.log2().floor() as i32;
+    let mut mantissa = ((value / 2f32.powi(exponent) - 1.0) * 1024.0).round() as u16;
+    let mut exponent = exponent + 15;
+    if mantissa == 1024 {
+        mantissa = 0;
+        exponent += 1;
+    }
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    sign | (exponent as u16) << 10 | mantissa
+}
+
+/// Assembles the data a dataset provides for `sector` out of the one degree dataset tiles it
+/// overlaps. Sectors are aligned to the map center rather than to whole degrees, so a sector
+/// usually straddles four tiles; each texel is copied from the nearest texel of the tile
+/// containing it. Tiles are fetched with `get_tile`, which is given the latitude and longitude of
+/// their south-west corner. Returns `None` if any overlapping tile is unavailable.
+fn mosaic_sector(
+    center: (f64, f64),
+    sector: Sector,
+    resolution: u32,
+    texel_bytes: usize,
+    mut get_tile: impl FnMut((i16, i16)) -> Result<Option<Vec<u8>>, Error>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let resolution = resolution as usize;
+    let north = center.1 - sector.1 as f64 * SECTOR_SIZE_DEGREES;
+    let west = center.0 + sector.0 as f64 * SECTOR_SIZE_DEGREES;
+    let texel_size = SECTOR_SIZE_DEGREES / resolution as f64;
+
+    let mut tiles: HashMap<(i16, i16), Vec<u8>> = HashMap::new();
+    let mut data = vec![0; resolution * resolution * texel_bytes];
+    for y in 0..resolution {
+        let latitude = north - (y as f64 + 0.5) * texel_size;
+        for x in 0..resolution {
+            let longitude = west + (x as f64 + 0.5) * texel_size;
+            let tile = (latitude.floor() as i16, longitude.floor() as i16);
+            if !tiles.contains_key(&tile) {
+                let contents = match get_tile(tile)? {
+                    Some(contents) => contents,
+                    None => return Ok(None),
+                };
+                ensure!(
+                    contents.len() == data.len(),
+                    "dataset tile {:?} has {} bytes, expected {}",
+                    tile,
+                    contents.len(),
+                    data.len()
+                );
+                tiles.insert(tile, contents);
+            }
+
+            // Rows of a tile run from north to south, like those of a sector.
+            let row = ((tile.0 as f64 + 1.0 - latitude) * resolution as f64) as usize;
+            let column = ((longitude - tile.1 as f64) * resolution as f64) as usize;
+            let src = (row.min(resolution - 1) * resolution + column.min(resolution - 1))
+                * texel_bytes;
+            let dst = (y * resolution + x) * texel_bytes;
+            data[dst..][..texel_bytes].copy_from_slice(&tiles[&tile][src..][..texel_bytes]);
+        }
+    }
+    Ok(Some(data))
+}
+
+/// Returns the sector containing the given point on a map centered at `center` (longitude,
+/// latitude). Sector x coordinates increase to the east and y coordinates to the south, matching
+/// the layout used by `write_layer_geotiff`.
+fn sector_for_lat_lon(center: (f64, f64), latitude: f64, longitude: f64) -> Sector {
+    Sector(
+        ((longitude - center.0) / SECTOR_SIZE_DEGREES).floor() as i32,
+        ((center.1 - latitude) / SECTOR_SIZE_DEGREES).floor() as i32,
+    )
+}
+
+/// Sectors overlapping a rectangle, found by taking the grid between the sectors of its north-west
+/// and south-east corners.
+fn sectors_in_rect(
+    center: (f64, f64),
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+) -> impl Iterator<Item = Sector> {
+    let nw = sector_for_lat_lon(center, max_lat, min_lon);
+    let se = sector_for_lat_lon(center, min_lat, max_lon);
+
+    let mut sectors: Vec<_> =
+        (nw.1..=se.1).flat_map(|y| (nw.0..=se.0).map(move |x| Sector(x, y))).collect();
+    sectors.sort_by_key(|&sector| sector_index(sector));
+    sectors.into_iter()
+}
+
+/// Writes `bytes` as the contents of `sector` within a layer's `data`. With deduplication, nothing
+/// is written if an identical sector was already stored; a reference to it is recorded instead.
+/// Returns whether the bytes were written to `data`.
+fn write_sector(
+    data: &mut [u8],
+    sector_bytes: u64,
+    dedup: Option<&mut SectorDedup>,
+    sector: Sector,
+    bytes: &[u8],
+) -> Result<bool, Error> {
+    ensure!(bytes.len() as u64 == sector_bytes, "sector data has wrong size");
+    let index = sector_index(sector);
+    if let Some(dedup) = dedup {
+        ensure!(
+            !dedup.references.values().any(|&s| s == sector),
+            "sector ({}, {}) is referenced by other sectors and can't be overwritten",
+            sector.0,
+            sector.1
+        );
+        let hash = hex::encode(Sha256::digest(bytes).as_slice());
+        match dedup.hashes.get(&hash) {
+            Some(&existing) if existing != sector => {
+                dedup.references.insert(index, existing);
+                return Ok(false);
+            }
+            _ => {
+                dedup.hashes.retain(|_, s| *s != sector);
+                dedup.hashes.insert(hash, sector);
+                dedup.references.remove(&index);
+            }
+        }
+    }
+
+    let offset = (index * sector_bytes) as usize;
+    data.get_mut(offset..offset + sector_bytes as usize)
+        .ok_or(format_err!("Sector ({}, {}) is outside of layer data", sector.0, sector.1))?
+        .copy_from_slice(bytes);
+    Ok(true)
+}
+
+/// Returns the contents of `sector` within a layer's `data`, following deduplication references.
+fn read_sector<'a>(
+    data: &'a [u8],
+    sector_bytes: u64,
+    dedup: Option<&SectorDedup>,
+    sector: Sector,
+) -> &'a [u8] {
+    let sector = dedup.map(|d| d.resolve(sector)).unwrap_or(sector);
+    let offset = (sector_index(sector) * sector_bytes) as usize;
+    &data[offset..offset + sector_bytes as usize]
+}
+
+/// Assembles the sectors of a layer into a single image and writes it as a GeoTIFF. Sectors run
+/// from `-side_length_sectors/2` on each axis, with x increasing to the east and y increasing to
+/// the south.
+fn write_layer_geotiff<W: Write>(
+    writer: &mut W,
+    desc: &LayerDesc,
+    data: &[u8],
+    dedup: Option<&SectorDedup>,
+    side_length_sectors: u16,
+    center: (f64, f64),
+) -> Result<(), Error> {
+    let half = side_length_sectors as i32 / 2;
+    let resolution = desc.resolution as usize;
+    let bytes_per_pixel = desc.format.bytes_per_pixel() as usize;
+    let row_bytes = resolution * bytes_per_pixel;
+    let side_length = side_length_sectors as usize * resolution;
+
+    let mut image = vec![0u8; side_length * side_length * bytes_per_pixel];
+    for (i, y) in (-half..(side_length_sectors as i32 - half)).enumerate() {
+        for (j, x) in (-half..(side_length_sectors as i32 - half)).enumerate() {
+            let sector = dedup.map(|d| d.resolve(Sector(x, y))).unwrap_or(Sector(x, y));
+            let offset = (desc.sector_bytes * sector_index(sector)) as usize;
+            let sector = data
+                .get(offset..offset + desc.sector_bytes as usize)
+                .ok_or(format_err!("Sector ({}, {}) is outside of layer data", x, y))?;
+            for (k, row) in sector.chunks_exact(row_bytes).enumerate() {
+                let start = ((i * resolution + k) * side_length + j * resolution) * bytes_per_pixel;
+                image[start..start + row_bytes].copy_from_slice(row);
+            }
+        }
+    }
+
+    let georeference = GeoReference {
+        origin: (
+            center.0 - half as f64 * SECTOR_SIZE_DEGREES,
+            center.1 + half as f64 * SECTOR_SIZE_DEGREES,
+        ),
+        pixel_size: SECTOR_SIZE_DEGREES / desc.resolution as f64,
+        point_registration: desc.corner_registration,
+    };
+    geotiff::write_tiled(
+        writer,
+        side_length as u32,
+        side_length as u32,
+        desc.format,
+        &georeference,
+        &image,
+    )
+}
+
+/// Total size of a file, or of all files inside a directory.
+fn disk_usage(path: &Path) -> Result<u64, Error> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut bytes = 0;
+    for entry in fs::read_dir(path)? {
+        bytes += disk_usage(&entry?.path())?;
+    }
+    Ok(bytes)
+}
+
+fn parse_layer_id(s: &str) -> Option<LayerId> {
+    let bytes = hex::decode(s).ok()?;
+    if bytes.len() != <Sha256 as Digest>::output_size() {
+        return None;
+    }
+    Some(LayerId(GenericArray::clone_from_slice(&bytes)))
+}
+
+/// Finds the cache entries under `cache_dir`, which follows the layout used by `Graph::from_file`:
+/// `datasets/<id>/` directories and `generated/<id>.{header,data,spv}` files.
+fn scan_cache(cache_dir: &Path) -> Result<Vec<CacheEntry>, Error> {
+    let mut entries = BTreeMap::new();
+
+    let datasets = cache_dir.join("datasets");
+    if datasets.is_dir() {
+        for entry in fs::read_dir(&datasets)? {
+            let path = entry?.path();
+            let id = path.file_name().and_then(|n| n.to_str()).and_then(parse_layer_id);
+            if let Some(id) = id {
+                let bytes = disk_usage(&path)?;
+                entries.insert(id, CacheEntry { kind: CacheEntryKind::Dataset, id, bytes });
+            }
+        }
+    }
+
+    let generated = cache_dir.join("generated");
+    if generated.is_dir() {
+        for entry in fs::read_dir(&generated)? {
+            let path = entry?.path();
+            let id = path.file_stem().and_then(|n| n.to_str()).and_then(parse_layer_id);
+            if let Some(id) = id {
+                let bytes = disk_usage(&path)?;
+                entries
+                    .entry(id)
+                    .or_insert(CacheEntry { kind: CacheEntryKind::Generated, id, bytes: 0 })
+                    .bytes += bytes;
+            }
+        }
+    }
+
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Magic number at the start of every SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Reads a SPIR-V module written by `Graph::from_file`. Returns None if the file is missing or
+/// doesn't contain a module, in which case the shader should be recompiled.
+fn read_cached_spirv(path: &Path) -> Option<Vec<u32>> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    let words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect();
+    if words.first() != Some(&SPIRV_MAGIC) {
+        return None;
+    }
+    Some(words)
+}
+
+fn spirv_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect()
+}
+
+/// Writes `contents` to a temporary file next to `path` and then renames it into place, so that
+/// readers never see a partially written file even if the process dies midway.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// Deletes every file under `cache_dir` belonging to `id`.
+fn remove_cache_entry(cache_dir: &Path, id: LayerId) -> Result<bool, Error> {
+    let hash = hex::encode(id.0.as_slice());
+    let mut removed = false;
+
+    let dataset = cache_dir.join("datasets").join(&hash);
+    if dataset.is_dir() {
+        fs::remove_dir_all(dataset)?;
+        removed = true;
+    }
+    for extension in &["header", "data", "spv", "refs", "inputs"] {
+        let file = cache_dir.join("generated").join(format!("{}.{}", hash, extension));
+        if file.is_file() {
+            fs::remove_file(file)?;
+            removed = true;
+        }
+    }
+    Ok(removed)
+}
+
+/// Combines several partial graph descriptions into one.
+fn merge_graph_files(fragments: Vec<GraphFileFragment>) -> Result<GraphFile, Error> {
+    let mut center: Option<String> = None;
+    let mut side_length_sectors: Option<u16> = None;
+    let mut seed: Option<u64> = None;
+    let mut nodes = BTreeMap::new();
+    let mut shaders = BTreeMap::new();
+    for fragment in fragments {
+        if let Some(c) = fragment.center {
+            match center {
+                Some(ref existing) if *existing != c => {
+                    bail!("conflicting centers '{}' and '{}'", existing, c)
+                }
+                _ => center = Some(c),
+            }
+        }
+        if let Some(s) = fragment.side_length_sectors {
+            match side_length_sectors {
+                Some(existing) if existing != s => {
+                    bail!("conflicting side_length_sectors {} and {}", existing, s)
+                }
+                _ => side_length_sectors = Some(s),
+            }
+        }
+        if let Some(s) = fragment.seed {
+            match seed {
+                Some(existing) if existing != s => {
+                    bail!("conflicting seeds {} and {}", existing, s)
+                }
+                _ => seed = Some(s),
+            }
+        }
+        for (name, node) in fragment.nodes {
+            if nodes.insert(name.clone(), node).is_some() {
+                bail!("node.{} defined more than once", name);
+            }
+        }
+        for (name, shader) in fragment.shaders {
+            if shaders.insert(name.clone(), shader).is_some() {
+                bail!("shader '{}' defined more than once", name);
+            }
+        }
+    }
+
+    Ok(GraphFile {
+        center: center.ok_or(format_err!("missing center"))?,
+        side_length_sectors: side_length_sectors
+            .ok_or(format_err!("missing side_length_sectors"))?,
+        seed: seed.unwrap_or(0),
+        nodes,
+        shaders,
+    })
+}
+
+/// Computes an ordering of the nodes in `config` such that every node comes after all of its
+/// inputs. Nodes that could go in either order are sorted by name so the result is deterministic.
+fn compute_order(config: &GraphFile) -> Result<Vec<String>, Error> {
+    let mut ids = HashMap::new();
+    let mut g = DiGraph::new();
+    for name in config.nodes.keys() {
+        ids.insert(name.to_owned(), g.add_node(name));
+    }
+    for (name, node) in config.nodes.iter() {
+        if let description::Node::Generated { ref inputs, .. } = node {
+            let child = ids[name];
+            for parent in inputs.values() {
+                match ids.get(parent) {
+                    Some(p) => g.add_edge(p.clone(), child, ()),
+                    None => bail!("node.{} not found", parent),
+                };
+            }
+        }
+    }
+    if let Err(cycle) = algo::toposort(&g, None) {
+        bail!("cycle detected involving node '{}'", g[cycle.node_id()]);
+    }
+
+    let mut in_degrees: HashMap<_, _> = g
+        .node_indices()
+        .map(|id| (id, g.neighbors_directed(id, Direction::Incoming).count()))
+        .collect();
+    let mut ready: BTreeSet<_> = in_degrees
+        .iter()
+        .filter(|&(_, &degree)| degree == 0)
+        .map(|(&id, _)| (g[id], id))
+        .collect();
+
+    let mut order = Vec::with_capacity(g.node_count());
+    while let Some(&(name, id)) = ready.iter().next() {
+        ready.remove(&(name, id));
+        order.push(name.to_string());
+        for child in g.neighbors_directed(id, Direction::Outgoing) {
+            let degree = in_degrees.get_mut(&child).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.insert((g[child], child));
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// Parses `config_string` and runs the checks `Graph::from_config` does before it creates any
+/// resources.
+fn validate_config(config_string: &str) -> Result<(), Error> {
+    let config: GraphFile = toml::from_str(config_string)?;
+    open_location_code::decode(&config.center).map_err(|e| format_err!("{}", e))?;
+    let order = compute_order(&config)?;
+    check_resolutions(&config)?;
+    check_samplers(&config)?;
+    describe_layers(&config, &order)?;
+    Ok(())
+}
+
+/// Checks that every node has a nonzero resolution and cache size, and that a single sector of
+/// each generated layer fits in a `u32` number of bytes.
+fn check_resolutions(config: &GraphFile) -> Result<(), Error> {
+    for (name, node) in &config.nodes {
+        let (resolution, cache_size) = match node {
+            Node::Generated { resolution, cache_size, .. }
+            | Node::Dataset { resolution, cache_size, .. } => (*resolution, *cache_size),
+        };
+        ensure!(resolution > 0, "node.{} has zero resolution", name);
+        ensure!(cache_size > 0, "node.{} has zero cache_size", name);
+        if let Node::Generated { format, .. } = node {
+            ensure!(
+                resolution
+                    .checked_mul(resolution)
+                    .and_then(|texels| texels.checked_mul(format.bytes_per_pixel()))
+                    .is_some(),
+                "node.{} is too large: {}x{} texels of {:?}",
+                name,
+                resolution,
+                resolution,
+                format
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the sampler of every generated node asks for a supported amount of anisotropic
+/// filtering.
+fn check_samplers(config: &GraphFile) -> Result<(), Error> {
+    for (name, node) in &config.nodes {
+        if let Node::Generated { sampler, .. } = node {
+            ensure!(
+                sampler.max_anisotropy >= 1 && sampler.max_anisotropy <= 16,
+                "node.{} has max_anisotropy {}, which must be between 1 and 16",
+                name,
+                sampler.max_anisotropy
             );
         }
+    }
+    Ok(())
+}
+
+/// Computes the length of the longest chain of inputs leading to each node. `order` must be
+/// topologically sorted, as returned by `compute_order`.
+fn compute_depths(config: &GraphFile, order: &[String]) -> HashMap<String, usize> {
+    let mut depths: HashMap<String, usize> = HashMap::new();
+    for name in order {
+        let depth = match config.nodes[name] {
+            Node::Generated { ref inputs, .. } => {
+                inputs.values().map(|input| depths[input] + 1).max().unwrap_or(0)
+            }
+            _ => 0,
+        };
+        depths.insert(name.clone(), depth);
+    }
+    depths
+}
+
+/// Groups the renderable layers by type. Intermediate layers don't get a group of their own, and
+/// are instead scheduled just ahead of the first renderable layer that needs them.
+/// The graph description after its shaders are replaced, along with the generated layers that
+/// have to be rebuilt as a result.
+struct ShaderRefresh {
+    config: GraphFile,
+    order: Vec<String>,
+    layer_ids: HashMap<String, LayerId>,
+    /// Generated layers whose id changed, with their new descriptions.
+    rebuild: Vec<(String, LayerDesc)>,
+}
+
+/// Works out which layers of the graph described by `config`, whose layers currently have the ids
+/// in `layer_ids`, have to be rebuilt if its shaders are replaced with `shaders`.
+fn plan_shader_refresh(
+    config: &GraphFile,
+    layer_ids: &HashMap<String, LayerId>,
+    shaders: BTreeMap<String, String>,
+) -> Result<ShaderRefresh, Error> {
+    let mut config = config.clone();
+    config.shaders = shaders;
+    let order = compute_order(&config)?;
+    let (new_ids, _, layer_descriptors) = describe_layers(&config, &order)?;
+    let rebuild = layer_descriptors
+        .into_iter()
+        .filter(|(name, _)| new_ids[name] != layer_ids[name])
+        .collect();
+    Ok(ShaderRefresh { config, order, layer_ids: new_ids, rebuild })
+}
+
+fn compute_priorities(config: &GraphFile, order: &[String]) -> HashMap<LayerType, Vec<String>> {
+    let mut priorities: HashMap<LayerType, Vec<String>> = HashMap::new();
+    for name in order.iter().rev() {
+        let ty = match config.nodes[name] {
+            Node::Generated {
+                kind: OutputKind::HeightMap,
+                ..
+            } => LayerType::Heightmap,
+            Node::Generated {
+                kind: OutputKind::NormalMap,
+                ..
+            } => LayerType::Heightmap,
+            Node::Generated {
+                kind: OutputKind::AlbedoMap,
+                ..
+            } => LayerType::Albedo,
+            _ => continue,
+        };
+
+        let bucket = priorities.entry(ty).or_insert(vec![]);
+        for intermediate in intermediate_ancestors(config, order, name) {
+            if !bucket.contains(&intermediate) {
+                bucket.push(intermediate);
+            }
+        }
+        bucket.push(name.to_owned());
+    }
+    priorities
+}
 
-        Ok(Graph {
-            config,
-            xdg_dirs,
-            order: order.iter().map(|name| layer_ids[name]).collect(),
-            layer_ids,
-            priorities,
-            generated_layers,
-            dataset_layers,
+/// Returns the intermediate layers that `name` depends on (either directly or through other
+/// intermediate layers) in topological order.
+fn intermediate_ancestors(config: &GraphFile, order: &[String], name: &str) -> Vec<String> {
+    let mut ancestors = HashSet::new();
+    let mut pending = vec![name];
+    while let Some(node) = pending.pop() {
+        if let Node::Generated { ref inputs, .. } = config.nodes[node] {
+            for input in inputs.values() {
+                if let Node::Generated { kind: OutputKind::F32, .. } = config.nodes[input] {
+                    if ancestors.insert(&input[..]) {
+                        pending.push(input);
+                    }
+                }
+            }
+        }
+    }
+
+    order.iter().filter(|n| ancestors.contains(&n[..])).cloned().collect()
+}
+
+/// Checks that the images bound by a node's shader line up with the node's inputs: every input
+/// slot must be bound by name, the result must be written to an image called `output`, and no
+/// other images may be referenced.
+fn validate_shader_bindings(
+    name: &str,
+    images: &[(u32, String)],
+    inputs: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    for (_, image) in images {
+        if image != "output" && !inputs.contains_key(image) {
+            bail!("shader for node '{}' binds image '{}' that isn't an input", name, image);
+        }
+    }
+    for slot in inputs.keys() {
+        if !images.iter().any(|(_, image)| image == slot) {
+            bail!("node '{}' declares input '{}' but its shader never binds it", name, slot);
+        }
+    }
+    if !images.iter().any(|(_, image)| image == "output") {
+        bail!("shader for node '{}' has no 'output' image", name);
+    }
+    Ok(())
+}
+
+/// GLSL expression for the `SEED` macro: a `uvec2` of the low and high 32 bits of `seed`.
+fn seed_define(seed: u64) -> String {
+    format!("uvec2({}u, {}u)", seed as u32, (seed >> 32) as u32)
+}
+
+/// Names and licenses of the dataset nodes in `config`, skipping those without a license.
+fn dataset_attributions(config: &GraphFile) -> Vec<(String, String)> {
+    config
+        .nodes
+        .iter()
+        .filter_map(|(name, node)| match node {
+            Node::Dataset { license: Some(license), .. } => Some((name.clone(), license.clone())),
+            _ => None,
         })
+        .collect()
+}
+
+/// Bibliography entries of the dataset nodes in `config`, with duplicates removed.
+fn dataset_bibliography(config: &GraphFile) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    for node in config.nodes.values() {
+        if let Node::Dataset { bib: Some(bib), .. } = node {
+            if !entries.contains(bib) {
+                entries.push(bib.clone());
+            }
+        }
     }
+    entries
+}
+
+/// Preprocessor definitions passed to the shader of node `name`. `OUTPUT_SRGB` and `<INPUT>_SRGB`
+/// (for each input slot, upper cased) are 1 if the corresponding layer holds sRGB encoded colors
+/// and 0 otherwise. Images are always bound with a UNORM format so that they can be used for
+/// storage, which leaves any conversion to linear up to the shader.
+///
+/// `<INPUT>_SCALE` is the number of texels of the input that cover one texel of the output along
+/// each axis. Inputs are bound at their own resolution, so shaders reading an input with a scale
+/// above 1.0 should filter over that many texels rather than point sample to avoid aliasing.
+fn shader_defines(config: &GraphFile, name: &str) -> Vec<(String, String)> {
+    let is_srgb = |node: &str| match config.nodes[node] {
+        Node::Generated { srgb, .. } => srgb,
+        Node::Dataset { .. } => false,
+    };
+    // Sectors and dataset tiles both span SECTOR_SIZE_DEGREES, so resolutions compare directly.
+    let resolution = |node: &str| match config.nodes[node] {
+        Node::Generated { resolution, .. } | Node::Dataset { resolution, .. } => resolution,
+    };
+
+    let mut defines = vec![("OUTPUT_SRGB".to_owned(), (is_srgb(name) as u8).to_string())];
+    if let Node::Generated { ref inputs, .. } = config.nodes[name] {
+        for (slot, input) in inputs {
+            let value = (is_srgb(input) as u8).to_string();
+            defines.push((format!("{}_SRGB", slot.to_uppercase()), value));
+            let scale = resolution(input) as f64 / resolution(name) as f64;
+            defines.push((format!("{}_SCALE", slot.to_uppercase()), format!("{:?}", scale)));
+        }
+    }
+    defines
+}
 
-    fn generate(&mut self, _sector: Sector, _id: LayerId) {
+/// Computes the id of every node in the graph along with the descriptions the ids are derived
+/// from. Ids are content hashes, so a node's id changes whenever it or any of its inputs do.
+#[allow(clippy::type_complexity)]
+fn describe_layers(
+    config: &GraphFile,
+    order: &[String],
+) -> Result<
+    (HashMap<String, LayerId>, HashMap<String, DatasetDesc>, BTreeMap<String, LayerDesc>),
+    Error,
+> {
+    let mut layer_ids = HashMap::new();
+    let mut dataset_descs = HashMap::new();
+    let mut layer_descriptors = BTreeMap::new();
+    for name in order {
+        match &config.nodes[name] {
+            Node::Dataset { url, resolution, format, texture_format, projection, .. } => {
+                let texture_format = texture_format.unwrap_or(format.default_texture_format());
+                ensure!(
+                    format.supports(texture_format),
+                    "node.{} can't decode {:?} tiles as {:?}",
+                    name,
+                    format,
+                    texture_format
+                );
+                let desc = DatasetDesc {
+                    url: url.to_owned(),
+                    credentials: None,
+                    projection: *projection,
+                    resolution: *resolution,
+                    file_format: *format,
+                    texture_format,
+                };
+                let desc_bytes = bincode::serialize(&desc)?;
+                layer_ids.insert(name.to_owned(), LayerId(Sha256::digest(&desc_bytes)));
+                dataset_descs.insert(name.to_owned(), desc);
+            }
+            Node::Generated {
+                ref inputs, resolution, corner_registration, format, srgb, ref shader, ..
+            } => {
+                let desc = LayerDesc {
+                    parents: {
+                        let mut parents = BTreeMap::new();
+                        for input in inputs.values() {
+                            let id = layer_ids[input];
+                            parents.insert(input.to_owned(), id);
+                        }
+                        parents
+                    },
+                    resolution: *resolution,
+                    corner_registration: *corner_registration,
+                    format: *format,
+                    srgb: *srgb,
+                    sector_bytes: (resolution * resolution * format.bytes_per_pixel()) as u64,
+                    shader: config
+                        .shaders
+                        .get(shader)
+                        .ok_or(format_err!("Missing shader '{}'", shader))?
+                        .to_owned(),
+                    center: config.center.clone(),
+                    seed: config.seed,
+                    local_size: GENERATE_LOCAL_SIZE,
+                };
+                let desc_bytes = bincode::serialize(&desc)?;
+                layer_ids.insert(name.to_owned(), LayerId(Sha256::digest(&desc_bytes)));
+                layer_descriptors.insert(name.to_owned(), desc);
+            }
+        };
     }
+    Ok((layer_ids, dataset_descs, layer_descriptors))
 }
 
 #[cfg(test)]
 mod test {
+    use super::description::DatasetFormat;
     use super::*;
 
     #[test]
@@ -470,4 +2595,881 @@ mod test {
         assert_eq!(Layer::compute_sector_index(Sector(-3, -1)), 5 * 4 + 3);
         assert_eq!(Layer::compute_sector_index(Sector(-3, -4)), 13 * 4 + 3);
     }
+
+    #[test]
+    fn downsample() {
+        let heights: Vec<u8> =
+            [1.0f32, 3.0, 5.0, 7.0, 5.0, 7.0, 9.0, 11.0, 0.0, 0.0, 2.0, 2.0, 0.0, 0.0, 2.0, 2.0]
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect();
+        let half = downsample_sector(&heights, 4, TextureFormat::R32F, false);
+        let half: Vec<f32> =
+            half.chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+        assert_eq!(half, vec![4.0, 8.0, 0.0, 2.0]);
+
+        // Odd resolutions keep their last row and column.
+        let heights: Vec<u8> = (0..9).flat_map(|i| (i as i16 * 4).to_le_bytes().to_vec()).collect();
+        let half = downsample_sector(&heights, 3, TextureFormat::R16S, false);
+        let half: Vec<i16> = half.chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert_eq!(half, vec![8, 14, 26, 32]);
+
+        // Black and white average to mid grey in linear space, which is lighter once encoded.
+        let albedo = [0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255];
+        let grey = downsample_sector(&albedo, 2, TextureFormat::Rgba8, false);
+        assert_eq!(grey, [128, 128, 128, 255]);
+        let grey = downsample_sector(&albedo, 2, TextureFormat::Rgba8, true);
+        assert_eq!(grey, [188, 188, 188, 255]);
+
+        for &v in &[0.0f32, 1.0, -2.5, 0.333, 65504.0, 1e-4] {
+            let round_trip = f16_to_f32(f32_to_f16(v));
+            assert!((round_trip - v).abs() <= v.abs() / 1024.0, "{} became {}", v, round_trip);
+        }
+    }
+
+    #[test]
+    fn sector_from_index() {
+        for x in -20..20 {
+            for y in -20..20 {
+                let index = Layer::compute_sector_index(Sector(x, y));
+                assert_eq!(Layer::sector_from_index(index), Sector(x, y));
+            }
+        }
+        for index in 0..1000 {
+            assert_eq!(Layer::compute_sector_index(Layer::sector_from_index(index)), index);
+        }
+    }
+
+    const INTERMEDIATE_CONFIG: &str = r#"
+        center = "87JC9W00+"
+        side_length_sectors = 8
+
+        [nodes.dem]
+        url = "https://example.com/{ns}{lat02}{ew}{long03}.zip"
+        projection = "NAD83"
+        resolution = 64
+        format = "GridFloat+zip"
+        cache_size = 4
+
+        [nodes.slope]
+        shader = "slope"
+        resolution = 64
+        kind = "f32"
+        format = "R32F"
+        inputs = { heights = "dem" }
+        cache_size = 4
+
+        [nodes.albedo]
+        shader = "albedo"
+        resolution = 64
+        kind = "albedomap"
+        format = "Rgba8"
+        inputs = { slope = "slope" }
+        cache_size = 4
+
+        [shaders]
+        slope = ""
+        albedo = ""
+    "#;
+
+    /// Builds variations of `INTERMEDIATE_CONFIG` by editing its parsed TOML rather than its text.
+    struct ConfigBuilder(toml::Value);
+    impl ConfigBuilder {
+        fn intermediate() -> Self {
+            ConfigBuilder(toml::from_str(INTERMEDIATE_CONFIG).unwrap())
+        }
+
+        /// Sets `key` of the node called `name` to `value`, which is given as TOML.
+        fn node(mut self, name: &str, key: &str, value: &str) -> Self {
+            let value: toml::Value = toml::from_str(&format!("value = {}", value)).unwrap();
+            let node = self.0["nodes"][name].as_table_mut().unwrap();
+            node.insert(key.to_owned(), value["value"].clone());
+            self
+        }
+
+        fn without_shader(mut self, name: &str) -> Self {
+            self.0["shaders"].as_table_mut().unwrap().remove(name);
+            self
+        }
+
+        fn to_toml(&self) -> String {
+            toml::to_string(&self.0).unwrap()
+        }
+
+        fn build(self) -> GraphFile {
+            self.0.try_into().unwrap()
+        }
+    }
+
+    #[test]
+    fn dataset_texture_format() {
+        let mut config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        let order = compute_order(&config).unwrap();
+        let (_, datasets, _) = describe_layers(&config, &order).unwrap();
+        assert_eq!(datasets["dem"].texture_format, TextureFormat::R32F);
+
+        fn set_dem(config: &mut GraphFile, format: DatasetFormat, texture: Option<TextureFormat>) {
+            match config.nodes.get_mut("dem") {
+                Some(Node::Dataset { format: f, texture_format: t, .. }) => {
+                    *f = format;
+                    *t = texture;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // GridFloat tiles only have a single band.
+        set_dem(&mut config, DatasetFormat::ZippedGridFloat, Some(TextureFormat::Rgba8));
+        assert!(describe_layers(&config, &order).is_err());
+
+        set_dem(&mut config, DatasetFormat::GeoTiff, None);
+        let (_, datasets, _) = describe_layers(&config, &order).unwrap();
+        assert_eq!(datasets["dem"].texture_format, TextureFormat::Rgba8);
+        assert_eq!(datasets["dem"].file_format, DatasetFormat::GeoTiff);
+
+        set_dem(&mut config, DatasetFormat::GeoTiff, Some(TextureFormat::R32F));
+        assert!(describe_layers(&config, &order).is_err());
+    }
+
+    #[test]
+    fn validate_without_gpu() {
+        assert!(validate_config(INTERMEDIATE_CONFIG).is_ok());
+
+        let config = ConfigBuilder::intermediate().without_shader("slope");
+        let error = validate_config(&config.to_toml());
+        assert_eq!(error.unwrap_err().to_string(), "Missing shader 'slope'");
+
+        let config =
+            ConfigBuilder::intermediate().node("slope", "inputs", r#"{ heights = "missing" }"#);
+        let error = validate_config(&config.to_toml());
+        assert_eq!(error.unwrap_err().to_string(), "node.missing not found");
+
+        let config = ConfigBuilder::intermediate().node("dem", "resolution", "0");
+        let error = validate_config(&config.to_toml());
+        assert_eq!(error.unwrap_err().to_string(), "node.dem has zero resolution");
+
+        let huge = ConfigBuilder::intermediate().node("slope", "resolution", "40000");
+        assert!(validate_config(&huge.to_toml()).unwrap_err().to_string().contains("too large"));
+
+        assert!(validate_config("center = \"87JC9W00+\"").is_err());
+    }
+
+    #[test]
+    fn layer_samplers() {
+        let config = ConfigBuilder::intermediate()
+            .node("albedo", "sampler", r#"{ filter = "linear", max_anisotropy = 8 }"#)
+            .build();
+        let sampler = |name: &str| match config.nodes[name] {
+            Node::Generated { sampler, .. } => sampler,
+            _ => unreachable!(),
+        };
+        assert_eq!(sampler("slope"), SamplerDesc::default());
+        assert_eq!(sampler("albedo").max_anisotropy, 8);
+        assert_eq!(sampler("albedo").address_mode, AddressMode::Clamp);
+
+        let info = sampler_info(sampler("albedo"), true);
+        assert_eq!(info.anisotropic, gfx_hal::image::Anisotropic::On(8));
+        assert_eq!(info.min_filter, gfx_hal::image::Filter::Linear);
+        let info = sampler_info(sampler("albedo"), false);
+        assert_eq!(info.anisotropic, gfx_hal::image::Anisotropic::Off);
+        assert_eq!(info.min_filter, gfx_hal::image::Filter::Linear);
+        let nearest = SamplerDesc { filter: FilterMode::Nearest, ..SamplerDesc::default() };
+        let info = sampler_info(nearest, true);
+        assert_eq!(info.mag_filter, gfx_hal::image::Filter::Nearest);
+        assert_eq!(info.anisotropic, gfx_hal::image::Anisotropic::Off);
+
+        // Samplers don't change layer ids.
+        let order = compute_order(&config).unwrap();
+        let plain: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        assert_eq!(
+            describe_layers(&config, &order).unwrap().0,
+            describe_layers(&plain, &order).unwrap().0
+        );
+
+        let invalid =
+            ConfigBuilder::intermediate().node("slope", "sampler", "{ max_anisotropy = 0 }");
+        assert!(validate_config(&invalid.to_toml()).is_err());
+    }
+
+    #[test]
+    fn cycle_detected() {
+        let config: GraphFile = toml::from_str(
+            r#"
+            center = "87JC9W00+"
+            side_length_sectors = 8
+
+            [nodes.a]
+            shader = "a"
+            resolution = 64
+            kind = "f32"
+            format = "R32F"
+            inputs = { input = "b" }
+            cache_size = 4
+
+            [nodes.b]
+            shader = "b"
+            resolution = 64
+            kind = "f32"
+            format = "R32F"
+            inputs = { input = "a" }
+            cache_size = 4
+
+            [shaders]
+            a = ""
+            b = ""
+        "#,
+        )
+        .unwrap();
+
+        let message = compute_order(&config).unwrap_err().to_string();
+        assert!(
+            message == "cycle detected involving node 'a'"
+                || message == "cycle detected involving node 'b'",
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn intermediate_scheduled_before_dependent() {
+        let config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        let order = compute_order(&config).unwrap();
+        let priorities = compute_priorities(&config, &order);
+
+        assert_eq!(priorities[&LayerType::Albedo], vec!["slope".to_owned(), "albedo".to_owned()]);
+        assert!(!priorities.contains_key(&LayerType::Intermediate));
+    }
+
+    #[test]
+    fn display_layer_desc() {
+        let heights = LayerId(Sha256::digest(b"heights"));
+        let desc = LayerDesc {
+            parents: vec![("base_heights".to_owned(), heights)].into_iter().collect(),
+            resolution: 256,
+            corner_registration: false,
+            format: TextureFormat::Rgba8,
+            srgb: false,
+            sector_bytes: 256 * 256 * 4,
+            shader: "#version 450\nvoid main() {}\n".to_owned(),
+            center: "87JC9W00+".to_owned(),
+            seed: 0,
+            local_size: GENERATE_LOCAL_SIZE,
+        };
+
+        let text = desc.to_string();
+        assert!(text.contains("resolution: 256x256"));
+        assert!(text.contains("format: Rgba8"));
+        assert!(text.contains("parents: [base_heights]"));
+    }
+
+    #[test]
+    fn export_geotiff() {
+        let desc = LayerDesc {
+            parents: BTreeMap::new(),
+            resolution: 64,
+            corner_registration: false,
+            format: TextureFormat::R32F,
+            srgb: false,
+            sector_bytes: 64 * 64 * 4,
+            shader: String::new(),
+            center: "87JC9W00+".to_owned(),
+            seed: 0,
+            local_size: GENERATE_LOCAL_SIZE,
+        };
+        let data = vec![0u8; 4 * desc.sector_bytes as usize];
+
+        let mut output = Vec::new();
+        write_layer_geotiff(&mut output, &desc, &data, None, 2, (-122.0, 37.0)).unwrap();
+
+        let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(output)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (128, 128));
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(32));
+    }
+
+    #[test]
+    fn sixteen_bit_formats() {
+        let config = ConfigBuilder::intermediate().node("slope", "format", r#""R16S""#).build();
+        let order = compute_order(&config).unwrap();
+        let (_, _, mut descs) = describe_layers(&config, &order).unwrap();
+        let mut desc = descs.remove("slope").unwrap();
+        assert_eq!(desc.format, TextureFormat::R16S);
+        assert_eq!(desc.sector_bytes, 64 * 64 * 2);
+
+        desc.format = TextureFormat::R16U;
+        let data = vec![0u8; 4 * desc.sector_bytes as usize];
+        let mut output = Vec::new();
+        write_layer_geotiff(&mut output, &desc, &data, None, 2, (-122.0, 37.0)).unwrap();
+
+        let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(output)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (128, 128));
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(16));
+    }
+
+    #[test]
+    fn merge_graph_files() {
+        let first: GraphFileFragment = toml::from_str(
+            r#"
+            center = "87JC9W00+"
+            side_length_sectors = 8
+
+            [nodes.dem]
+            url = "https://example.com/{ns}{lat02}{ew}{long03}.zip"
+            projection = "NAD83"
+            resolution = 64
+            format = "GridFloat+zip"
+            cache_size = 4
+        "#,
+        )
+        .unwrap();
+        let second: GraphFileFragment = toml::from_str(
+            r#"
+            [nodes.slope]
+            shader = "slope"
+            resolution = 64
+            kind = "f32"
+            format = "R32F"
+            inputs = { heights = "dem" }
+            cache_size = 4
+
+            [shaders]
+            slope = ""
+        "#,
+        )
+        .unwrap();
+
+        let config = super::merge_graph_files(vec![first.clone(), second]).unwrap();
+        assert_eq!(config.nodes.len(), 2);
+        assert_eq!(compute_order(&config).unwrap(), vec!["dem".to_owned(), "slope".to_owned()]);
+
+        assert!(super::merge_graph_files(vec![first.clone(), first]).is_err());
+    }
+
+    #[test]
+    fn order_is_deterministic() {
+        let config: GraphFile = toml::from_str(&INTERMEDIATE_CONFIG.replace(
+            "[shaders]",
+            r#"
+            [nodes.aspect]
+            shader = "slope"
+            resolution = 64
+            kind = "f32"
+            format = "R32F"
+            inputs = { heights = "dem" }
+            cache_size = 4
+
+            [shaders]"#,
+        ))
+        .unwrap();
+
+        let order = compute_order(&config).unwrap();
+        assert_eq!(order, vec!["dem", "aspect", "slope", "albedo"]);
+        assert_eq!(order, compute_order(&config).unwrap());
+    }
+
+    #[test]
+    fn depths() {
+        let config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        let order = compute_order(&config).unwrap();
+        let depths = compute_depths(&config, &order);
+        assert_eq!(depths["dem"], 0);
+        assert_eq!(depths["slope"], 1);
+        assert_eq!(depths["albedo"], 2);
+
+        // A diamond where one side is longer than the other.
+        let config: GraphFile = toml::from_str(&INTERMEDIATE_CONFIG.replace(
+            "[shaders]",
+            r#"
+            [nodes.joined]
+            shader = "slope"
+            resolution = 64
+            kind = "f32"
+            format = "R32F"
+            inputs = { heights = "dem", slope = "albedo" }
+            cache_size = 4
+
+            [shaders]"#,
+        ))
+        .unwrap();
+        let order = compute_order(&config).unwrap();
+        let depths = compute_depths(&config, &order);
+        assert_eq!(depths["dem"], 0);
+        assert_eq!(depths["albedo"], 2);
+        assert_eq!(depths["joined"], 3);
+    }
+
+    #[test]
+    fn slot_layers_checked() {
+        assert_eq!(slot_layers(3, 1).unwrap(), 3..4);
+        assert_eq!(slot_layers(0, 16).unwrap(), 0..16);
+
+        // Slots of a cache larger than `u16::MAX` must not wrap around.
+        let mut sector_indices = LinkedHashMap::new();
+        let keys: Vec<_> = (0..u16::MAX as i32 + 2).map(|i| Sector(i, 0)).collect();
+        let slots = preload_slots(&mut sector_indices, keys.len(), &keys);
+        assert!(slot_layers(slots[0].unwrap(), 1).is_ok());
+        assert!(slot_layers(slots[u16::MAX as usize - 1].unwrap(), 1).is_ok());
+        assert!(slot_layers(slots[u16::MAX as usize].unwrap(), 1).is_err());
+        assert!(slot_layers(0, slots.len()).is_err());
+    }
+
+    #[test]
+    fn seed_changes_layer_ids() {
+        let config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        assert_eq!(config.seed, 0);
+        let order = compute_order(&config).unwrap();
+        let (ids, _, _) = describe_layers(&config, &order).unwrap();
+        let (same, _, _) = describe_layers(&config, &order).unwrap();
+        assert_eq!(ids, same);
+
+        let seeded: GraphFile =
+            toml::from_str(&format!("seed = 42\n{}", INTERMEDIATE_CONFIG)).unwrap();
+        let (seeded_ids, _, _) = describe_layers(&seeded, &order).unwrap();
+        assert_eq!(seeded_ids["dem"], ids["dem"]);
+        assert_ne!(seeded_ids["slope"], ids["slope"]);
+        assert_ne!(seeded_ids["albedo"], ids["albedo"]);
+
+        // Shaders see every bit of the seed.
+        assert_eq!(seed_define(42), "uvec2(42u, 0u)");
+        assert_eq!(seed_define(0x1_0000_0002), "uvec2(2u, 1u)");
+    }
+
+    #[test]
+    fn eviction_policies() {
+        let mut sector_indices = LinkedHashMap::new();
+        let keys: Vec<_> = (0..4).map(|i| Sector(i, 0)).collect();
+        preload_slots(&mut sector_indices, 4, &keys);
+        let mut priorities: HashMap<_, _> =
+            keys.iter().zip(&[3.0, 1.0, 4.0, 2.0]).map(|(&k, &p)| (k, p)).collect();
+
+        // Choosing a victim doesn't remove it.
+        let policy = EvictionPolicy::LowestPriority;
+        assert_eq!(eviction_victim(&sector_indices, &priorities, policy), Sector(1, 0));
+        assert_eq!(sector_indices.len(), 4);
+        assert_eq!(priorities.len(), 4);
+
+        // The least recently used entry goes first, and hits count as uses.
+        let mut lru = sector_indices.clone();
+        let lru_victim =
+            |lru: &LinkedHashMap<_, _>| eviction_victim(lru, &HashMap::new(), EvictionPolicy::Lru);
+        assert_eq!(lru_victim(&lru), Sector(0, 0));
+        lru.remove(&Sector(0, 0));
+        preload_slots(&mut lru, 4, &[Sector(1, 0)]);
+        assert_eq!(lru_victim(&lru), Sector(2, 0));
+        lru.remove(&Sector(2, 0));
+        assert_eq!(lru_victim(&lru), Sector(3, 0));
+
+        // Evicts entries one at a time the way `TileCache::insert` does, returning their slots.
+        let evict = |sector_indices: &mut LinkedHashMap<_, _>, priorities: &mut HashMap<_, _>| {
+            let victim = eviction_victim(sector_indices, priorities, policy);
+            priorities.remove(&victim);
+            sector_indices.remove(&victim).unwrap()
+        };
+        assert_eq!(evict(&mut sector_indices, &mut priorities), 1);
+        assert_eq!(evict(&mut sector_indices, &mut priorities), 3);
+        assert!(!priorities.contains_key(&Sector(1, 0)));
+
+        // Entries without a priority go first.
+        sector_indices.insert(Sector(9, 0), 1);
+        assert_eq!(evict(&mut sector_indices, &mut priorities), 1);
+        assert_eq!(sector_indices.len(), 2);
+    }
+
+    #[test]
+    fn preload_fills_all_slots() {
+        let mut sector_indices = LinkedHashMap::new();
+        let keys: Vec<_> = (0..4).map(|i| Sector(i, 0)).collect();
+
+        let slots = preload_slots(&mut sector_indices, 4, &keys);
+        assert_eq!(slots, vec![Some(0), Some(1), Some(2), Some(3)]);
+        assert_eq!(sector_indices.len(), 4);
+
+        // Cached keys keep their slots and extra keys are left for `insert`.
+        let slots = preload_slots(&mut sector_indices, 4, &[Sector(2, 0), Sector(9, 9)]);
+        assert_eq!(slots, vec![Some(2), None]);
+    }
+
+    #[test]
+    fn release_slots() {
+        let mut sector_indices = LinkedHashMap::new();
+        let keys: Vec<_> = (0..4).map(|i| Sector(i, 0)).collect();
+        preload_slots(&mut sector_indices, 4, &keys);
+        let mut priorities: HashMap<_, _> = keys.iter().map(|&k| (k, 1.0)).collect();
+        let mut free_slots = Vec::new();
+
+        let released = release_slot(&mut sector_indices, &mut priorities, &mut free_slots, keys[1]);
+        assert_eq!(released, Some(1));
+        assert_eq!(free_slots, vec![1]);
+        assert!(!sector_indices.contains_key(&keys[1]));
+        assert!(!priorities.contains_key(&keys[1]));
+
+        // Removing a key that isn't resident is a no-op.
+        let released = release_slot(&mut sector_indices, &mut priorities, &mut free_slots, keys[1]);
+        assert_eq!(released, None);
+        let released =
+            release_slot(&mut sector_indices, &mut priorities, &mut free_slots, Sector(9, 9));
+        assert_eq!(released, None);
+        assert_eq!(free_slots, vec![1]);
+        assert_eq!(sector_indices.len(), 3);
+    }
+
+    #[test]
+    fn prefetch_skips_resident() {
+        let mut sector_indices = LinkedHashMap::new();
+        sector_indices.insert(Sector(0, 0), 0);
+
+        let keys = [Sector(0, 0), Sector(1, 0), Sector(1, 0), Sector(2, 0), Sector(3, 0)];
+        assert_eq!(prefetch_keys(&sector_indices, 2, &keys), vec![Sector(1, 0), Sector(2, 0)]);
+        assert_eq!(prefetch_keys(&sector_indices, 8, &keys).len(), 3);
+        assert!(prefetch_keys(&sector_indices, 8, &[Sector(0, 0)]).is_empty());
+    }
+
+    #[test]
+    fn cache_entries() {
+        let dir = std::env::temp_dir().join(format!("terra-cache-entries-{}", std::process::id()));
+        let dataset = LayerId(Sha256::digest(b"dataset"));
+        let generated = LayerId(Sha256::digest(b"generated"));
+
+        let dataset_dir = dir.join("datasets").join(hex::encode(dataset.0.as_slice()));
+        fs::create_dir_all(&dataset_dir).unwrap();
+        fs::create_dir_all(dir.join("generated")).unwrap();
+        fs::write(dataset_dir.join("header.json"), vec![0; 10]).unwrap();
+        fs::write(dataset_dir.join("n37w122.zip"), vec![0; 100]).unwrap();
+        let hash = hex::encode(generated.0.as_slice());
+        fs::write(dir.join("generated").join(format!("{}.header", hash)), vec![0; 20]).unwrap();
+        fs::write(dir.join("generated").join(format!("{}.data", hash)), vec![0; 4000]).unwrap();
+        let refs = dir.join("generated").join(format!("{}.refs", hash));
+        fs::write(&refs, vec![0; 10]).unwrap();
+
+        let entries = scan_cache(&dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&CacheEntry {
+            kind: CacheEntryKind::Dataset,
+            id: dataset,
+            bytes: 110
+        }));
+        assert!(entries.contains(&CacheEntry {
+            kind: CacheEntryKind::Generated,
+            id: generated,
+            bytes: 4030
+        }));
+
+        assert!(remove_cache_entry(&dir, generated).unwrap());
+        assert!(!refs.exists());
+        assert!(!remove_cache_entry(&dir, generated).unwrap());
+        assert_eq!(scan_cache(&dir).unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shader_change_invalidates_dependents() {
+        let config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        let order = compute_order(&config).unwrap();
+        let (ids, _, _) = describe_layers(&config, &order).unwrap();
+        let rebuilt = |shaders| {
+            let refresh = plan_shader_refresh(&config, &ids, shaders).unwrap();
+            refresh.rebuild.into_iter().map(|(name, _)| name).collect::<Vec<_>>()
+        };
+
+        assert!(rebuilt(config.shaders.clone()).is_empty());
+
+        let mut shaders = config.shaders.clone();
+        shaders.insert("slope".to_owned(), "// edited".to_owned());
+        let refresh = plan_shader_refresh(&config, &ids, shaders.clone()).unwrap();
+        assert_eq!(refresh.config.shaders, shaders);
+        assert_eq!(refresh.layer_ids["dem"], ids["dem"]);
+        assert_ne!(refresh.layer_ids["slope"], ids["slope"]);
+        assert_eq!(rebuilt(shaders), vec!["albedo", "slope"]);
+
+        let mut shaders = config.shaders.clone();
+        shaders.insert("albedo".to_owned(), "// edited".to_owned());
+        assert_eq!(rebuilt(shaders), vec!["albedo"]);
+
+        // Dropping a shader that a node uses is rejected rather than rebuilding anything.
+        let mut shaders = config.shaders.clone();
+        shaders.remove("slope");
+        assert!(plan_shader_refresh(&config, &ids, shaders).is_err());
+    }
+
+    #[test]
+    fn cache_dir() {
+        let root = std::env::temp_dir().join(format!("terra-cache-dir-{}", std::process::id()));
+        let cache_dir = CacheDir::new(root.clone());
+
+        let directory = cache_dir.create_directory("datasets/abc").unwrap();
+        assert_eq!(directory, root.join("datasets/abc"));
+        assert!(directory.is_dir());
+
+        let file = cache_dir.place_file("generated/abc.data").unwrap();
+        assert_eq!(file, root.join("generated/abc.data"));
+        assert!(root.join("generated").is_dir());
+        assert!(!file.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cached_spirv() {
+        let dir = std::env::temp_dir().join(format!("terra-cached-spirv-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.spv");
+        assert_eq!(read_cached_spirv(&path), None);
+
+        let words = vec![SPIRV_MAGIC, 0x0001_0000, 7, 42];
+        write_atomic(&path, &spirv_to_bytes(&words)).unwrap();
+        assert_eq!(read_cached_spirv(&path), Some(words));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Truncated or foreign files are treated as cache misses.
+        fs::write(&path, &[0x03, 0x02, 0x23, 0x07, 0x00]).unwrap();
+        assert_eq!(read_cached_spirv(&path), None);
+        fs::write(&path, spirv_to_bytes(&[0xdead_beef])).unwrap();
+        assert_eq!(read_cached_spirv(&path), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generation_limit() {
+        // Generations are modeled as flags that become true once they complete.
+        let mut limit = GenerationLimit::new(1);
+        assert!(limit.try_acquire());
+        limit.hold(false);
+        assert!(!limit.try_acquire());
+        assert!(limit.take_finished(|&mut done| done).is_empty());
+        assert!(!limit.try_acquire());
+
+        limit.in_flight[0] = true;
+        assert_eq!(limit.take_finished(|&mut done| done), vec![true]);
+        assert!(limit.try_acquire());
+
+        // Waiting on the oldest generation frees its permit too.
+        let mut limit = GenerationLimit::new(2);
+        limit.hold(1);
+        limit.hold(2);
+        assert!(!limit.try_acquire());
+        assert_eq!(limit.take_oldest(), Some(1));
+        assert!(limit.try_acquire());
+        assert_eq!(limit.take_oldest(), Some(2));
+        assert_eq!(limit.take_oldest(), None);
+    }
+
+    #[test]
+    fn layer_ids_by_name() {
+        let config: GraphFile = toml::from_str(INTERMEDIATE_CONFIG).unwrap();
+        let order = compute_order(&config).unwrap();
+        let (layer_ids, _, layer_descriptors) = describe_layers(&config, &order).unwrap();
+
+        assert_eq!(layer_ids.len(), 3);
+        assert_eq!(layer_descriptors["albedo"].parents["slope"], layer_ids["slope"]);
+        assert!(layer_ids.get("missing").is_none());
+
+        let desc_bytes = bincode::serialize(&layer_descriptors["slope"]).unwrap();
+        assert_eq!(layer_ids["slope"], LayerId(Sha256::digest(&desc_bytes)));
+    }
+
+    #[test]
+    fn sector_neighbors() {
+        assert_eq!(
+            Sector(0, 0).neighbors(),
+            [
+                Sector(-1, -1),
+                Sector(0, -1),
+                Sector(1, -1),
+                Sector(-1, 0),
+                Sector(1, 0),
+                Sector(-1, 1),
+                Sector(0, 1),
+                Sector(1, 1),
+            ]
+        );
+        assert_eq!(
+            Sector(0, 0).neighbors4(),
+            [Sector(-1, 0), Sector(1, 0), Sector(0, -1), Sector(0, 1)]
+        );
+        assert!(Sector(3, -2).neighbors4().iter().all(|n| Sector(3, -2).neighbors().contains(n)));
+    }
+
+    #[test]
+    fn srgb_defines() {
+        let config: GraphFile = toml::from_str(&INTERMEDIATE_CONFIG.replace(
+            "[shaders]",
+            r#"
+            [nodes.rock]
+            shader = "albedo"
+            resolution = 64
+            kind = "albedomap"
+            format = "Rgba8"
+            inputs = {}
+            cache_size = 4
+            srgb = true
+
+            [nodes.splat]
+            shader = "albedo"
+            resolution = 64
+            kind = "albedomap"
+            format = "Rgba8"
+            inputs = { rock = "rock", grass = "albedo" }
+            cache_size = 4
+
+            [shaders]"#,
+        ))
+        .unwrap();
+
+        let defines: BTreeMap<_, _> = shader_defines(&config, "splat").into_iter().collect();
+        assert_eq!(defines["OUTPUT_SRGB"], "0");
+        assert_eq!(defines["ROCK_SRGB"], "1");
+        assert_eq!(defines["GRASS_SRGB"], "0");
+        assert_eq!(shader_defines(&config, "rock"), vec![("OUTPUT_SRGB".to_owned(), "1".into())]);
+    }
+
+    #[test]
+    fn scale_defines() {
+        // Raise the resolution of `dem` and lower that of `slope`, leaving `albedo` at 64.
+        let config = ConfigBuilder::intermediate()
+            .node("dem", "resolution", "256")
+            .node("slope", "resolution", "32")
+            .build();
+
+        let defines: BTreeMap<_, _> = shader_defines(&config, "slope").into_iter().collect();
+        assert_eq!(defines["HEIGHTS_SCALE"], "8.0");
+        let defines: BTreeMap<_, _> = shader_defines(&config, "albedo").into_iter().collect();
+        assert_eq!(defines["SLOPE_SCALE"], "0.5");
+    }
+
+    #[test]
+    fn attributions() {
+        let dataset = |license: &str, bib: &str| {
+            format!(
+                "url = \"https://example.com/\"\nprojection = \"NAD83\"\nresolution = 64\n\
+                 format = \"GridFloat+zip\"\ncache_size = 4\n{}{}",
+                license, bib
+            )
+        };
+        let config: GraphFile = toml::from_str(&INTERMEDIATE_CONFIG.replace(
+            "[shaders]",
+            &format!(
+                "[nodes.copernicus]\n{}\n[nodes.aster]\n{}\n[nodes.ned]\n{}\n[shaders]",
+                dataset("license = \"Copernicus\"\n", "bib = \"@misc{cop}\"\n"),
+                dataset("", "bib = \"@misc{aster}\"\n"),
+                dataset("license = \"Public domain\"\n", "bib = \"@misc{cop}\"\n"),
+            ),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            dataset_attributions(&config),
+            vec![
+                ("copernicus".to_owned(), "Copernicus".to_owned()),
+                ("ned".to_owned(), "Public domain".to_owned()),
+            ]
+        );
+        assert_eq!(dataset_bibliography(&config), vec!["@misc{aster}", "@misc{cop}"]);
+    }
+
+    #[test]
+    fn input_hashes() {
+        let id = LayerId(Sha256::digest(b"dem"));
+        let inputs = |slot: &str, data: &[u8]| vec![(slot.to_owned(), id, data.to_vec())];
+
+        let hash = hash_inputs(&inputs("heights", &[1, 2, 3]));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_inputs(&inputs("heights", &[1, 2, 3])));
+        assert_ne!(hash, hash_inputs(&inputs("heights", &[1, 2, 4])));
+        assert_ne!(hash, hash_inputs(&inputs("slope", &[1, 2, 3])));
+
+        // Moving bytes between the slot name and the data changes the hash.
+        assert_ne!(hash_inputs(&inputs("ab", b"c")), hash_inputs(&inputs("a", b"bc")));
+    }
+
+    #[test]
+    fn rect_sectors() {
+        let center = (-122.0, 37.0);
+        assert_eq!(sector_for_lat_lon(center, 36.5, -121.5), Sector(0, 0));
+        assert_eq!(sector_for_lat_lon(center, 37.5, -122.5), Sector(-1, -1));
+
+        let mut sectors: Vec<_> = sectors_in_rect(center, 36.5, -122.5, 37.5, -121.5).collect();
+        assert!(sectors.windows(2).all(|w| sector_index(w[0]) < sector_index(w[1])));
+        sectors.sort();
+        assert_eq!(sectors, vec![Sector(-1, -1), Sector(-1, 0), Sector(0, -1), Sector(0, 0)]);
+
+        assert_eq!(sectors_in_rect(center, 36.2, -121.8, 36.4, -121.6).count(), 1);
+    }
+
+    #[test]
+    fn shader_binding_mismatch() {
+        let images: Vec<(u32, String)> =
+            vec![(0, "heights".into()), (2, "slope".into()), (1, "output".into())];
+        let one_input = vec![("heights".to_owned(), "dem".to_owned())].into_iter().collect();
+        let two_inputs = vec![
+            ("heights".to_owned(), "dem".to_owned()),
+            ("slope".to_owned(), "slope".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(validate_shader_bindings("albedo", &images, &two_inputs).is_ok());
+        let err = validate_shader_bindings("albedo", &images, &one_input).unwrap_err();
+        assert!(err.to_string().contains("'slope'"));
+        assert!(validate_shader_bindings("albedo", &images[..2], &two_inputs).is_err());
+    }
+
+    #[test]
+    fn dedup_identical_sectors() {
+        let sector_bytes = 16;
+        let mut data = vec![0u8; 8 * sector_bytes as usize];
+        let mut dedup = SectorDedup::default();
+        let ocean = [7u8; 16];
+        let land = [9u8; 16];
+
+        assert!(write_sector(&mut data, sector_bytes, Some(&mut dedup), Sector(0, 0), &ocean)
+            .unwrap());
+        assert!(!write_sector(&mut data, sector_bytes, Some(&mut dedup), Sector(-1, 0), &ocean)
+            .unwrap());
+        assert!(write_sector(&mut data, sector_bytes, Some(&mut dedup), Sector(0, -1), &land)
+            .unwrap());
+
+        // Only the first copy of the repeated sector was written.
+        assert_eq!(data.iter().filter(|&&b| b == 7).count(), 16);
+        assert_eq!(read_sector(&data, sector_bytes, Some(&dedup), Sector(-1, 0)), &ocean);
+        assert_eq!(read_sector(&data, sector_bytes, Some(&dedup), Sector(0, 0)), &ocean);
+        assert_eq!(read_sector(&data, sector_bytes, Some(&dedup), Sector(0, -1)), &land);
+        assert!(write_sector(&mut data, sector_bytes, Some(&mut dedup), Sector(0, 0), &land)
+            .is_err());
+    }
+
+    #[test]
+    fn dataset_mosaic() {
+        // Each tile holds its own id plus the index of the texel.
+        let tile_id = |(lat, long): (i16, i16)| (10 * (lat - 36) + 40 * (long + 123)) as u8;
+        let mut fetched = Vec::new();
+        let mut get_tile = |tile| {
+            fetched.push(tile);
+            Ok(Some((0..4).map(|i| tile_id(tile) + i).collect()))
+        };
+
+        // A sector whose corners are half a degree off the tile grid takes one texel from each of
+        // the four tiles it overlaps.
+        let data = mosaic_sector((-122.5, 37.5), Sector(0, 0), 2, 1, &mut get_tile).unwrap();
+        assert_eq!(data, Some(vec![13, 52, 1, 40]));
+        let data = mosaic_sector((-122.0, 37.0), Sector(0, 0), 2, 1, &mut get_tile).unwrap();
+        assert_eq!(data, Some(vec![40, 41, 42, 43]));
+        assert_eq!(fetched, vec![(37, -123), (37, -122), (36, -123), (36, -122), (36, -122)]);
+
+        assert_eq!(mosaic_sector((-122.5, 37.5), Sector(0, 0), 2, 1, |_| Ok(None)).unwrap(), None);
+        assert!(mosaic_sector((-122.0, 37.0), Sector(0, 0), 2, 1, |_| Ok(Some(vec![0]))).is_err());
+    }
+
+    #[test]
+    fn generated_sector_round_trip() {
+        // A generated sector lands in its own slot of the data file.
+        let sector_bytes = 16;
+        let mut data = MmapMut::map_anon(8 * sector_bytes as usize).unwrap();
+        let output: Vec<u8> = (0..16).collect();
+        assert!(write_sector(&mut data, sector_bytes, None, Sector(-1, 0), &output).unwrap());
+        assert_eq!(read_sector(&data, sector_bytes, None, Sector(-1, 0)), &output[..]);
+        let offset = (sector_index(Sector(-1, 0)) * sector_bytes) as usize;
+        assert_eq!(&data[offset..][..16], &output[..]);
+        assert!(data[..offset].iter().all(|&b| b == 0));
+    }
 }
@@ -23,9 +23,9 @@ use crate::generate::{
     ComputeShader, GenDisplacementsUniforms, GenHeightmapsUniforms, GenNormalsUniforms,
 };
 use crate::mapfile::TileState;
-use crate::terrain::quadtree::node::VNode;
 use crate::terrain::quadtree::render::NodeState;
-use crate::terrain::tile_cache::{LayerType, TileCache};
+use crate::terrain::raster::NormalSpace;
+use crate::terrain::tile_cache::{self, LayerType, TileCache};
 use anyhow::Error;
 use cgmath::Vector2;
 use futures::executor;
@@ -36,8 +36,17 @@ use vec_map::VecMap;
 // use wgpu_glyph::{GlyphBrush, Section};
 
 pub use crate::mapfile::MapFile;
+pub use crate::terrain::quadtree::node::VNode;
+pub use crate::terrain::tile_cache::{CacheStats, Priority};
+pub use crate::utils::math::BoundingBox;
 pub use generate::MapFileBuilder;
 
+/// Internals used by the benchmarks in `benches/`. Not part of the public API.
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::terrain::tile_cache::{read_tiles_with, LayerType};
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct UniformBlock {
@@ -84,11 +93,51 @@ pub struct Terrain {
 }
 impl Terrain {
     pub fn new(
+        device: &wgpu::Device,
+        queue: &mut wgpu::Queue,
+        mapfile: MapFile,
+    ) -> Result<Self, Error> {
+        let tile_cache = TileCache::new(mapfile.layers().clone(), tile_cache::DEFAULT_SIZE)?;
+        Self::with_tile_cache(device, queue, mapfile, tile_cache)
+    }
+
+    /// Same as `new` but sizes the tile cache so that it takes at most `memory_budget` bytes of
+    /// GPU memory. Useful on GPUs with little video memory, where the default cache can fail to
+    /// allocate.
+    pub fn with_memory_budget(
+        device: &wgpu::Device,
+        queue: &mut wgpu::Queue,
+        mapfile: MapFile,
+        memory_budget: usize,
+    ) -> Result<Self, Error> {
+        let tile_cache = TileCache::with_memory_budget(mapfile.layers().clone(), memory_budget)?;
+        Self::with_tile_cache(device, queue, mapfile, tile_cache)
+    }
+
+    /// Bytes of GPU memory taken by the tile cache that `new` creates for `mapfile`. A starting
+    /// point for picking a budget to pass to `with_memory_budget`.
+    pub fn default_memory_budget(mapfile: &MapFile) -> usize {
+        TileCache::default_memory_budget(mapfile.layers())
+    }
+
+    /// Same as `new` but with a tile cache of `cache_size` slots, each of which holds one tile of
+    /// every layer. `required_cache_size` estimates how many slots a given view distance needs.
+    pub fn with_cache_size(
+        device: &wgpu::Device,
+        queue: &mut wgpu::Queue,
+        mapfile: MapFile,
+        cache_size: usize,
+    ) -> Result<Self, Error> {
+        let tile_cache = TileCache::new(mapfile.layers().clone(), cache_size)?;
+        Self::with_tile_cache(device, queue, mapfile, tile_cache)
+    }
+
+    fn with_tile_cache(
         device: &wgpu::Device,
         queue: &mut wgpu::Queue,
         mut mapfile: MapFile,
+        tile_cache: TileCache,
     ) -> Result<Self, Error> {
-        let tile_cache = TileCache::new(mapfile.layers().clone(), 512);
         let quadtree = QuadTree::new(tile_cache.resolution(LayerType::Displacements) - 1);
 
         let mut watcher = rshader::ShaderDirectoryWatcher::new("src/shaders").unwrap();
@@ -182,28 +231,25 @@ impl Terrain {
 
         let gen_heightmaps = ComputeShader::new(
             device,
-            rshader::ShaderSet::compute_only(
-                &mut watcher,
-                rshader::shader_source!("shaders", "version", "hash", "gen-heightmaps.comp"),
-            )
-            .unwrap(),
-        );
+            &mut watcher,
+            rshader::shader_source!("shaders", "version", "hash", "gen-heightmaps.comp"),
+            (8, 8),
+        )
+        .unwrap();
         let gen_displacements = ComputeShader::new(
             device,
-            rshader::ShaderSet::compute_only(
-                &mut watcher,
-                rshader::shader_source!("shaders", "version", "gen-displacements.comp"),
-            )
-            .unwrap(),
-        );
+            &mut watcher,
+            rshader::shader_source!("shaders", "version", "gen-displacements.comp"),
+            (8, 8),
+        )
+        .unwrap();
         let gen_normals = ComputeShader::new(
             device,
-            rshader::ShaderSet::compute_only(
-                &mut watcher,
-                rshader::shader_source!("shaders", "version", "hash", "gen-normals.comp"),
-            )
-            .unwrap(),
-        );
+            &mut watcher,
+            rshader::shader_source!("shaders", "version", "hash", "gen-normals.comp"),
+            (4, 4),
+        )
+        .unwrap();
 
         // TODO: only clear if shader has changed?
         mapfile.clear_generated(LayerType::Displacements).unwrap();
@@ -243,6 +289,98 @@ impl Terrain {
         })
     }
 
+    /// Stops shaders from being reloaded when their source files change, for instance while
+    /// running a long batch of tile generation. Changes made while paused are applied by the first
+    /// refresh after `resume_shader_reloading`.
+    pub fn pause_shader_reloading(&mut self) {
+        self.watcher.pause();
+    }
+
+    /// Resumes reloading shaders after `pause_shader_reloading`.
+    pub fn resume_shader_reloading(&mut self) {
+        self.watcher.resume();
+    }
+
+    /// Sets the anisotropic filtering level used when sampling terrain textures. Values are
+    /// clamped to 1..=16 and rounded down to a power of two. Returns the level that was applied.
+    pub fn set_anisotropy(&mut self, level: u8) -> u8 {
+        let applied = self.quadtree.set_anisotropy(level);
+        self.bindgroup_pipeline = None;
+        applied
+    }
+
+    /// Scales how much terrain detail is loaded and rendered. The default is 1.0; larger values
+    /// refine nodes further from the camera, smaller ones cull more aggressively. Values are
+    /// clamped to 1/64..=64. Returns the bias that was applied.
+    pub fn set_detail_bias(&mut self, bias: f32) -> f32 {
+        self.quadtree.set_detail_bias(bias)
+    }
+
+    /// Estimates how many tile cache slots are needed to hold every node within `view_distance`
+    /// meters of the camera that would be drawn with the current maximum level, detail bias and
+    /// priority function. A cache smaller than this will evict tiles that are still in view.
+    pub fn required_cache_size(&self, view_distance: f32) -> usize {
+        self.quadtree.required_cache_size(view_distance)
+    }
+
+    /// Returns how many tile requests were satisfied from the tile cache, how many needed a slot,
+    /// and how many slots were taken from lower priority tiles since the terrain was created or
+    /// `reset_cache_stats` was last called. Useful for choosing a cache size.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.tile_cache.stats()
+    }
+
+    /// Zeroes the counts returned by `cache_stats`, for instance to measure a single frame.
+    pub fn reset_cache_stats(&mut self) {
+        self.tile_cache.reset_stats();
+    }
+
+    /// Returns the deepest quadtree level that terrain is loaded and rendered at.
+    pub fn max_level(&self) -> u8 {
+        self.quadtree.max_level()
+    }
+
+    /// Limits how deep into the quadtree terrain is loaded and rendered. Each level halves the
+    /// side length of a node, starting from a quarter of the planet's circumference at level 0.
+    /// Lower limits save memory and bandwidth at the cost of close-up detail. Fails without
+    /// changing anything if `max_level` is greater than 25, the deepest level nodes can address.
+    pub fn set_max_level(&mut self, max_level: u8) -> Result<(), Error> {
+        self.quadtree.set_max_level(max_level)
+    }
+
+    /// Replaces the function used to decide which nodes to load and render, for instance with one
+    /// based on screen space error. It is called with the camera position projected onto the unit
+    /// cube the quadtree is laid out on, and nodes whose priority falls below
+    /// `Priority::cutoff()` (scaled by the detail bias) are skipped. Defaults to
+    /// `VNode::priority`.
+    pub fn set_priority_fn(
+        &mut self,
+        priority_fn: impl Fn(&VNode, cgmath::Point3<f64>) -> Priority + Send + Sync + 'static,
+    ) {
+        self.quadtree.set_priority_fn(priority_fn);
+    }
+
+    /// Returns the nodes drawn in full by the last call to `render`, from highest to lowest
+    /// priority, which for the default priority function means front to back. `VNode::bounds`
+    /// gives the region each of them covers.
+    pub fn visible_nodes(&self) -> &[VNode] {
+        self.quadtree.visible_nodes()
+    }
+
+    /// Returns the nodes of which only some quadrants were drawn by the last call to `render`,
+    /// along with a mask of the drawn quadrants. Sorted the same way as `visible_nodes`.
+    pub fn partially_visible_nodes(&self) -> &[(VNode, u8)] {
+        self.quadtree.partially_visible_nodes()
+    }
+
+    /// Returns the latitude and longitude bounds of `node` in degrees, as `(min_latitude,
+    /// max_latitude, min_longitude, max_longitude)`. The bounds are conservative: they are padded
+    /// slightly so that they always contain the whole node. Nodes that straddle the antimeridian
+    /// have `min_longitude > max_longitude`.
+    pub fn node_geographic_bounds(&self, node: VNode) -> (f64, f64, f64, f64) {
+        self.quadtree.node_geographic_bounds(node)
+    }
+
     pub fn render(
         &mut self,
         device: &wgpu::Device,
@@ -306,7 +444,9 @@ impl Terrain {
                 device,
                 &self.shader,
                 Some(self.uniform_buffer.slice(0..mem::size_of::<UniformBlock>() as u64)),
-            );
+                self.quadtree.anisotropy(),
+            )
+            .unwrap();
             let render_pipeline_layout =
                 device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     bind_group_layouts: &[&bind_group_layout],
@@ -371,7 +511,9 @@ impl Terrain {
                 device,
                 &self.sky_shader,
                 Some(self.sky_uniform_buffer.slice(0..mem::size_of::<SkyUniformBlock>() as u64)),
-            );
+                1,
+            )
+            .unwrap();
             let render_pipeline_layout =
                 device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     bind_group_layouts: [&bind_group_layout][..].into(),
@@ -424,16 +566,27 @@ impl Terrain {
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         let mut missing = VecMap::new();
-        for (i, texture) in &self.gpu_state.tile_cache {
-            let mut layer_missing = self.tile_cache.upload_tiles(
-                device,
-                &mut encoder,
-                &texture,
-                &mut self.mapfile,
-                LayerType::from_index(i),
-            );
+        let mut pending = Vec::new();
+        for (i, _) in &self.gpu_state.tile_cache {
+            let ty = LayerType::from_index(i);
+            let (uploads, mut layer_missing) = self.tile_cache.find_pending(&self.mapfile, ty);
             layer_missing.sort_by_key(|n| n.level());
             missing.insert(i, layer_missing);
+            pending.extend(uploads.into_iter().map(|(slot, node)| (ty, slot, node)));
+        }
+        // Slots are only marked valid once uploaded, so a failed read is retried next frame.
+        let mut tiles = match tile_cache::read_tiles(&self.mapfile, pending) {
+            Ok(tiles) => tiles,
+            Err(e) => {
+                log::warn!("failed to read tiles: {}", e);
+                VecMap::new()
+            }
+        };
+        for (i, texture) in &self.gpu_state.tile_cache {
+            if let Some(tiles) = tiles.remove(i) {
+                let ty = LayerType::from_index(i);
+                self.tile_cache.upload_tiles(device, &mut encoder, &texture, ty, tiles);
+            }
         }
 
         let heightmaps_resolution = self.tile_cache.resolution(LayerType::Heightmaps);
@@ -443,7 +596,8 @@ impl Terrain {
         let normals_border = self.tile_cache.border(LayerType::Normals);
         let normals_row_pitch = self.tile_cache.row_pitch(LayerType::Normals);
 
-        for (i, node) in missing.remove(LayerType::Normals.index()).unwrap().into_iter().enumerate()
+        'normals: for (i, node) in
+            missing.remove(LayerType::Normals.index()).unwrap().into_iter().enumerate()
         {
             if node.level() > 0 && i >= 16 {
                 continue;
@@ -466,6 +620,16 @@ impl Terrain {
 
             let normals_slot = self.tile_cache.get_slot(node).unwrap() as i32;
 
+            let mut neighbor_slots = [-1; 4];
+            for (slot, neighbor) in neighbor_slots.iter_mut().zip(node.neighbors().iter()) {
+                if let Some(neighbor_slot) = neighbor
+                    .and_then(|n| self.tile_cache.get_slot(n))
+                    .filter(|&s| self.tile_cache.slot_valid(s, LayerType::Heightmaps))
+                {
+                    *slot = neighbor_slot as i32;
+                }
+            }
+
             if !self.tile_cache.slot_valid(heightmaps_slot as usize, LayerType::Heightmaps) {
                 let mut nodes_needed = vec![node];
                 let mut parent = node.parent().unwrap().0;
@@ -489,12 +653,13 @@ impl Terrain {
                     //     - cgmath::Vector3::new(spacing, 0.0, spacing) * heightmaps_border as f32;
                     let resolution = heightmaps_resolution - heightmaps_border * 2 - 1;
                     let level_resolution = resolution << node.level();
-                    self.gen_heightmaps.run(
+                    let result = self.gen_heightmaps.run_split(
                         device,
                         &mut encoder,
                         &self.gpu_state,
-                        ((heightmaps_resolution + 7) / 8, (heightmaps_resolution + 7) / 8, 1),
-                        &GenHeightmapsUniforms {
+                        self.gen_heightmaps
+                            .dispatch_size(heightmaps_resolution, heightmaps_resolution),
+                        |(x, y, _)| GenHeightmapsUniforms {
                             position: [
                                 (node.x() * resolution) as i32
                                     - level_resolution as i32 / 2
@@ -511,8 +676,13 @@ impl Terrain {
                             in_slot,
                             out_slot,
                             level_resolution: level_resolution as i32,
+                            workgroup_offset: [x, y],
                         },
                     );
+                    if let Err(e) = result {
+                        log::warn!("failed to generate heightmap for {:?}: {}", node, e);
+                        continue 'normals;
+                    }
                     self.tile_cache.set_slot_valid(out_slot as usize, LayerType::Heightmaps);
                 }
             }
@@ -531,12 +701,12 @@ impl Terrain {
             let cspace_origin_dy =
                 node.cell_position_cspace(0, 1, normals_border as u16, normals_resolution as u16);
 
-            self.gen_normals.run(
+            let result = self.gen_normals.run_split(
                 device,
                 &mut encoder,
                 &self.gpu_state,
-                ((normals_resolution + 3) / 4, (normals_resolution + 3) / 4, 1),
-                &GenNormalsUniforms {
+                self.gen_normals.dispatch_size(normals_resolution, normals_resolution),
+                |(x, y, _)| GenNormalsUniforms {
                     heightmaps_origin: [
                         (heightmaps_border - normals_border) as i32,
                         (heightmaps_border - normals_border) as i32,
@@ -571,9 +741,17 @@ impl Terrain {
                             (normals_resolution - normals_border) / 2
                         },
                     ],
+                    heightmaps_border: heightmaps_border as i32,
+                    normal_space: NormalSpace::Tangent as i32,
                     padding: 0,
+                    neighbor_slots,
+                    workgroup_offset: [x, y],
                 },
             );
+            if let Err(e) = result {
+                log::warn!("failed to generate normals for {:?}: {}", node, e);
+                continue;
+            }
             self.tile_cache.set_slot_valid(normals_slot as usize, LayerType::Normals);
             if albedo_slot >= 0 {
                 self.tile_cache.set_slot_valid(albedo_slot as usize, LayerType::Albedo);
@@ -680,12 +858,13 @@ impl Terrain {
                 continue;
             }
 
-            self.gen_displacements.run(
+            let result = self.gen_displacements.run_split(
                 device,
                 &mut encoder,
                 &self.gpu_state,
-                ((displacements_resolution + 7) / 8, (displacements_resolution + 7) / 8, 1),
-                &GenDisplacementsUniforms {
+                self.gen_displacements
+                    .dispatch_size(displacements_resolution, displacements_resolution),
+                |(x, y, _)| GenDisplacementsUniforms {
                     origin: [
                         (heightmaps_border
                             + (heightmaps_resolution - heightmaps_border * 2 - 1) * offset.x
@@ -697,8 +876,14 @@ impl Terrain {
                     stride: stride as i32,
                     displacements_slot: displacements_slot as i32,
                     heightmaps_slot: heightmaps_slot as i32,
+                    padding: 0,
+                    workgroup_offset: [x, y],
                 },
             );
+            if let Err(e) = result {
+                log::warn!("failed to generate displacements for {:?}: {}", node, e);
+                continue;
+            }
             self.tile_cache.set_slot_valid(displacements_slot as usize, LayerType::Displacements);
 
             if let TileState::Missing =
@@ -849,6 +1034,10 @@ impl Terrain {
         // self.glyph_brush
         //     .draw_queued(device, &mut encoder, &frame.view, frame_size.0, frame_size.1)
         //     .unwrap();
-        queue.submit(Some(encoder.finish()));
+        let submitted = generate::submit(queue, encoder);
+
+        self.gen_heightmaps.recycle_staging(&submitted);
+        self.gen_displacements.recycle_staging(&submitted);
+        self.gen_normals.recycle_staging(&submitted);
     }
 }
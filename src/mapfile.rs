@@ -41,7 +41,7 @@ pub(crate) struct TextureDescriptor {
 
 pub struct MapFile {
     layers: VecMap<LayerParams>,
-    _db: sled::Db,
+    db: sled::Db,
     tiles: sled::Tree,
     textures: sled::Tree,
 }
@@ -57,7 +57,7 @@ impl MapFile {
             layers,
             tiles: db.open_tree("tiles").unwrap(),
             textures: db.open_tree("textures").unwrap(),
-            _db: db,
+            db,
         }
     }
 
@@ -381,6 +381,15 @@ impl MapFile {
         Ok(())
     }
 
+    /// Returns the seed the noise texture was last generated with, if it has been generated.
+    pub(crate) fn noise_seed(&self) -> Result<Option<u64>, Error> {
+        Ok(self.db.get("noise_seed")?.map(|value| serde_json::from_slice(&value).unwrap()))
+    }
+    pub(crate) fn set_noise_seed(&self, seed: u64) -> Result<(), Error> {
+        self.db.insert("noise_seed", serde_json::to_vec(&seed).unwrap())?;
+        Ok(())
+    }
+
     fn lookup_texture(&self, name: &str) -> Result<Option<TextureDescriptor>, Error> {
         Ok(self.textures.get(name)?.map(|value| serde_json::from_slice(&value).unwrap()))
     }
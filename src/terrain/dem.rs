@@ -1,7 +1,12 @@
-use crate::cache::{AssetLoadContext, WebAsset};
+use crate::cache::{self, AssetLoadContext, Checksum, DownloadError, WebAsset};
+use crate::coordinates;
 use crate::terrain::raster::{GlobalRaster, Raster, RasterSource};
 use anyhow::{ensure, Error};
-use std::io::{Cursor, Read};
+use bit_vec::BitVec;
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
 use std::str::FromStr;
 use thiserror::Error;
 use zip::ZipArchive;
@@ -9,8 +14,22 @@ use std::collections::HashSet;
 use lazy_static::lazy_static;
 
 #[derive(Debug, Error)]
-#[error("failed to parse DEM file")]
-pub struct DemParseError;
+pub enum DemError {
+    #[error("DEM header is missing field '{0}'")]
+    MissingHeaderField(String),
+    #[error("DEM data has the wrong size: expected {expected} bytes, got {got}")]
+    SizeMismatch { expected: usize, got: usize },
+    #[error("DEM data of {0} bytes is not a square grid of 16-bit samples")]
+    NotSquare(usize),
+    #[error("DEM has the wrong dimensions: expected {expected}x{expected}, got {width}x{height}")]
+    WrongDimensions { expected: usize, width: usize, height: usize },
+    #[error("unrecognized byte order: {0}")]
+    UnrecognizedByteOrder(String),
+    #[error("DEM samples are not 16-bit integers")]
+    UnsupportedSampleFormat,
+    #[error("zip file does not contain a DEM data file")]
+    MissingDataFile,
+}
 
 lazy_static! {
     static ref SRTM3_FILES: HashSet<&'static str> =
@@ -31,6 +50,12 @@ pub enum DemSource {
     /// available globally between 60° north and 56° south latitude.
     #[allow(unused)]
     Srtm90m,
+    /// Use DEMs from version 3 of the ASTER Global Digital Elevation Model at 1 arc-second. Data
+    /// is available globally between 83° north and 83° south latitude. Downloads require NASA
+    /// Earthdata credentials, which are read from the `EARTHDATA_USERNAME` and
+    /// `EARTHDATA_PASSWORD` environment variables.
+    #[allow(unused)]
+    AsterGdem,
 }
 impl DemSource {
     pub(crate) fn url_str(&self) -> &str {
@@ -44,6 +69,7 @@ impl DemSource {
             DemSource::Srtm90m => {
                 "https://cloud.sdsc.edu/v1/AUTH_opentopography/Raster/SRTM_GL3/SRTM_GL3_srtm/"
             }
+            DemSource::AsterGdem => "https://e4ftl01.cr.usgs.gov/ASTT/ASTGTM.003/2000.03.01/",
         }
     }
     pub(crate) fn directory_str(&self) -> &str {
@@ -51,6 +77,7 @@ impl DemSource {
             DemSource::Usgs30m => "dems/ned1",
             DemSource::Usgs10m => "dems/ned13",
             DemSource::Srtm90m => "dems/srtm3",
+            DemSource::AsterGdem => "dems/astgtm3",
         }
     }
     /// Returns the approximate resolution of data from this source in meters.
@@ -59,6 +86,7 @@ impl DemSource {
             DemSource::Usgs30m => 30,
             DemSource::Usgs10m => 10,
             DemSource::Srtm90m => 90,
+            DemSource::AsterGdem => 30,
         }
     }
     /// Returns the size of cells from this data source in arcseconds.
@@ -67,6 +95,47 @@ impl DemSource {
             DemSource::Usgs30m => 1.0,
             DemSource::Usgs10m => 1.0 / 3.0,
             DemSource::Srtm90m => 3.0,
+            DemSource::AsterGdem => 1.0,
+        }
+    }
+    /// Returns the typical number of bytes downloaded for a single 1x1 degree tile.
+    fn typical_tile_bytes(&self) -> u64 {
+        match *self {
+            // Zipped 3612x3612 GridFloat files compress poorly, so these are close to the raw size.
+            DemSource::Usgs30m => 48_000_000,
+            DemSource::Usgs10m => 420_000_000,
+            // Uncompressed 1201x1201 16-bit samples.
+            DemSource::Srtm90m => 1201 * 1201 * 2,
+            // Zipped 3601x3601 16-bit GeoTIFFs, which typically compress to around half size.
+            DemSource::AsterGdem => 13_000_000,
+        }
+    }
+    /// Estimates how many bytes must be downloaded to cover the given region. The estimate counts
+    /// the tiles overlapping the region and assumes each is of typical size. For SRTM data, tiles
+    /// that don't exist (such as those entirely over the ocean) are skipped.
+    pub fn estimate_bytes(&self, min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> u64 {
+        let tiles = |min: f64, max: f64| {
+            min.floor() as i16..(max.ceil().max(min.floor() + 1.0)) as i16
+        };
+
+        let mut count = 0;
+        for latitude in tiles(min_lat, max_lat) {
+            for longitude in tiles(min_lon, max_lon) {
+                if self.has_tile(latitude, longitude) {
+                    count += 1;
+                }
+            }
+        }
+        count * self.typical_tile_bytes()
+    }
+    /// Returns false for tiles known not to exist. Only SRTM publishes a list of its tiles, so
+    /// for other sources this is always true.
+    fn has_tile(&self, latitude: i16, longitude: i16) -> bool {
+        match *self {
+            DemSource::Srtm90m => {
+                SRTM3_FILES.contains(&format!("{}.hgt", tile_name(latitude, longitude))[..])
+            }
+            _ => true,
         }
     }
 }
@@ -79,27 +148,112 @@ impl RasterSource for DemSource {
         latitude: i16,
         longitude: i16,
     ) -> Option<Raster<f32>> {
-        DigitalElevationModelParams { latitude, longitude, source: *self }.load(context).ok()
+        self.try_load(context, latitude, longitude).ok().flatten()
     }
     fn bands(&self) -> usize {
         1
     }
+    fn try_load(
+        &self,
+        context: &mut AssetLoadContext,
+        latitude: i16,
+        longitude: i16,
+    ) -> Result<Option<Raster<f32>>, Error> {
+        if !self.has_tile(latitude, longitude) {
+            return Ok(None);
+        }
+        let params =
+            DigitalElevationModelParams { latitude, longitude, source: *self, fill_voids: false };
+        match params.load(context) {
+            Ok(raster) => Ok(Some(raster)),
+            Err(e) => match e.downcast_ref::<DownloadError>() {
+                Some(DownloadError::NotFound(_)) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// Reads USGS GridFloat DEMs from a local directory rather than downloading them. Files must use
+/// the same naming convention as downloaded tiles, so the tile with its southwest corner at 37°N
+/// 122°W is expected at `root/n37_w122.zip`.
+pub struct LocalDemSource {
+    pub root: PathBuf,
+}
+impl LocalDemSource {
+    pub(crate) fn path(&self, latitude: i16, longitude: i16) -> PathBuf {
+        self.root.join(format!("{}.zip", tile_name(latitude, longitude)))
+    }
+}
+impl RasterSource for LocalDemSource {
+    type Type = f32;
+    type Container = Vec<f32>;
+    fn load(
+        &self,
+        context: &mut AssetLoadContext,
+        latitude: i16,
+        longitude: i16,
+    ) -> Option<Raster<f32>> {
+        self.try_load(context, latitude, longitude).ok().flatten()
+    }
+    fn bands(&self) -> usize {
+        1
+    }
+    fn try_load(
+        &self,
+        _context: &mut AssetLoadContext,
+        latitude: i16,
+        longitude: i16,
+    ) -> Result<Option<Raster<f32>>, Error> {
+        let data = match fs::read(self.path(latitude, longitude)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        parse_ned_zip(data).map(Some)
+    }
+}
+
+/// Name of the tile with its southwest corner at the given position, like `n37_w122`.
+fn tile_name(latitude: i16, longitude: i16) -> String {
+    format!(
+        "{}{:02}_{}{:03}",
+        if latitude >= 0 { 'n' } else { 's' },
+        latitude.abs(),
+        if longitude >= 0 { 'e' } else { 'w' },
+        longitude.abs()
+    )
 }
 
 pub struct DigitalElevationModelParams {
     pub latitude: i16,
     pub longitude: i16,
     pub source: DemSource,
+    /// Whether to interpolate voids in SRTM data from the surrounding cells rather than setting
+    /// them to zero.
+    pub fill_voids: bool,
 }
 impl WebAsset for DigitalElevationModelParams {
     type Type = Raster<f32>;
 
     fn compressed(&self) -> bool {
         match self.source {
-            DemSource::Usgs30m | DemSource::Usgs10m => false,
+            DemSource::Usgs30m | DemSource::Usgs10m | DemSource::AsterGdem => false,
             DemSource::Srtm90m => true,
         }
     }
+    fn credentials(&self) -> Option<(String, String)> {
+        match self.source {
+            DemSource::AsterGdem => Some((
+                std::env::var("EARTHDATA_USERNAME").ok()?,
+                std::env::var("EARTHDATA_PASSWORD").ok()?,
+            )),
+            _ => None,
+        }
+    }
+    fn checksum(&self) -> Option<Checksum> {
+        cache::known_checksum(&self.filename())
+    }
     fn url(&self) -> String {
         let (latitude, longitude) = match self.source {
             DemSource::Usgs30m | DemSource::Usgs10m => (self.latitude + 1, self.longitude),
@@ -133,34 +287,32 @@ impl WebAsset for DigitalElevationModelParams {
                 e_or_w.to_uppercase().next().unwrap(),
                 longitude.abs()
             ),
+            DemSource::AsterGdem => format!(
+                "{}ASTGTMV003_{}{:02}{}{:03}.zip",
+                self.source.url_str(),
+                n_or_s.to_uppercase().next().unwrap(),
+                latitude.abs(),
+                e_or_w.to_uppercase().next().unwrap(),
+                longitude.abs()
+            ),
         }
     }
     fn filename(&self) -> String {
-        let n_or_s = if self.latitude >= 0 { 'n' } else { 's' };
-        let e_or_w = if self.longitude >= 0 { 'e' } else { 'w' };
+        let name = tile_name(self.latitude, self.longitude);
         match self.source {
-            DemSource::Usgs30m | DemSource::Usgs10m => format!(
-                "{}/{}{:02}_{}{:03}.zip",
-                self.source.directory_str(),
-                n_or_s,
-                self.latitude.abs(),
-                e_or_w,
-                self.longitude.abs()
-            ),
-            DemSource::Srtm90m => format!(
-                "{}/{}{:02}_{}{:03}.hgt.sz",
-                self.source.directory_str(),
-                n_or_s,
-                self.latitude.abs(),
-                e_or_w,
-                self.longitude.abs()
-            ),
+            DemSource::Usgs30m | DemSource::Usgs10m | DemSource::AsterGdem => {
+                format!("{}/{}.zip", self.source.directory_str(), name)
+            }
+            DemSource::Srtm90m => format!("{}/{}.hgt.sz", self.source.directory_str(), name),
         }
     }
-    fn parse(&self, _context: &mut AssetLoadContext, data: Vec<u8>) -> Result<Self::Type, Error> {
+    fn parse(&self, context: &mut AssetLoadContext, data: Vec<u8>) -> Result<Self::Type, Error> {
         match self.source {
             DemSource::Usgs30m | DemSource::Usgs10m => parse_ned_zip(data),
-            DemSource::Srtm90m => parse_srtm3_hgt(self.latitude, self.longitude, data),
+            DemSource::Srtm90m => {
+                parse_srtm_hgt(self.latitude, self.longitude, data, self.fill_voids)
+            }
+            DemSource::AsterGdem => parse_aster_zip(context, self.latitude, self.longitude, data),
         }
     }
 }
@@ -210,7 +362,7 @@ fn parse_ned_zip(data: Vec<u8>) -> Result<Raster<f32>, Error> {
                     byte_order = match value {
                         "LSBFIRST" => Some(ByteOrder::LsbFirst),
                         "MSBFIRST" => Some(ByteOrder::MsbFirst),
-                        _ => panic!("unrecognized byte order: {}", value),
+                        _ => Err(DemError::UnrecognizedByteOrder(value.to_owned()))?,
                     }
                 }
                 _ => {}
@@ -218,74 +370,341 @@ fn parse_ned_zip(data: Vec<u8>) -> Result<Raster<f32>, Error> {
         }
     }
 
-    let width = width.ok_or(DemParseError)?;
-    let height = height.ok_or(DemParseError)?;
-    let xllcorner = xllcorner.ok_or(DemParseError)?;
-    let yllcorner = yllcorner.ok_or(DemParseError)?;
-    let cell_size = cell_size.ok_or(DemParseError)?;
-    let byte_order = byte_order.ok_or(DemParseError)?;
-    let nodata_value = nodata_value.ok_or(DemParseError)?;
+    let missing = |field: &str| DemError::MissingHeaderField(field.to_owned());
+    let width = width.ok_or_else(|| missing("ncols"))?;
+    let height = height.ok_or_else(|| missing("nrows"))?;
+    let xllcorner = xllcorner.ok_or_else(|| missing("xllcorner"))?;
+    let yllcorner = yllcorner.ok_or_else(|| missing("yllcorner"))?;
+    let cell_size = cell_size.ok_or_else(|| missing("cellsize"))?;
+    let byte_order = byte_order.ok_or_else(|| missing("byteorder"))?;
+    let nodata_value = nodata_value.ok_or_else(|| missing("NODATA_value"))?;
 
+    if flt.is_empty() {
+        Err(DemError::MissingDataFile)?;
+    }
     let size = width * height;
     if flt.len() != size * 4 {
-        Err(DemParseError)?;
+        Err(DemError::SizeMismatch { expected: size * 4, got: flt.len() })?;
     }
 
-    let flt: &[u32] = bytemuck::cast_slice(&flt[..]);
-    let mut elevations: Vec<f32> = Vec::with_capacity(size);
-    for f in flt {
-        let e = bytemuck::cast(match byte_order {
-            ByteOrder::LsbFirst => f.to_le(),
-            ByteOrder::MsbFirst => f.to_be(),
-        });
-        elevations.push(if e == nodata_value { 0.0 } else { e });
+    let mut elevations = match byte_order {
+        ByteOrder::LsbFirst => decode_f32le(&flt),
+        ByteOrder::MsbFirst => decode_f32be(&flt),
+    };
+    let mut void_cells = 0;
+    let mut nodata = BitVec::from_elem(elevations.len(), false);
+    for (i, e) in elevations.iter_mut().enumerate() {
+        if *e == nodata_value {
+            *e = 0.0;
+            void_cells += 1;
+            nodata.set(i, true);
+        }
     }
 
+    // The header gives the outer corner of the lower left cell with x as longitude, while rasters
+    // store the position of the lower left sample itself, so shift inwards by half a cell.
     Ok(Raster {
-        width,
-        height,
-        bands: 1,
-        latitude_llcorner: xllcorner,
-        longitude_llcorner: yllcorner,
-        cell_size,
-        values: elevations,
+        void_cells,
+        nodata: Some(nodata),
+        ..Raster::new(
+            width,
+            height,
+            1,
+            cell_size,
+            yllcorner + 0.5 * cell_size,
+            xllcorner + 0.5 * cell_size,
+            elevations,
+        )
     })
 }
 
-/// Load a HGT file in the format for the NASA's STRM 90m dataset.
-fn parse_srtm3_hgt(latitude: i16, longitude: i16, hgt: Vec<u8>) -> Result<Raster<f32>, Error> {
-    let resolution = 1201;
-    let cell_size = 1.0 / 1200.0;
+/// Decodes a buffer of little endian f32's. Trailing bytes that don't form a whole value are
+/// ignored.
+pub(crate) fn decode_f32le(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+}
 
-    if hgt.len() != resolution * resolution * 2 {
-        Err(DemParseError)?;
-    }
+/// Decodes a buffer of big endian f32's. Trailing bytes that don't form a whole value are ignored.
+pub(crate) fn decode_f32be(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|c| f32::from_be_bytes([c[0], c[1], c[2], c[3]])).collect()
+}
 
-    let hgt = bytemuck::cast_slice(&hgt[..]);
-    let mut elevations: Vec<f32> = Vec::with_capacity(resolution * resolution);
+/// Decodes a buffer of big endian i16's. Trailing bytes that don't form a whole value are ignored.
+pub(crate) fn decode_i16be(bytes: &[u8]) -> Vec<i16> {
+    bytes.chunks_exact(2).map(|c| i16::from_be_bytes([c[0], c[1]])).collect()
+}
 
-    for y in 0..resolution {
-        for x in 0..resolution {
-            let h = i16::from_be(hgt[x + y * resolution]);
-            if h == -32768 {
-                elevations.push(0.0);
-            } else {
-                elevations.push(h as f32);
+/// Returns the contents of an SRTM tile that may have been served gzipped (`.hgt.gz`) or zipped
+/// (`.hgt.zip`) rather than as a bare `.hgt` file. Data in neither format is returned unchanged.
+fn unwrap_hgt(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut hgt = Vec::new();
+        GzDecoder::new(Cursor::new(data)).read_to_end(&mut hgt)?;
+        Ok(hgt)
+    } else if data.starts_with(b"PK\x03\x04") {
+        let mut zip = ZipArchive::new(Cursor::new(data))?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.name().ends_with(".hgt") {
+                let mut hgt = Vec::new();
+                file.read_to_end(&mut hgt)?;
+                return Ok(hgt);
             }
         }
+        Err(DemError::MissingDataFile)?
+    } else {
+        Ok(data)
+    }
+}
+
+/// Load a HGT file in the format for NASA's SRTM datasets. The file may also be wrapped in gzip or
+/// zip compression. Tiles span one degree, and their resolution is worked out from the file size,
+/// so both SRTM1 (3601x3601) and SRTM3 (1201x1201) tiles are accepted.
+fn parse_srtm_hgt(
+    latitude: i16,
+    longitude: i16,
+    hgt: Vec<u8>,
+    fill_voids: bool,
+) -> Result<Raster<f32>, Error> {
+    let hgt = unwrap_hgt(hgt)?;
+    let resolution = hgt_resolution(hgt.len())?;
+    let cell_size = 1.0 / (resolution - 1) as f64;
+
+    let heights = decode_i16be(&hgt);
+    let void_cells = heights.iter().filter(|&&h| h == -32768).count();
+    let nodata = heights.iter().map(|&h| h == -32768).collect();
+    let elevations = heights.into_iter().map(|h| if h == -32768 { 0.0 } else { h as f32 });
+
+    let mut raster = Raster {
+        void_cells,
+        nodata: Some(nodata),
+        ..Raster::new(
+            resolution,
+            resolution,
+            1,
+            cell_size,
+            latitude as f64,
+            longitude as f64,
+            elevations.collect(),
+        )
+    };
+    if fill_voids {
+        raster.fill_voids();
+    }
+    Ok(raster)
+}
+
+/// Returns the side length of a square HGT file that is `len` bytes long.
+fn hgt_resolution(len: usize) -> Result<usize, DemError> {
+    let resolution = ((len / 2) as f64).sqrt().round() as usize;
+    if resolution < 2 || resolution * resolution * 2 != len {
+        return Err(DemError::NotSquare(len));
+    }
+    Ok(resolution)
+}
+
+/// Load a zip file containing a single ASTER GDEM v3 tile. Voids in this dataset have already been
+/// filled from other sources, but any remaining no-data cells are set to zero.
+fn parse_aster_zip(
+    context: &mut AssetLoadContext,
+    latitude: i16,
+    longitude: i16,
+    data: Vec<u8>,
+) -> Result<Raster<f32>, Error> {
+    let resolution = 3601;
+    let cell_size = 1.0 / 3600.0;
+
+    let mut tif = Vec::new();
+    let mut zip = ZipArchive::new(Cursor::new(data))?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if file.name().ends_with("_dem.tif") {
+            file.read_to_end(&mut tif)?;
+        }
+    }
+    if tif.is_empty() {
+        Err(DemError::MissingDataFile)?;
     }
 
+    let (width, height, heights) = decode_tiff_i16(context, "ASTER GDEM tile", tif)?;
+    if width != resolution || height != resolution {
+        Err(DemError::WrongDimensions { expected: resolution, width, height })?;
+    }
+
+    let void_cells = heights.iter().filter(|&&h| h == -9999).count();
+    let nodata = heights.iter().map(|&h| h == -9999).collect();
+    let elevations = heights.into_iter().map(|h| if h == -9999 { 0.0 } else { h as f32 }).collect();
+
     Ok(Raster {
-        width: resolution,
-        height: resolution,
-        bands: 1,
-        latitude_llcorner: latitude as f64,
-        longitude_llcorner: longitude as f64,
-        cell_size,
-        values: elevations,
+        void_cells,
+        nodata: Some(nodata),
+        ..Raster::new(
+            resolution,
+            resolution,
+            1,
+            cell_size,
+            latitude as f64,
+            longitude as f64,
+            elevations,
+        )
     })
 }
 
+/// Decodes a single band GeoTIFF of 16-bit samples, returning its width, height, and values.
+fn decode_tiff_i16(
+    context: &mut AssetLoadContext,
+    name: &str,
+    contents: Vec<u8>,
+) -> Result<(usize, usize, Vec<i16>), Error> {
+    context.reset(&format!("Decoding {}...", name), 100);
+    let mut tiff_decoder = tiff::decoder::Decoder::new(Cursor::new(contents))?;
+    let (width, height) = tiff_decoder.dimensions()?;
+
+    let mut offset = 0;
+    let mut values: Vec<i16> = vec![0; width as usize * height as usize];
+    let strip_count = tiff_decoder.strip_count()?;
+
+    context.set_progress_and_total(0, strip_count);
+    for i in 0..strip_count {
+        if let tiff::decoder::DecodingResult::U16(v) = tiff_decoder.read_strip()? {
+            context.set_progress(i);
+            values[offset..][..v.len()].copy_from_slice(bytemuck::cast_slice(&v));
+            offset += v.len();
+        } else {
+            Err(DemError::UnsupportedSampleFormat)?;
+        }
+    }
+
+    Ok((width as usize, height as usize, values))
+}
+
+/// Maximum number of smoothing sweeps made by `Raster::fill_voids`.
+const VOID_FILL_ITERATIONS: usize = 100;
+
+impl Raster<f32> {
+    /// Replaces the values of no-data cells with estimates from the cells around them and drops
+    /// the no-data mask. Voids are first filled from their edges inward, then smoothed with
+    /// Gauss-Seidel sweeps that set each one to the average of its four neighbors. Sweeps stop once
+    /// no value changes by more than a millimeter, or after `VOID_FILL_ITERATIONS`. Rasters without
+    /// a mask are left unchanged.
+    pub fn fill_voids(&mut self) {
+        assert_eq!(self.bands, 1);
+        let nodata = match self.nodata.take() {
+            Some(nodata) => nodata,
+            None => return,
+        };
+
+        let (width, height) = (self.width, self.height);
+        let values = self.values.iter().zip(nodata.iter());
+        let values = values.map(|(&v, void)| if void { None } else { Some(v) }).collect();
+        self.values = fill_voids_from_neighbors(values, width, height);
+
+        let voids: Vec<usize> = (0..nodata.len()).filter(|&i| nodata[i]).collect();
+        for _ in 0..VOID_FILL_ITERATIONS {
+            let mut max_change = 0.0f32;
+            for &i in &voids {
+                let (x, y) = (i % width, i / width);
+                let neighbors = [
+                    if x > 0 { Some(i - 1) } else { None },
+                    if x + 1 < width { Some(i + 1) } else { None },
+                    if y > 0 { Some(i - width) } else { None },
+                    if y + 1 < height { Some(i + width) } else { None },
+                ];
+                let neighbors: Vec<f32> =
+                    neighbors.iter().flatten().map(|&j| self.values[j]).collect();
+                if !neighbors.is_empty() {
+                    let value = neighbors.iter().sum::<f32>() / neighbors.len() as f32;
+                    max_change = max_change.max((value - self.values[i]).abs());
+                    self.values[i] = value;
+                }
+            }
+            if max_change <= 0.001 {
+                break;
+            }
+        }
+    }
+
+    /// Returns the elevation at the given position, interpolated bilinearly from the surrounding
+    /// cells that have data. The outer `None` means the position is outside the raster, while the
+    /// inner `None` means none of the surrounding cells had data. Unlike `interpolate`, this keeps
+    /// no-data cells apart from real elevations near zero, such as along coastlines.
+    pub fn get_elevation_raw(&self, latitude: f64, longitude: f64) -> Option<Option<f32>> {
+        let (x, y) = self.cell_offsets(latitude, longitude);
+        let y = (self.height - 1) as f64 - y;
+
+        let fx = x.floor() as usize;
+        let fy = y.floor() as usize;
+        if x < 0.0 || fx >= self.width || y < 0.0 || fy >= self.height {
+            return None;
+        }
+
+        let (tx, ty) = (x - fx as f64, y - fy as f64);
+        let fx_1 = (fx + 1).min(self.width - 1);
+        let fy_1 = (fy + 1).min(self.height - 1);
+        let cells = [
+            (fx + fy * self.width, (1.0 - tx) * (1.0 - ty)),
+            (fx_1 + fy * self.width, tx * (1.0 - ty)),
+            (fx + fy_1 * self.width, (1.0 - tx) * ty),
+            (fx_1 + fy_1 * self.width, tx * ty),
+        ];
+
+        let mut sum = 0.0;
+        let mut total_weight = 0.0;
+        for &(i, weight) in &cells {
+            if weight > 0.0 && !self.nodata.as_ref().map_or(false, |nodata| nodata[i]) {
+                sum += self.values[i * self.bands] as f64 * weight;
+                total_weight += weight;
+            }
+        }
+        Some(if total_weight > 0.0 { Some((sum / total_weight) as f32) } else { None })
+    }
+}
+
+/// Replaces missing values by repeatedly averaging the known values among their four neighbors, so
+/// voids are filled in from their edges inward. Cells that can't be reached from any known value
+/// are set to zero.
+fn fill_voids_from_neighbors(
+    mut values: Vec<Option<f32>>,
+    width: usize,
+    height: usize,
+) -> Vec<f32> {
+    let mut voids: Vec<usize> = (0..values.len()).filter(|&i| values[i].is_none()).collect();
+    while !voids.is_empty() {
+        let filled: Vec<(usize, Option<f32>)> = voids
+            .iter()
+            .map(|&i| {
+                let (x, y) = (i % width, i / width);
+                let neighbors = [
+                    if x > 0 { values[i - 1] } else { None },
+                    if x + 1 < width { values[i + 1] } else { None },
+                    if y > 0 { values[i - width] } else { None },
+                    if y + 1 < height { values[i + width] } else { None },
+                ];
+                let known: Vec<f32> = neighbors.iter().filter_map(|&v| v).collect();
+                if known.is_empty() {
+                    (i, None)
+                } else {
+                    (i, Some(known.iter().sum::<f32>() / known.len() as f32))
+                }
+            })
+            .collect();
+
+        let remaining = filled.iter().filter(|f| f.1.is_none()).count();
+        if remaining == voids.len() {
+            break;
+        }
+        voids.clear();
+        for (i, value) in filled {
+            match value {
+                Some(_) => values[i] = value,
+                None => voids.push(i),
+            }
+        }
+    }
+
+    values.into_iter().map(|v| v.unwrap_or(0.0)).collect()
+}
+
 pub struct GlobalDem;
 impl WebAsset for GlobalDem {
     type Type = GlobalRaster<i16>;
@@ -296,6 +715,9 @@ impl WebAsset for GlobalDem {
     fn filename(&self) -> String {
         "dems/ETOPO1_Ice_c_geotiff.zip".to_string()
     }
+    fn checksum(&self) -> Option<Checksum> {
+        cache::known_checksum(&self.filename())
+    }
     fn parse(&self, context: &mut AssetLoadContext, data: Vec<u8>) -> Result<Self::Type, Error> {
         let mut zip = ZipArchive::new(Cursor::new(data))?;
         ensure!(zip.len() == 1, "Unexpected zip file contents");
@@ -309,25 +731,247 @@ impl WebAsset for GlobalDem {
             context.set_progress(i * 4096);
         }
 
-        context.reset("Decoding ETOPO1_Ice_c_geotiff.tif...", 100);
-        let mut tiff_decoder = tiff::decoder::Decoder::new(Cursor::new(contents))?;
-        let (width, height) = tiff_decoder.dimensions()?;
+        let (width, height, values) =
+            decode_tiff_i16(context, "ETOPO1_Ice_c_geotiff.tif", contents)?;
+        Ok(GlobalRaster {
+            bands: 1,
+            width,
+            height,
+            planet_radius: coordinates::PLANET_RADIUS,
+            values,
+        })
+    }
+}
 
-        let mut offset = 0;
-        let mut values: Vec<i16> = vec![0; width as usize * height as usize];
-        let strip_count = tiff_decoder.strip_count()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::AssetLoadContextBuf;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
 
-        context.set_progress_and_total(0, strip_count);
-        for i in 0..strip_count {
-            if let tiff::decoder::DecodingResult::U16(v) = tiff_decoder.read_strip()? {
-                context.set_progress(i);
-                values[offset..][..v.len()].copy_from_slice(bytemuck::cast_slice(&v));
-                offset += v.len();
-            } else {
-                unreachable!();
+    const NED_HEADER: &[u8] = b"ncols 2\nnrows 2\nxllcorner -122\nyllcorner 37\ncellsize 0.5\n\
+                                NODATA_value -9999\nbyteorder LSBFIRST\n";
+
+    fn ned_zip(hdr: &[u8], flt: Option<&[f32]>) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("tile.hdr", FileOptions::default()).unwrap();
+        zip.write_all(hdr).unwrap();
+        if let Some(flt) = flt {
+            zip.start_file("tile.flt", FileOptions::default()).unwrap();
+            for v in flt {
+                zip.write_all(&v.to_le_bytes()).unwrap();
             }
         }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn decode_values() {
+        let le = [0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x20, 0xc1];
+        assert_eq!(decode_f32le(&le), vec![1.0, -10.0]);
+        let be = [0x3f, 0x80, 0x00, 0x00, 0x42, 0x28, 0x00, 0x00, 0xff];
+        assert_eq!(decode_f32be(&be), vec![1.0, 42.0]);
+        assert_eq!(decode_i16be(&[0x01, 0x00, 0x80, 0x00, 0xff, 0xff]), vec![256, -32768, -1]);
+    }
+
+    #[test]
+    fn fill_voids() {
+        let mut values = vec![Some(12.0); 25];
+        values[12] = None;
+        values[13] = None;
+        values[18] = None;
 
-        Ok(GlobalRaster { bands: 1, width: width as usize, height: height as usize, values })
+        let filled = fill_voids_from_neighbors(values, 5, 5);
+        assert_eq!(filled, vec![12.0; 25]);
+    }
+
+    #[test]
+    fn fill_voids_gradient() {
+        let mut values: Vec<f32> = (0..25).map(|i| (10 * (i % 5) + i / 5) as f32).collect();
+        let mut nodata = BitVec::from_elem(25, false);
+        values[12] = 0.0;
+        nodata.set(12, true);
+
+        let mut raster = Raster {
+            void_cells: 1,
+            nodata: Some(nodata),
+            ..Raster::new(5, 5, 1, 1.0, 0.0, 0.0, values)
+        };
+        raster.fill_voids();
+        assert_eq!(raster.values[12], 22.0);
+        assert!(raster.nodata.is_none());
+        assert_eq!(raster.get_elevation_raw(2.0, 2.0), Some(Some(22.0)));
+    }
+
+    #[test]
+    fn void_fraction() {
+        let cells = 1201 * 1201;
+        let mut hgt = Vec::with_capacity(cells * 2);
+        for i in 0..cells {
+            let h: i16 = if i < cells / 4 { -32768 } else { 100 };
+            hgt.extend_from_slice(&h.to_be_bytes());
+        }
+
+        let raster = parse_srtm_hgt(37, -122, hgt, false).unwrap();
+        assert!((raster.void_fraction() - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn compressed_hgt() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let hgt: Vec<u8> =
+            (0..1201 * 1201).flat_map(|i| ((i % 100) as i16).to_be_bytes().to_vec()).collect();
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+        gz.write_all(&hgt).unwrap();
+        let raster = parse_srtm_hgt(37, -122, gz.finish().unwrap(), false).unwrap();
+        assert_eq!(raster.values[..3], [0.0, 1.0, 2.0]);
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("N37W122.hgt", FileOptions::default()).unwrap();
+        zip.write_all(&hgt).unwrap();
+        let raster = parse_srtm_hgt(37, -122, zip.finish().unwrap().into_inner(), false).unwrap();
+        assert_eq!(raster.values[1201], 1.0);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+        gz.write_all(&hgt[..1000]).unwrap();
+        let error = parse_srtm_hgt(37, -122, gz.finish().unwrap(), false).unwrap_err();
+        match error.downcast::<DemError>() {
+            Ok(DemError::NotSquare(1000)) => {}
+            e => panic!("unexpected result: {:?}", e.map(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn hgt_resolutions() {
+        assert_eq!(hgt_resolution(3601 * 3601 * 2).unwrap(), 3601);
+        assert_eq!(hgt_resolution(1201 * 1201 * 2).unwrap(), 1201);
+        assert!(matches!(hgt_resolution(1201 * 1201), Err(DemError::NotSquare(_))));
+        assert!(matches!(hgt_resolution(1201 * 1200 * 2), Err(DemError::NotSquare(_))));
+        assert!(matches!(hgt_resolution(2), Err(DemError::NotSquare(2))));
+
+        let hgt: Vec<u8> = (0..25).flat_map(|i| (i as i16).to_be_bytes().to_vec()).collect();
+        let raster = parse_srtm_hgt(37, -122, hgt, false).unwrap();
+        assert_eq!((raster.width, raster.height), (5, 5));
+        assert_eq!(raster.cell_size, 0.25);
+        assert_eq!(raster.values[24], 24.0);
+    }
+
+    #[test]
+    fn estimate_bytes() {
+        let tile = DemSource::Usgs30m.typical_tile_bytes();
+        assert_eq!(DemSource::Usgs30m.estimate_bytes(37.0, -122.0, 39.0, -120.0), 4 * tile);
+        assert_eq!(DemSource::Usgs30m.estimate_bytes(37.5, -121.5, 38.5, -120.5), 4 * tile);
+        assert_eq!(DemSource::Usgs30m.estimate_bytes(37.2, -121.8, 37.4, -121.6), tile);
+
+        // Only one of these two tiles contains land.
+        let tile = DemSource::Srtm90m.typical_tile_bytes();
+        assert_eq!(DemSource::Srtm90m.estimate_bytes(0.0, 5.0, 1.0, 7.0), tile);
+    }
+
+    #[test]
+    fn missing_srtm_tile() {
+        // Over the ocean, so the tile is absent without needing to download anything.
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+        assert!(!DemSource::Srtm90m.has_tile(0, 5));
+        assert!(DemSource::Srtm90m.try_load(&mut context, 0, 5).unwrap().is_none());
+    }
+
+    #[test]
+    fn aster_urls() {
+        let params = |latitude, longitude| DigitalElevationModelParams {
+            latitude,
+            longitude,
+            source: DemSource::AsterGdem,
+            fill_voids: false,
+        };
+        assert!(params(-34, 18).url().ends_with("/ASTGTMV003_S34E018.zip"));
+        assert!(params(37, -122).url().ends_with("/ASTGTMV003_N37W122.zip"));
+        assert_eq!(params(-34, 18).filename(), "dems/astgtm3/s34_e018.zip");
+    }
+
+    #[test]
+    fn local_source() {
+        let root = std::env::temp_dir().join("terra-local-dem-test");
+        fs::create_dir_all(&root).unwrap();
+        let source = LocalDemSource { root: root.clone() };
+        assert_eq!(source.path(37, -122), root.join("n37_w122.zip"));
+        assert_eq!(source.path(-1, 5), root.join("s01_e005.zip"));
+
+        let zip = ned_zip(NED_HEADER, Some(&[1.0, 2.0, 3.0, -9999.0]));
+        fs::write(source.path(37, -122), zip).unwrap();
+
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+        let raster = source.load(&mut context, 37, -122).unwrap();
+        assert_eq!(raster.values, vec![1.0, 2.0, 3.0, 0.0]);
+        assert_eq!(raster.void_cells, 1);
+        assert!(source.load(&mut context, 38, -122).is_none());
+
+        // A file that exists but can't be parsed is an error rather than a missing tile.
+        assert!(source.try_load(&mut context, 38, -122).unwrap().is_none());
+        fs::write(source.path(38, -122), b"not a zip").unwrap();
+        assert!(source.try_load(&mut context, 38, -122).is_err());
+        fs::remove_file(source.path(38, -122)).unwrap();
+    }
+
+    #[test]
+    fn elevation_raw() {
+        let raster = parse_ned_zip(ned_zip(NED_HEADER, Some(&[0.0, 2.0, 3.0, -9999.0]))).unwrap();
+        let (lat, lon) = (raster.latitude_llcorner, raster.longitude_llcorner);
+
+        assert_eq!(raster.get_elevation_raw(lat + 0.5, lon), Some(Some(0.0)));
+        assert_eq!(raster.get_elevation_raw(lat, lon), Some(Some(3.0)));
+        assert_eq!(raster.get_elevation_raw(lat, lon + 0.5), Some(None));
+        let mixed = raster.get_elevation_raw(lat + 0.25, lon + 0.25).unwrap().unwrap();
+        assert!((mixed - 5.0 / 3.0).abs() < 1e-6);
+        assert_eq!(raster.get_elevation_raw(lat, lon - 1.0), None);
+    }
+
+    #[test]
+    fn ned_georeference() {
+        let raster = parse_ned_zip(ned_zip(NED_HEADER, Some(&[0.0, 1.0, 2.0, 3.0]))).unwrap();
+        assert_eq!((raster.latitude_llcorner, raster.longitude_llcorner), (37.25, -121.75));
+
+        // Look up the cell containing each point the way the header describes the grid, and check
+        // that the raster agrees.
+        let points = [(37.6, -121.6), (37.6, -121.4), (37.3, -121.3), (37.4, -121.7)];
+        for &(latitude, longitude) in &points {
+            let column = ((longitude - -122.0) / 0.5).floor() as usize;
+            let row = ((38.0 - latitude) / 0.5).floor() as usize;
+            let expected = raster.values[column + row * 2] as f64;
+            assert_eq!(raster.interpolate_nearest(latitude, longitude, 0), Some(expected));
+        }
+        assert_eq!(raster.interpolate(37.75, -121.75, 0), Some(0.0));
+        assert_eq!(raster.interpolate(37.5, -121.5, 0), Some(1.5));
+    }
+
+    #[test]
+    fn ned_errors() {
+        let error = |data: Vec<u8>| match parse_ned_zip(data).unwrap_err().downcast::<DemError>() {
+            Ok(e) => e,
+            Err(e) => panic!("unexpected error: {}", e),
+        };
+
+        match error(ned_zip(NED_HEADER, Some(&[1.0, 2.0, 3.0]))) {
+            DemError::SizeMismatch { expected: 16, got: 12 } => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        match error(ned_zip(NED_HEADER, None)) {
+            DemError::MissingDataFile => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        match error(ned_zip(b"ncols 2\nnrows 2\n", Some(&[0.0; 4]))) {
+            DemError::MissingHeaderField(field) => assert_eq!(field, "xllcorner"),
+            e => panic!("unexpected error: {}", e),
+        }
+        let hdr = String::from_utf8(NED_HEADER.to_vec()).unwrap().replace("LSBFIRST", "VAX");
+        match error(ned_zip(hdr.as_bytes(), Some(&[0.0; 4]))) {
+            DemError::UnrecognizedByteOrder(order) => assert_eq!(order, "VAX"),
+            e => panic!("unexpected error: {}", e),
+        }
     }
 }
@@ -1,5 +1,6 @@
 use rand::distributions::Distribution;
-use rand::{self, Rng};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rand_distr::Normal;
 
 use std::f32::consts::PI;
@@ -84,8 +85,11 @@ impl<T: Copy + Add<T, Output = T> + Div<T, Output = T> + From<u8>> Heightmap<T>
     }
 }
 
+/// Evaluate Perlin noise on a grid with the given resolution and grid spacing. The gradients are
+/// drawn from a ChaCha8 generator initialized with `seed`, so the same arguments always give the
+/// same output, even across versions of `rand`.
 #[allow(dead_code)]
-pub fn perlin_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f32> {
+pub fn perlin_noise(grid_resolution: usize, grid_spacing: usize, seed: u64) -> Heightmap<f32> {
     fn dot(a: (f32, f32), b: (f32, f32)) -> f32 {
         a.0 * b.0 + a.1 * b.1
     }
@@ -99,7 +103,7 @@ pub fn perlin_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f3
         a * (1.0 - t) + b * t
     }
 
-    let mut rng = rand::thread_rng();
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
     let gradients: Vec<(f32, f32)> = (0..(grid_resolution * grid_resolution))
         .map(|_| rng.gen_range(0.0, 2.0 * PI).sin_cos())
         .collect();
@@ -143,8 +147,9 @@ pub fn perlin_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f3
 
 /// Evaluate wavelet noise on a grid with the given resolution and grid spacing. ///
 /// The output heightmap will have a width and height of `grid_resolution` * `grid_spacing`. Values
-/// will have a mean of approximately zero, and a variance of 1.
-pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f32> {
+/// will have a mean of approximately zero, and a variance of 1. The output is fully determined by
+/// the arguments, including `seed`, which initializes a ChaCha8 generator.
+pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize, seed: u64) -> Heightmap<f32> {
     // See: https://graphics.pixar.com/library/WaveletNoise/paper.pdf
 
     fn modulo(x: i32, n: usize) -> usize {
@@ -183,7 +188,7 @@ pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f
             }
         }
     }
-    fn generate_noise_tile(n: usize) -> Vec<f32> {
+    fn generate_noise_tile(n: usize, seed: u64) -> Vec<f32> {
         assert!(n % 2 == 0); // size must be even!
 
         let mut temp1 = vec![0.0; n * n];
@@ -192,8 +197,9 @@ pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f
 
         // Step 1. Fill the tile with random numbers in the range -1 to 1.
         let normal = Normal::new(0.0, 1.0).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
         for _ in 0..(n * n) {
-            noise.push(normal.sample(&mut rand::thread_rng()) as f32);
+            noise.push(normal.sample(&mut rng) as f32);
         }
 
         // Steps 2 and 3. Downsample and upsample the tile
@@ -261,7 +267,7 @@ pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f
         result
     }
 
-    let noise_tile = generate_noise_tile(grid_resolution);
+    let noise_tile = generate_noise_tile(grid_resolution, seed);
 
     let mut heights = Vec::new();
     for x in 0..(grid_resolution * grid_spacing) {
@@ -290,3 +296,16 @@ pub fn wavelet_noise(grid_resolution: usize, grid_spacing: usize) -> Heightmap<f
         height: (grid_resolution * grid_spacing) as u16,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_noise() {
+        assert_eq!(wavelet_noise(16, 2, 7).heights, wavelet_noise(16, 2, 7).heights);
+        assert_ne!(wavelet_noise(16, 2, 7).heights, wavelet_noise(16, 2, 8).heights);
+        assert_eq!(perlin_noise(4, 4, 7).heights, perlin_noise(4, 4, 7).heights);
+        assert_ne!(perlin_noise(4, 4, 7).heights, perlin_noise(4, 4, 8).heights);
+    }
+}
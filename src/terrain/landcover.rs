@@ -7,6 +7,7 @@ use memmap::Mmap;
 use zip::ZipArchive;
 
 use crate::cache::{AssetLoadContext, WebAsset};
+use crate::coordinates;
 use crate::terrain::raster::{
     BitContainer, GlobalRaster, MMappedRasterHeader, Raster, RasterSource,
 };
@@ -40,7 +41,13 @@ impl WebAsset for BlueMarble {
             }
         }
 
-        Ok(GlobalRaster { width, height, bands: 3, values })
+        Ok(GlobalRaster {
+            width,
+            height,
+            bands: 3,
+            planet_radius: coordinates::PLANET_RADIUS,
+            values,
+        })
     }
 }
 
@@ -99,6 +106,7 @@ impl WebAsset for BlueMarbleTile {
                 cell_size: 90.0 / 21600.0,
                 latitude_llcorner: self.latitude_llcorner as f64,
                 longitude_llcorner: self.longitude_llcorner as f64,
+                planet_radius: coordinates::PLANET_RADIUS,
             },
             values,
         ))
@@ -158,6 +166,7 @@ impl WebAsset for GlobalWaterMask {
                 width,
                 height,
                 bands: 1,
+                planet_radius: coordinates::PLANET_RADIUS,
                 values: BitContainer(image.into_raw().into_iter().map(|v| v == 0).collect()),
             })
         } else {
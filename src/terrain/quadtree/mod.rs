@@ -1,8 +1,10 @@
-// use crate::coordinates::PLANET_RADIUS;
+use crate::generate::EARTH_CIRCUMFERENCE;
 use crate::terrain::tile_cache::LayerType;
 use crate::terrain::tile_cache::{Priority, TileCache};
+use anyhow::{ensure, Error};
 use cgmath::*;
 use collision::Frustum;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
 pub(crate) mod node;
@@ -11,17 +13,53 @@ pub(crate) mod render;
 pub(crate) use crate::terrain::quadtree::node::*;
 pub(crate) use crate::terrain::quadtree::render::*;
 
+/// Deepest level nodes are loaded and rendered at unless configured otherwise.
+pub(crate) const DEFAULT_MAX_LEVEL: u8 = 22;
+
+/// Deepest level a `VNode` can address.
+pub(crate) const MAX_LEVEL: u8 = 25;
+
+/// Largest anisotropic filtering level that samplers accept.
+pub(crate) const MAX_ANISOTROPY: u8 = 16;
+
+/// Range that the detail bias is clamped to. Beyond it the scaled cutoff either keeps every node
+/// down to the maximum level or culls everything but the roots.
+const MIN_DETAIL_BIAS: f32 = 1.0 / 64.0;
+const MAX_DETAIL_BIAS: f32 = 64.0;
+
+/// Clamps `level` to `[1, MAX_ANISOTROPY]`, rounding down to the nearest valid power of two.
+fn clamp_anisotropy(level: u8) -> u8 {
+    let level = level.max(1).min(MAX_ANISOTROPY);
+    1 << (7 - level.leading_zeros())
+}
+
+/// Computes how much a node is needed given the position of the camera in cspace. Nodes with
+/// priority below `Priority::cutoff()`, scaled by the quadtree's detail bias, are not loaded or
+/// rendered.
+pub(crate) type PriorityFn = dyn Fn(&VNode, Point3<f64>) -> Priority + Send + Sync;
+
 /// The central object in terra. It holds all relevant state and provides functions to update and
 /// render the terrain.
 pub(crate) struct QuadTree {
     // ocean: Ocean<R>,
-    /// List of nodes that will be rendered.
+    /// List of nodes that will be rendered, sorted from highest to lowest priority.
     visible_nodes: Vec<VNode>,
     partially_visible_nodes: Vec<(VNode, u8)>,
 
     heights_resolution: u32,
 
     node_states: Vec<NodeState>,
+
+    /// Anisotropic filtering level used by the samplers for terrain tiles.
+    anisotropy: u8,
+
+    /// Multiplier applied to node priorities before comparing them to `Priority::cutoff()`.
+    detail_bias: f32,
+
+    /// Deepest level that nodes are loaded at.
+    max_level: u8,
+
+    priority_fn: Box<PriorityFn>,
 }
 
 impl std::fmt::Debug for QuadTree {
@@ -33,12 +71,81 @@ impl std::fmt::Debug for QuadTree {
 #[allow(unused)]
 impl QuadTree {
     pub(crate) fn new(heights_resolution: u32) -> Self {
-        Self {
+        Self::with_params(heights_resolution, DEFAULT_MAX_LEVEL).unwrap()
+    }
+
+    /// Like `new`, but only subdivides nodes down to `max_level` rather than `DEFAULT_MAX_LEVEL`.
+    /// Each level halves the side length of a node, starting from a quarter of the planet's
+    /// circumference at level 0. Fails if `max_level` is greater than `MAX_LEVEL`.
+    pub(crate) fn with_params(heights_resolution: u32, max_level: u8) -> Result<Self, Error> {
+        let mut quadtree = Self {
             visible_nodes: Vec::new(),
             partially_visible_nodes: Vec::new(),
             node_states: Vec::new(),
             heights_resolution,
-        }
+            anisotropy: 1,
+            detail_bias: 1.0,
+            max_level: DEFAULT_MAX_LEVEL,
+            priority_fn: Box::new(|node, camera| node.priority(camera)),
+        };
+        quadtree.set_max_level(max_level)?;
+        Ok(quadtree)
+    }
+
+    pub fn max_level(&self) -> u8 {
+        self.max_level
+    }
+
+    /// Changes the deepest level that nodes are loaded at. Takes effect on the next call to
+    /// `update_cache`. Fails without changing anything if `max_level` is greater than `MAX_LEVEL`.
+    pub fn set_max_level(&mut self, max_level: u8) -> Result<(), Error> {
+        ensure!(
+            max_level <= MAX_LEVEL,
+            "quadtree depth of {} levels exceeds the maximum of {}",
+            max_level,
+            MAX_LEVEL
+        );
+        self.max_level = max_level;
+        Ok(())
+    }
+
+    /// Replaces the function used to decide which nodes to load and render. Defaults to
+    /// `VNode::priority`, which compares a node's distance from the camera to its size.
+    pub fn set_priority_fn(
+        &mut self,
+        priority_fn: impl Fn(&VNode, Point3<f64>) -> Priority + Send + Sync + 'static,
+    ) {
+        self.priority_fn = Box::new(priority_fn);
+    }
+
+    /// Sets the anisotropic filtering level for terrain samplers. The level is clamped to
+    /// `[1, MAX_ANISOTROPY]`, the range wgpu accepts for sampler anisotropy, and rounded down to a
+    /// power of two. Returns the level applied.
+    pub fn set_anisotropy(&mut self, level: u8) -> u8 {
+        self.anisotropy = clamp_anisotropy(level);
+        self.anisotropy
+    }
+
+    pub fn anisotropy(&self) -> u8 {
+        self.anisotropy
+    }
+
+    /// Scales how much detail is loaded and rendered. Values above 1.0 keep nodes that would
+    /// otherwise fall below the priority cutoff, values below 1.0 cull more aggressively. The bias
+    /// is clamped to `[MIN_DETAIL_BIAS, MAX_DETAIL_BIAS]`, and NaN is treated as the minimum.
+    /// Returns the bias applied.
+    pub fn set_detail_bias(&mut self, bias: f32) -> f32 {
+        self.detail_bias = bias.max(MIN_DETAIL_BIAS).min(MAX_DETAIL_BIAS);
+        self.detail_bias
+    }
+
+    pub fn detail_bias(&self) -> f32 {
+        self.detail_bias
+    }
+
+    /// Smallest priority a node can have and still be loaded or rendered.
+    fn cutoff(&self) -> Priority {
+        Priority::cutoff().scaled(1.0 / self.detail_bias)
     }
 
     pub(crate) fn create_index_buffers(
@@ -81,17 +188,20 @@ impl QuadTree {
         let r = camera.x.abs().max(camera.y.abs()).max(camera.z.abs());
         let camera = Point3::new(camera.x / r, camera.y / r, camera.z / r);
 
-        tile_cache.update_priorities(camera);
+        let cutoff = self.cutoff();
+        let max_level = self.max_level;
+        let priority_fn = &*self.priority_fn;
+        tile_cache.update_priorities(camera, priority_fn);
 
         VNode::breadth_first(|node| {
-            let priority = node.priority(camera);
-            if priority < Priority::cutoff() {
+            let priority = priority_fn(&node, camera);
+            if priority < cutoff {
                 return false;
             }
 
             tile_cache.add_missing((priority, node));
 
-            if node.level() >= 22 {
+            if node.level() >= max_level {
                 return false;
             }
 
@@ -99,6 +209,40 @@ impl QuadTree {
         });
     }
 
+    /// Estimates how many tile cache slots will be in use once every node within `view_distance`
+    /// meters of the camera that passes the priority cutoff has been loaded. Each slot holds one
+    /// tile of every layer. The count depends on where the camera is relative to the cube faces,
+    /// so this returns the largest count among cameras over the center, edge and corner of a face.
+    pub fn required_cache_size(&self, view_distance: f32) -> usize {
+        // Each cube face spans [-1, 1] and covers a quarter of the circumference.
+        let max_distance = view_distance as f64 / (EARTH_CIRCUMFERENCE / 8.0);
+        let max_distance2 = max_distance * max_distance;
+
+        let cameras =
+            [Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(1.0, 1.0, 1.0)];
+        let cutoff = self.cutoff();
+        let max_level = self.max_level;
+        let priority_fn = &*self.priority_fn;
+        cameras
+            .iter()
+            .map(|&camera| {
+                let mut count = 0;
+                VNode::breadth_first(|node| {
+                    if priority_fn(&node, camera) < cutoff
+                        || (node.level() > 0 && node.distance2_cspace(camera) > max_distance2)
+                    {
+                        return false;
+                    }
+
+                    count += 1;
+                    node.level() < max_level
+                });
+                count
+            })
+            .max()
+            .unwrap()
+    }
+
     pub fn update_visibility(
         &mut self,
         tile_cache: &TileCache,
@@ -115,8 +259,10 @@ impl QuadTree {
         let mut node_visibilities: HashMap<VNode, bool> = HashMap::new();
 
         // Any node with all needed layers in cache is visible...
+        let cutoff = self.cutoff();
+        let priority_fn = &*self.priority_fn;
         VNode::breadth_first(|node| {
-            let visible = node.level() == 0 || node.priority(camera) >= Priority::cutoff();
+            let visible = node.level() == 0 || priority_fn(&node, camera) >= cutoff;
             node_visibilities.insert(node, visible);
             visible
         });
@@ -160,6 +306,41 @@ impl QuadTree {
                 false
             }
         });
+
+        // Stable sorts, so nodes of equal priority stay in breadth first order.
+        let priority_fn = &*self.priority_fn;
+        self.visible_nodes.sort_by_cached_key(|node| Reverse(priority_fn(node, camera)));
+        self.partially_visible_nodes
+            .sort_by_cached_key(|(node, _)| Reverse(priority_fn(node, camera)));
+    }
+
+    /// Returns the nodes drawn in full after the last call to `update_visibility`, from highest to
+    /// lowest priority, which for the default priority function means front to back.
+    pub fn visible_nodes(&self) -> &[VNode] {
+        &self.visible_nodes
+    }
+
+    /// Returns the nodes of which only some quadrants were drawn after the last call to
+    /// `update_visibility`, along with a mask of the drawn quadrants. Sorted the same way as
+    /// `visible_nodes`.
+    pub fn partially_visible_nodes(&self) -> &[(VNode, u8)] {
+        &self.partially_visible_nodes
+    }
+
+    /// Returns the latitude and longitude bounds of `node` in degrees, as described by
+    /// `VNode::geographic_bounds`. Useful for finding which dataset tiles a node touches.
+    pub fn node_geographic_bounds(&self, node: VNode) -> (f64, f64, f64, f64) {
+        node.geographic_bounds()
+    }
+
+    /// Returns each node drawn after the last call to `update_visibility` along with its level.
+    /// Partially visible nodes are included since some of their quadrants are still rendered.
+    pub fn last_rendered_levels(&self) -> Vec<(VNode, u8)> {
+        self.visible_nodes
+            .iter()
+            .chain(self.partially_visible_nodes.iter().map(|(node, _)| node))
+            .map(|node| (*node, node.level()))
+            .collect()
     }
 
     // pub fn get_height(
@@ -226,3 +407,143 @@ impl QuadTree {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec_map::VecMap;
+
+    #[test]
+    fn required_cache_size() {
+        let quadtree = QuadTree::new(65);
+        let near = quadtree.required_cache_size(10_000.0);
+        let far = quadtree.required_cache_size(1_000_000.0);
+        assert!(near <= far);
+
+        // At least the nodes that could be drawn from directly above a face center must fit.
+        let camera = Point3::new(1.0, 0.0, 0.0);
+        let mut visible = 0;
+        VNode::breadth_first(|node| {
+            if node.priority(camera) < Priority::cutoff() {
+                return false;
+            }
+            if node.distance2_cspace(camera) == 0.0 {
+                visible += 1;
+            }
+            node.level() < 22
+        });
+        assert!(near >= visible);
+    }
+
+    /// Loads and marks valid every node the quadtree requests, then returns the rendered levels.
+    fn render_all(quadtree: &mut QuadTree, camera: mint::Point3<f64>) -> Vec<(VNode, u8)> {
+        let mut tile_cache = TileCache::new(VecMap::new(), 100_000).unwrap();
+        quadtree.update_cache(&mut tile_cache, camera);
+        tile_cache.process_missing();
+
+        VNode::breadth_first(|node| match tile_cache.get_slot(node) {
+            Some(slot) => {
+                tile_cache.set_slot_valid(slot, LayerType::Displacements);
+                true
+            }
+            None => false,
+        });
+        quadtree.update_visibility(&tile_cache, camera, None);
+        quadtree.last_rendered_levels()
+    }
+
+    #[test]
+    fn last_rendered_levels() {
+        let mut quadtree = QuadTree::new(65);
+        let levels = render_all(&mut quadtree, mint::Point3 { x: 1.0, y: 0.0, z: 0.0 });
+
+        let camera = Point3::new(1.0, 0.0, 0.0);
+        let near = levels.iter().filter(|(n, _)| n.distance2_cspace(camera) == 0.0).map(|l| l.1);
+        let far = levels.iter().filter(|(n, _)| n.distance2_cspace(camera) > 0.25).map(|l| l.1);
+        assert!(near.min().unwrap() > far.max().unwrap());
+    }
+
+    #[test]
+    fn visible_nodes_sorted() {
+        let camera = mint::Point3 { x: 1.0, y: 0.0, z: 0.0 };
+        let mut quadtree = QuadTree::new(65);
+        render_all(&mut quadtree, camera);
+
+        let camera = Point3::new(1.0, 0.0, 0.0);
+        let nodes = quadtree.visible_nodes();
+        assert!(!nodes.is_empty());
+        assert!(nodes.windows(2).all(|w| w[0].priority(camera) >= w[1].priority(camera)));
+        assert_eq!(
+            nodes.len() + quadtree.partially_visible_nodes().len(),
+            quadtree.last_rendered_levels().len()
+        );
+    }
+
+    #[test]
+    fn max_level() {
+        assert_eq!(QuadTree::new(65).max_level(), DEFAULT_MAX_LEVEL);
+        assert!(QuadTree::with_params(65, MAX_LEVEL).is_ok());
+        assert!(QuadTree::with_params(65, MAX_LEVEL + 1).is_err());
+
+        let mut quadtree = QuadTree::new(65);
+        assert!(quadtree.set_max_level(MAX_LEVEL + 1).is_err());
+        assert_eq!(quadtree.max_level(), DEFAULT_MAX_LEVEL);
+        quadtree.set_max_level(4).unwrap();
+        assert_eq!(quadtree.max_level(), 4);
+
+        let camera = mint::Point3 { x: 1.0, y: 0.0, z: 0.0 };
+        let mut shallow = QuadTree::with_params(65, 4).unwrap();
+        let levels = render_all(&mut shallow, camera);
+        assert_eq!(levels.iter().map(|&(_, level)| level).max(), Some(4));
+        assert!(render_all(&mut QuadTree::new(65), camera).iter().any(|&(_, level)| level > 4));
+
+        let shallow = QuadTree::with_params(65, 4).unwrap();
+        let default = QuadTree::new(65);
+        assert!(shallow.required_cache_size(1e6) < default.required_cache_size(1e6));
+    }
+
+    #[test]
+    fn custom_priority_fn() {
+        let camera = mint::Point3 { x: 1.0, y: 0.0, z: 0.0 };
+        let mut quadtree = QuadTree::new(65);
+        assert!(render_all(&mut quadtree, camera).iter().any(|&(_, level)| level > 2));
+
+        quadtree.set_priority_fn(|node, _| {
+            if node.level() <= 2 {
+                Priority::from_f32(2.0)
+            } else {
+                Priority::none()
+            }
+        });
+        let levels = render_all(&mut quadtree, camera);
+        assert_eq!(levels.len(), 6 * 16);
+        assert!(levels.iter().all(|&(_, level)| level == 2));
+    }
+
+    #[test]
+    fn detail_bias() {
+        let camera = mint::Point3 { x: 1.0, y: 0.0, z: 0.0 };
+        let mut quadtree = QuadTree::new(65);
+        let default = render_all(&mut quadtree, camera).len();
+        quadtree.set_detail_bias(4.0);
+        assert!(render_all(&mut quadtree, camera).len() > default);
+        quadtree.set_detail_bias(0.25);
+        assert!(render_all(&mut quadtree, camera).len() < default);
+
+        assert_eq!(quadtree.set_detail_bias(0.0), MIN_DETAIL_BIAS);
+        assert_eq!(quadtree.set_detail_bias(-3.0), MIN_DETAIL_BIAS);
+        assert_eq!(quadtree.set_detail_bias(std::f32::NAN), MIN_DETAIL_BIAS);
+        assert_eq!(quadtree.set_detail_bias(std::f32::INFINITY), MAX_DETAIL_BIAS);
+        assert_eq!(quadtree.detail_bias(), MAX_DETAIL_BIAS);
+    }
+
+    #[test]
+    fn anisotropy_clamped() {
+        let mut quadtree = QuadTree::new(65);
+        assert_eq!(quadtree.anisotropy(), 1);
+        assert_eq!(quadtree.set_anisotropy(64), MAX_ANISOTROPY);
+        assert_eq!(quadtree.set_anisotropy(6), 4);
+        assert_eq!(quadtree.set_anisotropy(0), 1);
+        assert_eq!(quadtree.anisotropy(), 1);
+    }
+}
@@ -1,11 +1,21 @@
+use crate::coordinates::PLANET_RADIUS;
 use crate::generate::EARTH_CIRCUMFERENCE;
 use crate::terrain::tile_cache::Priority;
+use crate::utils::math::BoundingBox;
 use cgmath::*;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
 const ROOT_SIDE_LENGTH: f32 = (EARTH_CIRCUMFERENCE * 0.25) as f32;
 
+/// Range of elevations in meters that node bounds allow for, from a little below the deepest ocean
+/// trench to a little above the highest mountain.
+const MIN_ELEVATION: f64 = -11000.0;
+const MAX_ELEVATION: f64 = 9000.0;
+
+/// Number of samples along each edge of a node used to compute its bounds.
+const BOUNDS_SAMPLES: u16 = 9;
+
 lazy_static! {
     pub static ref OFFSETS: [Vector2<i32>; 4] =
         [Vector2::new(0, 0), Vector2::new(1, 0), Vector2::new(0, 1), Vector2::new(1, 1),];
@@ -13,8 +23,10 @@ lazy_static! {
         [Vector2::new(-1, -1), Vector2::new(1, -1), Vector2::new(-1, 1), Vector2::new(1, 1),];
 }
 
+/// A node of the quadtree that terrain is loaded and rendered from. Each of the six faces of a cube
+/// around the planet is the root of a tree, and every level halves the side length of its parent.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize)]
-pub(crate) struct VNode(u64);
+pub struct VNode(u64);
 
 impl VNode {
     fn new(level: u8, face: u8, x: u32, y: u32) -> Self {
@@ -134,11 +146,139 @@ impl VNode {
         self.fspace_to_cspace(fx, fy)
     }
 
-    /// How much this node is needed for the current frame. Nodes with priority less than 1.0 will
-    /// not be rendered (they are too detailed).
-    pub fn priority(&self, camera_cspace: Point3<f64>) -> Priority {
-        let min_distance = self.min_distance();
+    /// Latitude and longitude bounds of this node in degrees, returned as `(min_latitude,
+    /// max_latitude, min_longitude, max_longitude)`. Nodes that straddle the antimeridian have
+    /// `min_longitude > max_longitude`, and nodes that contain or come close to a pole span every
+    /// longitude.
+    ///
+    /// The bounds are found by sampling the node's edges and then padded so that they are never
+    /// smaller than the node, at the cost of being up to half a sample spacing too large.
+    pub fn geographic_bounds(&self) -> (f64, f64, f64, f64) {
+        const SAMPLES: u32 = 64;
+
+        let scale = 2.0 / (1u32 << self.level()) as f64;
+        let fx0 = self.x() as f64 * scale - 1.0;
+        let fy0 = self.y() as f64 * scale - 1.0;
+
+        // Latitude and longitude extremes of a region that doesn't contain a pole always lie on
+        // its boundary, so it suffices to walk the edges.
+        let mut latitudes = Vec::with_capacity(4 * SAMPLES as usize);
+        let mut longitudes = Vec::with_capacity(4 * SAMPLES as usize);
+        let mut max_spacing: f64 = 0.0;
+        let edges: [fn(f64) -> (f64, f64); 4] =
+            [|t| (t, 0.0), |t| (1.0, t), |t| (1.0 - t, 1.0), |t| (0.0, 1.0 - t)];
+        for edge in &edges {
+            let mut previous: Option<Vector3<f64>> = None;
+            for i in 0..=SAMPLES {
+                let (fx, fy) = edge(i as f64 / SAMPLES as f64);
+                let p = self.fspace_to_cspace(fx0 + fx * scale, fy0 + fy * scale).normalize();
+                if let Some(previous) = previous {
+                    max_spacing = max_spacing.max(previous.dot(p).min(1.0).acos());
+                }
+                previous = Some(p);
+                latitudes.push(p.z.asin().to_degrees());
+                longitudes.push(p.y.atan2(p.x).to_degrees());
+            }
+        }
+
+        // Edges are great circle arcs, so every point on one is within half a sample spacing of a
+        // sample. Latitude changes by at most that angle, and longitude by at most that angle
+        // divided by the cosine of the latitude.
+        let padding = 0.5 * max_spacing;
+        let min_latitude = latitudes.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max_latitude = latitudes.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let mut min_latitude = (min_latitude - padding.to_degrees()).max(-90.0);
+        let mut max_latitude = (max_latitude + padding.to_degrees()).min(90.0);
+        let contains_origin = |f0: f64| f0 <= 0.0 && f0 + scale >= 0.0;
+        let contains_pole = self.face() >= 4 && contains_origin(fx0) && contains_origin(fy0);
+        if contains_pole {
+            if self.face() == 4 {
+                max_latitude = 90.0;
+            } else {
+                min_latitude = -90.0;
+            }
+            return (min_latitude, max_latitude, -180.0, 180.0);
+        }
+        let max_abs_latitude = min_latitude.abs().max(max_latitude.abs()).to_radians();
+        let longitude_padding = padding.sin() / max_abs_latitude.cos();
+        if !(longitude_padding < 1.0) {
+            return (min_latitude, max_latitude, -180.0, 180.0);
+        }
+        let longitude_padding = longitude_padding.asin().to_degrees();
 
+        let mut min_longitude = longitudes.iter().cloned().fold(f64::INFINITY, f64::min);
+        let mut max_longitude = longitudes.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if max_longitude - min_longitude > 180.0 {
+            // The samples wrap around the antimeridian: measure the western half as if it
+            // continued past 180 degrees.
+            let wrapped = || longitudes.iter().map(|&l| if l < 0.0 { l + 360.0 } else { l });
+            min_longitude = wrapped().fold(f64::INFINITY, f64::min);
+            max_longitude = wrapped().fold(f64::NEG_INFINITY, f64::max);
+        }
+        if max_longitude - min_longitude + 2.0 * longitude_padding >= 360.0 {
+            return (min_latitude, max_latitude, -180.0, 180.0);
+        }
+
+        let wrap = |l: f64| {
+            if l < -180.0 {
+                l + 360.0
+            } else if l > 180.0 {
+                l - 360.0
+            } else {
+                l
+            }
+        };
+        (
+            min_latitude,
+            max_latitude,
+            wrap(min_longitude - longitude_padding),
+            wrap(max_longitude + longitude_padding),
+        )
+    }
+
+    /// Returns a box in planet centered coordinates (meters) that contains all terrain this node
+    /// can cover, for any elevation between `MIN_ELEVATION` and `MAX_ELEVATION`.
+    pub fn bounds(&self) -> BoundingBox {
+        let n = BOUNDS_SAMPLES;
+        let directions: Vec<Vector3<f64>> = (0..n)
+            .flat_map(|y| (0..n).map(move |x| (x, y)))
+            .map(|(x, y)| self.grid_position_cspace(x as i32, y as i32, 0, n).normalize())
+            .collect();
+
+        // Between samples the surface bulges outward by up to 1/cos of the angle from the center
+        // of a cell to its corners.
+        let mut max_angle: f64 = 0.0;
+        for y in 0..(n - 1) as usize {
+            for x in 0..(n - 1) as usize {
+                let i = y * n as usize + x;
+                let diagonals = [(i, i + n as usize + 1), (i + 1, i + n as usize)];
+                for &(a, b) in &diagonals {
+                    let angle = directions[a].dot(directions[b]).min(1.0).acos();
+                    max_angle = max_angle.max(angle);
+                }
+            }
+        }
+        let inner = PLANET_RADIUS + MIN_ELEVATION;
+        let outer = (PLANET_RADIUS + MAX_ELEVATION) / (0.5 * max_angle).cos();
+
+        let mut min = Vector3::new(std::f64::INFINITY, std::f64::INFINITY, std::f64::INFINITY);
+        let mut max = -min;
+        for d in &directions {
+            for &r in &[inner, outer] {
+                let p = d * r;
+                min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+        }
+        BoundingBox::new(
+            Point3::new(min.x as f32, min.y as f32, min.z as f32),
+            Point3::new(max.x as f32, max.y as f32, max.z as f32),
+        )
+    }
+
+    /// Squared distance from the camera to the closest point on this node, measured on the faces
+    /// of the cube with the warping from `fspace_to_cspace` undone.
+    pub fn distance2_cspace(&self, camera_cspace: Point3<f64>) -> f64 {
         let c = Vector3::new(camera_cspace.x * (1.4511 + (1.0 - 1.4511) * camera_cspace.x.abs()),
                              camera_cspace.y * (1.4511 + (1.0 - 1.4511) * camera_cspace.y.abs()),
                              camera_cspace.z * (1.4511 + (1.0 - 1.4511) * camera_cspace.z.abs()));
@@ -152,7 +292,14 @@ impl VNode {
         let dx = ((a.x - r) - c.x).max(c.x - (a.x + r)).max(0.0);
         let dy = ((a.y - r) - c.y).max(c.y - (a.y + r)).max(0.0);
         let dz = ((a.z - r) - c.z).max(c.z - (a.z + r)).max(0.0);
-        let distance = dx * dx + dy * dy + dz * dz;
+        dx * dx + dy * dy + dz * dz
+    }
+
+    /// How much this node is needed for the current frame. Nodes with priority less than 1.0 will
+    /// not be rendered (they are too detailed).
+    pub fn priority(&self, camera_cspace: Point3<f64>) -> Priority {
+        let min_distance = self.min_distance();
+        let distance = self.distance2_cspace(camera_cspace);
 
         Priority::from_f32(((min_distance * min_distance) / distance.max(1e-12)) as f32)
     }
@@ -165,6 +312,19 @@ impl VNode {
         Some((VNode::new(self.level() - 1, self.face(), self.x() / 2, self.y() / 2), child_index))
     }
 
+    /// Returns the nodes at the same level that share an edge with this one, in the order -x, +x,
+    /// -y, +y. Neighbors on a different cube face are not found and returned as `None`.
+    pub fn neighbors(&self) -> [Option<VNode>; 4] {
+        let (level, face, x, y) = (self.level(), self.face(), self.x(), self.y());
+        let max = (1u32 << level) - 1;
+        [
+            if x > 0 { Some(VNode::new(level, face, x - 1, y)) } else { None },
+            if x < max { Some(VNode::new(level, face, x + 1, y)) } else { None },
+            if y > 0 { Some(VNode::new(level, face, x, y - 1)) } else { None },
+            if y < max { Some(VNode::new(level, face, x, y + 1)) } else { None },
+        ]
+    }
+
     pub fn children(&self) -> [VNode; 4] {
         assert!(self.level() < 31);
         [
@@ -212,3 +372,90 @@ impl VNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_share_edges() {
+        let node = VNode::new(3, 2, 4, 0);
+        let neighbors = node.neighbors();
+        assert_eq!(neighbors[2], None);
+
+        // Every neighbor should see the original node across the opposite edge.
+        for (i, opposite) in [1, 0, 3, 2].iter().enumerate() {
+            if let Some(neighbor) = neighbors[i] {
+                assert_eq!(neighbor.level(), node.level());
+                assert_eq!(neighbor.neighbors()[*opposite], Some(node));
+            }
+        }
+        assert_eq!(VNode::new(0, 1, 0, 0).neighbors(), [None; 4]);
+    }
+
+    #[test]
+    fn bounds() {
+        let mut nodes = Vec::new();
+        VNode::breadth_first(|node| {
+            nodes.push(node);
+            node.level() < 2
+        });
+        for &node in &nodes {
+            let bounds = node.bounds();
+            for y in 0..=16 {
+                for x in 0..=16 {
+                    let d = node.grid_position_cspace(x, y, 0, 17).normalize();
+                    for &r in &[PLANET_RADIUS + MIN_ELEVATION, PLANET_RADIUS + MAX_ELEVATION] {
+                        let p = d * r;
+                        let p = Point3::new(p.x as f32, p.y as f32, p.z as f32);
+                        assert_eq!(bounds.square_distance(p), 0.0, "{:?} {:?}", node, p);
+                    }
+                }
+            }
+        }
+
+        // Deeper nodes have tighter bounds.
+        let root = nodes[0].bounds();
+        let child = nodes[0].children()[0].bounds();
+        assert!(child.max.y - child.min.y < root.max.y - root.min.y);
+    }
+
+    #[test]
+    fn geographic_bounds() {
+        // Padding adds less than two degrees to the root nodes.
+        let (min_lat, max_lat, min_lon, max_lon) = VNode::new(0, 0, 0, 0).geographic_bounds();
+        assert!(min_lat < -45.0 && min_lat > -47.0 && max_lat > 45.0 && max_lat < 47.0);
+        assert!(min_lon < -45.0 && min_lon > -47.0 && max_lon > 45.0 && max_lon < 47.0);
+
+        // The root of face 1 is centered on the antimeridian.
+        let (_, _, min_lon, max_lon) = VNode::new(0, 1, 0, 0).geographic_bounds();
+        assert!(min_lon < 135.0 && min_lon > 133.0 && max_lon > -135.0 && max_lon < -133.0);
+
+        // Every point of a node lies within its bounds.
+        let node = VNode::new(6, 0, 37, 21);
+        let (min_lat, max_lat, min_lon, max_lon) = node.geographic_bounds();
+        for y in 0..=32 {
+            for x in 0..=32 {
+                let p = node.grid_position_cspace(x, y, 0, 33).normalize();
+                let (lat, lon) = (p.z.asin().to_degrees(), p.y.atan2(p.x).to_degrees());
+                assert!(lat >= min_lat && lat <= max_lat && lon >= min_lon && lon <= max_lon);
+            }
+        }
+
+        // Every child of the north pole face touches the pole.
+        for child in VNode::new(0, 4, 0, 0).children().iter() {
+            let (min_lat, max_lat, min_lon, max_lon) = child.geographic_bounds();
+            assert!(min_lat > 30.0 && min_lat < 90.0);
+            assert_eq!((max_lat, min_lon, max_lon), (90.0, -180.0, 180.0));
+        }
+
+        // Children tile their parent.
+        let parent = VNode::new(2, 2, 1, 3);
+        let bounds = parent.geographic_bounds();
+        for child in parent.children().iter() {
+            let b = child.geographic_bounds();
+            assert!(b.0 >= bounds.0 - 1e-9 && b.1 <= bounds.1 + 1e-9);
+            assert!(b.2 >= bounds.2 - 1e-9 && b.3 <= bounds.3 + 1e-9);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use bit_vec::BitVec;
-use anyhow::Error;
+use anyhow::{anyhow, ensure, Error};
 use lru_cache::LruCache;
 use memmap::Mmap;
 use serde::{Deserialize, Serialize};
@@ -7,11 +7,14 @@ use serde::{Deserialize, Serialize};
 use crate::cache::{AssetLoadContext, MMappedAsset};
 use crate::coordinates;
 
+use cgmath::InnerSpace;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::f64::consts::PI;
-use std::ops::{Deref, Index};
+use std::io::{Read, Write};
+use std::ops::{Deref, Index, Range};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub trait Scalar: Copy + 'static {
     fn from_f64(_: f64) -> Self;
@@ -41,6 +44,184 @@ pub(crate) struct MMappedRasterHeader {
 
     pub latitude_llcorner: f64,
     pub longitude_llcorner: f64,
+
+    pub planet_radius: f64,
+}
+
+/// Interpolation used when sampling a raster between cell locations.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kernel {
+    /// Value of the closest cell.
+    Nearest,
+    /// Linear blend of the four surrounding cells.
+    Bilinear,
+    /// Catmull-Rom spline through the surrounding 4x4 cells.
+    Bicubic,
+}
+
+/// Coordinate frame that generated normals are expressed in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum NormalSpace {
+    /// Relative to the local surface: +X is east, +Y is north and +Z is up.
+    Tangent = 0,
+    /// Earth-centered, earth-fixed coordinates.
+    Object = 1,
+}
+
+/// Which azimuths ambient occlusion sweeps the horizon along.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OcclusionDirections {
+    /// The four axis aligned directions.
+    Four,
+    /// The axes and diagonals.
+    Eight,
+    /// The axes, the diagonals, and the directions two cells along one axis and one along the
+    /// other.
+    Sixteen,
+}
+impl OcclusionDirections {
+    /// Step in cells taken along each direction.
+    fn steps(self) -> &'static [(isize, isize)] {
+        match self {
+            OcclusionDirections::Four => &[(0, 1), (0, -1), (1, 0), (-1, 0)],
+            OcclusionDirections::Eight => {
+                &[(0, 1), (0, -1), (1, 0), (-1, 0), (1, 1), (1, -1), (-1, 1), (-1, -1)]
+            }
+            OcclusionDirections::Sixteen => &[
+                (0, 1),
+                (0, -1),
+                (1, 0),
+                (-1, 0),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+                (1, 2),
+                (1, -2),
+                (-1, 2),
+                (-1, -2),
+                (2, 1),
+                (2, -1),
+                (-2, 1),
+                (-2, -1),
+            ],
+        }
+    }
+}
+
+/// How to fill cells beyond the edge of a raster.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BorderMode {
+    /// Use zero for all cells outside the raster.
+    Zero,
+    /// Repeat the closest edge cell.
+    Clamp,
+    /// Mirror the raster across its edge, including the edge cell itself.
+    Reflect,
+    /// Tile the raster so that each edge continues from the opposite one.
+    Wrap,
+}
+
+/// Semi-major axis of the WGS84 ellipsoid, in meters.
+const WGS84_A: f64 = 6378137.0;
+/// Flattening of the WGS84 ellipsoid.
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+/// Coordinate system that a raster's grid is laid out in.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Cells are evenly spaced in latitude and longitude, measured in degrees.
+    Equirectangular,
+    /// Spherical Mercator as used by web maps (EPSG:3857), measured in meters.
+    WebMercator,
+    /// Universal Transverse Mercator on the WGS84 ellipsoid, measured in meters.
+    Utm { zone: u8, north: bool },
+}
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Equirectangular
+    }
+}
+impl Projection {
+    /// Converts a latitude and longitude in degrees into (easting, northing) in this projection.
+    pub fn project(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        match *self {
+            Projection::Equirectangular => (longitude, latitude),
+            Projection::WebMercator => {
+                let y = (PI / 4.0 + latitude.to_radians() / 2.0).tan().ln();
+                (WGS84_A * longitude.to_radians(), WGS84_A * y)
+            }
+            Projection::Utm { zone, north } => {
+                let (easting, northing) = transverse_mercator(
+                    latitude.to_radians(),
+                    (longitude - (zone as f64 * 6.0 - 183.0)).to_radians(),
+                );
+                (easting + 500000.0, if north { northing } else { northing + 10000000.0 })
+            }
+        }
+    }
+
+    /// Ratio of ground distance to projected distance at the given latitude, near the middle of
+    /// the projection.
+    fn ground_scale(&self, latitude: f64) -> f64 {
+        match *self {
+            Projection::Equirectangular => 1.0,
+            Projection::WebMercator => latitude.to_radians().cos(),
+            Projection::Utm { .. } => 1.0 / 0.9996,
+        }
+    }
+
+    /// Converts a northing in this projection back into a latitude in degrees. Only exact for
+    /// projections whose northing doesn't depend on longitude, and approximate for UTM.
+    fn latitude(&self, northing: f64) -> f64 {
+        match *self {
+            Projection::Equirectangular => northing,
+            Projection::WebMercator => (northing / WGS84_A).sinh().atan().to_degrees(),
+            Projection::Utm { north, .. } => {
+                let northing = if north { northing } else { northing - 10000000.0 };
+                (northing / 0.9996 / WGS84_A).to_degrees()
+            }
+        }
+    }
+}
+
+/// Transverse Mercator projection of the WGS84 ellipsoid with the UTM scale factor, returning
+/// meters east of the central meridian and north of the equator. `delta_longitude` is measured from
+/// the central meridian. Uses the series expansion from Snyder's "Map Projections: A Working
+/// Manual", which is accurate to well under a meter within a UTM zone.
+fn transverse_mercator(latitude: f64, delta_longitude: f64) -> (f64, f64) {
+    let k0 = 0.9996;
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    let ep2 = e2 / (1.0 - e2);
+
+    let (sin, cos, tan) = (latitude.sin(), latitude.cos(), latitude.tan());
+    let n = WGS84_A / (1.0 - e2 * sin * sin).sqrt();
+    let t = tan * tan;
+    let c = ep2 * cos * cos;
+    let a = cos * delta_longitude;
+    let m = WGS84_A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * latitude
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * latitude).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * latitude).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * latitude).sin());
+
+    let easting = k0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0);
+    let northing = k0
+        * (m + n
+            * tan
+            * (a * a / 2.0
+                + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    (easting, northing)
+}
+
+fn default_planet_radius() -> f64 {
+    coordinates::PLANET_RADIUS
 }
 
 /// Currently assumes that values are taken at the lower left corner of each cell.
@@ -54,6 +235,22 @@ pub struct Raster<T: Into<f64> + Copy, C: Deref<Target = [T]> = Vec<T>> {
     pub latitude_llcorner: f64,
     pub longitude_llcorner: f64,
 
+    /// Number of cells that had no data in the source and hold a substituted value instead.
+    #[serde(default)]
+    pub void_cells: usize,
+    /// Which cells had no data in the source, for sources that mark them. Indexed by cell rather
+    /// than by value, so it applies to every band. Not serialized.
+    #[serde(skip)]
+    pub nodata: Option<BitVec>,
+    /// Radius in meters of the body the raster covers, used to convert cell sizes to distances.
+    #[serde(default = "default_planet_radius")]
+    pub planet_radius: f64,
+    /// Coordinate system of the grid. For projections other than `Equirectangular`,
+    /// `longitude_llcorner` and `latitude_llcorner` hold the easting and northing of the lower left
+    /// corner and `cell_size` is in the projection's units rather than degrees.
+    #[serde(default)]
+    pub projection: Projection,
+
     pub values: C,
 }
 
@@ -65,34 +262,390 @@ impl Raster<u8, Mmap> {
         let (header, mmap) = asset.load(context)?;
 
         Ok(Self {
-            width: header.width,
-            height: header.height,
-            bands: header.bands,
-            cell_size: header.cell_size,
-            latitude_llcorner: header.latitude_llcorner,
-            longitude_llcorner: header.longitude_llcorner,
-            values: mmap.make_read_only()?,
+            planet_radius: header.planet_radius,
+            ..Raster::new(
+                header.width,
+                header.height,
+                header.bands,
+                header.cell_size,
+                header.latitude_llcorner,
+                header.longitude_llcorner,
+                mmap.make_read_only()?,
+            )
         })
     }
 }
 
+impl<T: Into<f64> + bytemuck::Pod> Raster<T> {
+    /// Returns the header describing this raster's layout, as stored alongside the values of
+    /// memory mapped rasters.
+    pub(crate) fn mmapped_header(&self) -> MMappedRasterHeader {
+        MMappedRasterHeader {
+            width: self.width,
+            height: self.height,
+            bands: self.bands,
+            cell_size: self.cell_size,
+            latitude_llcorner: self.latitude_llcorner,
+            longitude_llcorner: self.longitude_llcorner,
+            planet_radius: self.planet_radius,
+        }
+    }
+
+    /// Writes the raster in the layout `MMappedAsset` uses: a bincode encoded
+    /// `MMappedRasterHeader` to `header` and the raw values in little endian byte order to `data`.
+    /// This is much faster than going through serde for large rasters.
+    ///
+    /// Only equirectangular rasters without a no-data mask can be written, since the header has no
+    /// room for the rest.
+    pub fn write_to<H: Write, D: Write>(&self, header: H, mut data: D) -> Result<(), Error> {
+        ensure!(
+            self.projection == Projection::Equirectangular,
+            "can't store a raster in the {:?} projection",
+            self.projection
+        );
+        ensure!(self.nodata.is_none(), "can't store a raster with a no-data mask");
+
+        bincode::serialize_into(header, &self.mmapped_header())?;
+        let mut bytes = bytemuck::cast_slice::<T, u8>(&self.values).to_vec();
+        to_little_endian::<T>(&mut bytes);
+        data.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Reads a raster written by `write_to`.
+    pub fn read_from<H: Read, D: Read>(header: H, mut data: D) -> Result<Self, Error> {
+        let header: MMappedRasterHeader = bincode::deserialize_from(header)?;
+        let len = header
+            .width
+            .checked_mul(header.height)
+            .and_then(|cells| cells.checked_mul(header.bands))
+            .filter(|len| len.checked_mul(std::mem::size_of::<T>()).is_some())
+            .ok_or(anyhow!(
+                "raster of {}x{}x{} values is too large",
+                header.width,
+                header.height,
+                header.bands
+            ))?;
+        let mut values = vec![T::zeroed(); len];
+        data.read_exact(bytemuck::cast_slice_mut(&mut values))?;
+        to_little_endian::<T>(bytemuck::cast_slice_mut(&mut values));
+        Ok(Self {
+            planet_radius: header.planet_radius,
+            ..Raster::new(
+                header.width,
+                header.height,
+                header.bands,
+                header.cell_size,
+                header.latitude_llcorner,
+                header.longitude_llcorner,
+                values,
+            )
+        })
+    }
+}
+
+/// Converts `bytes`, holding values of type `T`, between native and little endian byte order.
+fn to_little_endian<T>(bytes: &mut [u8]) {
+    if cfg!(target_endian = "big") {
+        for value in bytes.chunks_mut(std::mem::size_of::<T>()) {
+            value.reverse();
+        }
+    }
+}
+
 impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> Raster<T, C> {
-    /// Returns the vertical spacing between cells, in meters.
+    /// Creates an equirectangular raster of the default planet with no void cells. `values` holds
+    /// `bands` values per cell, row by row.
+    pub fn new(
+        width: usize,
+        height: usize,
+        bands: usize,
+        cell_size: f64,
+        latitude_llcorner: f64,
+        longitude_llcorner: f64,
+        values: C,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            bands,
+            cell_size,
+            latitude_llcorner,
+            longitude_llcorner,
+            void_cells: 0,
+            nodata: None,
+            planet_radius: coordinates::PLANET_RADIUS,
+            projection: Projection::Equirectangular,
+            values,
+        }
+    }
+
+    /// Returns a raster covering the same grid of the same planet as this one, but holding
+    /// `values` and no void cells.
+    pub(crate) fn with_values<U: Into<f64> + Copy, D: Deref<Target = [U]>>(
+        &self,
+        values: D,
+    ) -> Raster<U, D> {
+        Raster {
+            width: self.width,
+            height: self.height,
+            bands: self.bands,
+            cell_size: self.cell_size,
+            latitude_llcorner: self.latitude_llcorner,
+            longitude_llcorner: self.longitude_llcorner,
+            void_cells: 0,
+            nodata: None,
+            planet_radius: self.planet_radius,
+            projection: self.projection,
+            values,
+        }
+    }
+
+    /// Returns the vertical spacing between cells, in meters. For projected rasters this is
+    /// measured at the middle row.
     pub fn vertical_spacing(&self) -> f64 {
-        self.cell_size.to_radians() * coordinates::PLANET_RADIUS
+        match self.projection {
+            Projection::Equirectangular => self.cell_size.to_radians() * self.planet_radius,
+            projection => {
+                let northing = self.latitude_llcorner + self.cell_size * self.height as f64 / 2.0;
+                self.cell_size * projection.ground_scale(projection.latitude(northing))
+            }
+        }
     }
 
     /// Returns the horizontal spacing between cells, in meters.
     pub fn horizontal_spacing(&self, y: usize) -> f64 {
-        self.vertical_spacing()
-            * (self.latitude_llcorner + self.cell_size * y as f64).to_radians().cos()
+        let northing = self.latitude_llcorner + self.cell_size * y as f64;
+        match self.projection {
+            Projection::Equirectangular => {
+                self.vertical_spacing() * northing.to_radians().cos()
+            }
+            projection => self.cell_size * projection.ground_scale(projection.latitude(northing)),
+        }
+    }
+
+    /// Returns the position of the given point in cells east and north of the lower left corner,
+    /// after converting it into the raster's projection.
+    pub(crate) fn cell_offsets(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let (easting, northing) = self.projection.project(latitude, longitude);
+        (
+            (easting - self.longitude_llcorner) / self.cell_size,
+            (northing - self.latitude_llcorner) / self.cell_size,
+        )
+    }
+
+    /// Copies a single band into a new single-band raster.
+    pub fn band_view(&self, band: usize) -> Raster<T> {
+        assert!(band < self.bands);
+        Raster {
+            bands: 1,
+            nodata: self.nodata.clone(),
+            ..self.with_values(self.values.iter().skip(band).step_by(self.bands).copied().collect())
+        }
+    }
+
+    /// Returns a copy of the raster with rows and columns swapped. The upper left corner stays in
+    /// place, so the covered area extends along the other axis afterwards.
+    pub fn transpose(&self) -> Raster<T> {
+        let top = self.latitude_llcorner + self.cell_size * self.height as f64;
+        let mut raster = self.remap(self.height, self.width, |x, y| (y, x));
+        raster.latitude_llcorner = top - self.cell_size * raster.height as f64;
+        raster
+    }
+
+    /// Returns a copy of the raster with the order of the rows reversed.
+    pub fn flip_vertical(&self) -> Raster<T> {
+        self.remap(self.width, self.height, |x, y| (x, self.height - 1 - y))
+    }
+
+    /// Returns a copy of the raster with the order of the columns reversed.
+    pub fn flip_horizontal(&self) -> Raster<T> {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+
+    /// Builds a `width` x `height` raster where each cell is copied from the cell of this raster
+    /// at the position returned by `source`.
+    fn remap(
+        &self,
+        width: usize,
+        height: usize,
+        source: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Raster<T> {
+        let mut values = Vec::with_capacity(width * height * self.bands);
+        let mut nodata = self.nodata.as_ref().map(|_| BitVec::with_capacity(width * height));
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = source(x, y);
+                let start = (sx + sy * self.width) * self.bands;
+                values.extend_from_slice(&self.values[start..start + self.bands]);
+                if let (Some(nodata), Some(mask)) = (nodata.as_mut(), self.nodata.as_ref()) {
+                    nodata.push(mask[sx + sy * self.width]);
+                }
+            }
+        }
+
+        Raster { width, height, nodata, ..self.with_values(values) }
+    }
+
+    /// Stitches `tiles` into one raster spanning all of them. Every tile must have the same
+    /// `cell_size`, `bands` and projection, and its corner must fall on the shared grid of cells.
+    /// Where tiles overlap, such as the shared edge of adjacent SRTM tiles, the tile that comes
+    /// later in `tiles` wins. Cells covered by no tile are set to `fill` and, if any tile has a
+    /// nodata mask, marked as nodata. `void_cells` counts the uncovered cells along with those
+    /// marked in the masks of the tiles that ended up providing them; voids of tiles without a
+    /// mask can't be located, so they aren't counted.
+    pub fn mosaic(tiles: &[Self], fill: T) -> Result<Raster<T>, Error> {
+        let first = tiles.first().ok_or_else(|| anyhow!("mosaic needs at least one tile"))?;
+        let cell_size = first.cell_size;
+        for tile in tiles {
+            if tile.cell_size != cell_size || tile.bands != first.bands {
+                return Err(anyhow!(
+                    "tile at ({}, {}) has cell size {} and {} bands, expected {} and {}",
+                    tile.latitude_llcorner,
+                    tile.longitude_llcorner,
+                    tile.cell_size,
+                    tile.bands,
+                    cell_size,
+                    first.bands
+                ));
+            }
+            if tile.projection != first.projection {
+                return Err(anyhow!("tiles use different projections"));
+            }
+        }
+
+        let min_x = tiles.iter().map(|t| t.longitude_llcorner).fold(f64::INFINITY, f64::min);
+        let min_y = tiles.iter().map(|t| t.latitude_llcorner).fold(f64::INFINITY, f64::min);
+        let cells = |offset: f64| -> Result<usize, Error> {
+            let cells = offset / cell_size;
+            if (cells - cells.round()).abs() > 1e-6 {
+                return Err(anyhow!("tile corner is not aligned with the other tiles' cells"));
+            }
+            Ok(cells.round() as usize)
+        };
+
+        // Offsets of each tile's lower left cell from the lower left of the mosaic.
+        let mut offsets = Vec::with_capacity(tiles.len());
+        let (mut width, mut height) = (0, 0);
+        for tile in tiles {
+            let x = cells(tile.longitude_llcorner - min_x)?;
+            let y = cells(tile.latitude_llcorner - min_y)?;
+            width = width.max(x + tile.width);
+            height = height.max(y + tile.height);
+            offsets.push((x, y));
+        }
+
+        let bands = first.bands;
+        let mut values = vec![fill; width * height * bands];
+        let mut void = BitVec::from_elem(width * height, true);
+        for (tile, &(x0, y0)) in tiles.iter().zip(&offsets) {
+            // Rows are stored from north to south, so the tile's first row lands `height - y0 -
+            // tile.height` rows below the top of the mosaic.
+            let top = height - y0 - tile.height;
+            for y in 0..tile.height {
+                let src = y * tile.width * bands;
+                let dst = ((top + y) * width + x0) * bands;
+                values[dst..dst + tile.width * bands]
+                    .copy_from_slice(&tile.values[src..src + tile.width * bands]);
+                for x in 0..tile.width {
+                    let cell = (top + y) * width + x0 + x;
+                    void.set(cell, tile.nodata.as_ref().map_or(false, |m| m[x + y * tile.width]));
+                }
+            }
+        }
+
+        Ok(Raster {
+            width,
+            height,
+            bands,
+            cell_size,
+            latitude_llcorner: min_y,
+            longitude_llcorner: min_x,
+            void_cells: void.iter().filter(|&void| void).count(),
+            nodata: if tiles.iter().any(|t| t.nodata.is_some()) { Some(void) } else { None },
+            ..first.with_values(values)
+        })
+    }
+
+    /// Applies an affine transform to the raster. `matrix` maps cell coordinates `(x, y, 1)` in
+    /// this raster to cell coordinates in the `out_width` x `out_height` output, which is sampled
+    /// from this raster by applying the inverse transform and interpolating bilinearly. Output
+    /// cells that map outside of this raster are set to `fill`. The upper left corner of the
+    /// output is placed at that of this raster. Fails if the transform isn't invertible or this
+    /// raster has no cells.
+    pub fn affine_warp(
+        &self,
+        matrix: [[f64; 3]; 2],
+        out_width: usize,
+        out_height: usize,
+        fill: f64,
+    ) -> Result<Raster<f64>, Error> {
+        let [[a, b, tx], [c, d, ty]] = matrix;
+        let det = a * d - b * c;
+        if !det.is_finite() || det.abs() <= 1e-12 {
+            return Err(anyhow!("affine transform {:?} is not invertible", matrix));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(anyhow!("can't warp an empty raster"));
+        }
+
+        let mut values = Vec::with_capacity(out_width * out_height * self.bands);
+        for y in 0..out_height {
+            for x in 0..out_width {
+                let (u, v) = (x as f64 - tx, y as f64 - ty);
+                let sx = (d * u - b * v) / det;
+                let sy = (a * v - c * u) / det;
+
+                let eps = 1e-9;
+                if sx < -eps
+                    || sy < -eps
+                    || sx > (self.width - 1) as f64 + eps
+                    || sy > (self.height - 1) as f64 + eps
+                {
+                    values.extend(std::iter::repeat(fill).take(self.bands));
+                    continue;
+                }
+
+                let (sx, sy) = (sx.max(0.0), sy.max(0.0));
+                let x0 = (sx as usize).min(self.width - 1);
+                let y0 = (sy as usize).min(self.height - 1);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let (fx, fy) = (sx - x0 as f64, sy - y0 as f64);
+                for band in 0..self.bands {
+                    let get = |x: usize, y: usize| -> f64 {
+                        self.values[(x + y * self.width) * self.bands + band].into()
+                    };
+                    let top = get(x0, y0) + (get(x1, y0) - get(x0, y0)) * fx;
+                    let bottom = get(x0, y1) + (get(x1, y1) - get(x0, y1)) * fx;
+                    values.push(top + (bottom - top) * fy);
+                }
+            }
+        }
+
+        let top = self.latitude_llcorner + self.cell_size * self.height as f64;
+        Ok(Raster {
+            width: out_width,
+            height: out_height,
+            latitude_llcorner: top - self.cell_size * out_height as f64,
+            ..self.with_values(values)
+        })
+    }
+
+    /// Fraction of cells that had no data in the source.
+    pub fn void_fraction(&self) -> f32 {
+        self.void_cells as f32 / (self.width * self.height) as f32
+    }
+
+    /// Returns the approximate ground area covered by a cell in row `y`, in square meters.
+    pub fn cell_area_m2(&self, y: usize) -> f64 {
+        self.vertical_spacing() * self.horizontal_spacing(y)
     }
 
     pub fn interpolate(&self, latitude: f64, longitude: f64, band: usize) -> Option<f64> {
         assert!(band < self.bands);
 
-        let x = (longitude - self.longitude_llcorner) / self.cell_size;
-        let y = (self.height - 1) as f64 - (latitude - self.latitude_llcorner) / self.cell_size;
+        let (x, y) = self.cell_offsets(latitude, longitude);
+        let y = (self.height - 1) as f64 - y;
 
         let fx = x.floor() as usize;
         let fy = y.floor() as usize;
@@ -101,7 +654,8 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> Raster<T, C> {
             return None;
         }
 
-        // TODO: These should be interpolating across tiles...
+        // Samples in the last row or column are clamped to this raster. `RasterCache::interpolate`
+        // reads the neighboring rasters instead.
         let fx_1 = (fx + 1).min(self.width - 1);
         let fy_1 = (fy + 1).min(self.height - 1);
 
@@ -114,11 +668,143 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> Raster<T, C> {
         Some(h0 + (h1 - h0) * (x - fx as f64))
     }
 
+    /// Like `interpolate` but returns the value of the closest cell.
+    pub fn interpolate_nearest(&self, latitude: f64, longitude: f64, band: usize) -> Option<f64> {
+        assert!(band < self.bands);
+
+        let (x, y) = self.cell_offsets(latitude, longitude);
+        let y = (self.height - 1) as f64 - y;
+
+        if x < 0.0
+            || x.floor() as usize >= self.width
+            || y < 0.0
+            || y.floor() as usize >= self.height
+        {
+            return None;
+        }
+
+        let x = (x.round() as usize).min(self.width - 1);
+        let y = (y.round() as usize).min(self.height - 1);
+        Some(self.values[(x + y * self.width) * self.bands + band].into())
+    }
+
+    /// Same as `interpolate_nearest`. Suited to categorical bands such as land cover classes,
+    /// where blending neighboring cells would produce meaningless values. Unlike `nearest3`, this
+    /// rounds to the closest cell center and works with any number of bands.
+    pub fn nearest(&self, latitude: f64, longitude: f64, band: usize) -> Option<f64> {
+        self.interpolate_nearest(latitude, longitude, band)
+    }
+
+    /// Like `interpolate` but fits a Catmull-Rom spline through the 4x4 neighborhood. Cells past
+    /// the edge of the raster are clamped to the nearest edge cell.
+    pub fn interpolate_bicubic(&self, latitude: f64, longitude: f64, band: usize) -> Option<f64> {
+        assert!(band < self.bands);
+
+        let (x, y) = self.cell_offsets(latitude, longitude);
+        let y = (self.height - 1) as f64 - y;
+
+        if x < 0.0
+            || x.floor() as usize >= self.width
+            || y < 0.0
+            || y.floor() as usize >= self.height
+        {
+            return None;
+        }
+        Some(self.sample_bicubic(x, y, band))
+    }
+
+    /// Evaluates a Catmull-Rom spline at cell coordinates `(x, y)`, which must be non-negative.
+    fn sample_bicubic(&self, x: f64, y: f64, band: usize) -> f64 {
+        let fx = x.floor() as usize;
+        let fy = y.floor() as usize;
+
+        let get = |x: isize, y: isize| -> f64 {
+            let x = x.max(0).min(self.width as isize - 1) as usize;
+            let y = y.max(0).min(self.height as isize - 1) as usize;
+            self.values[(x + y * self.width) * self.bands + band].into()
+        };
+        let cubic = |p: [f64; 4], t: f64| -> f64 {
+            p[1] + 0.5
+                * t
+                * (p[2] - p[0]
+                    + t * (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]
+                        + t * (3.0 * (p[1] - p[2]) + p[3] - p[0])))
+        };
+
+        let (tx, ty) = (x - fx as f64, y - fy as f64);
+        let (fx, fy) = (fx as isize, fy as isize);
+        let mut rows = [0.0; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            let y = fy + i as isize - 1;
+            *row = cubic([get(fx - 1, y), get(fx, y), get(fx + 1, y), get(fx + 2, y)], tx);
+        }
+        cubic(rows, ty)
+    }
+
+    /// Resamples the raster to `width` x `height` cells covering the same area, using the same
+    /// Catmull-Rom spline as `interpolate_bicubic`. The corner cells stay in place so only
+    /// `cell_size` changes, which means the new dimensions must preserve the aspect ratio.
+    /// Resampling to the current dimensions returns the original values. Fails if either raster
+    /// is less than two cells across or the aspect ratio would change.
+    pub fn resample(&self, width: usize, height: usize) -> Result<Raster<f64>, Error> {
+        if width < 2 || height < 2 || self.width < 2 || self.height < 2 {
+            return Err(anyhow!(
+                "can't resample {}x{} raster to {}x{}, both need at least 2x2 cells",
+                self.width,
+                self.height,
+                width,
+                height
+            ));
+        }
+        if (self.height - 1) * (width - 1) != (height - 1) * (self.width - 1) {
+            return Err(anyhow!(
+                "resampling {}x{} raster to {}x{} doesn't preserve the aspect ratio",
+                self.width,
+                self.height,
+                width,
+                height
+            ));
+        }
+
+        let scale_x = (self.width - 1) as f64 / (width - 1) as f64;
+        let scale_y = (self.height - 1) as f64 / (height - 1) as f64;
+        let mut values = Vec::with_capacity(width * height * self.bands);
+        for y in 0..height {
+            for x in 0..width {
+                for band in 0..self.bands {
+                    values.push(self.sample_bicubic(x as f64 * scale_x, y as f64 * scale_y, band));
+                }
+            }
+        }
+
+        Ok(Raster {
+            width,
+            height,
+            cell_size: self.cell_size * scale_x,
+            ..self.with_values(values)
+        })
+    }
+
+    /// Samples the raster using the given interpolation kernel.
+    pub fn interpolate_with(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        band: usize,
+        kernel: Kernel,
+    ) -> Option<f64> {
+        match kernel {
+            Kernel::Nearest => self.nearest(latitude, longitude, band),
+            Kernel::Bilinear => self.interpolate(latitude, longitude, band),
+            Kernel::Bicubic => self.interpolate_bicubic(latitude, longitude, band),
+        }
+    }
+
     pub fn nearest3(&self, latitude: f64, longitude: f64) -> Option<[f64;3]> {
         assert!(self.bands >= 3);
 
-        let x = (longitude - self.longitude_llcorner) / self.cell_size;
-        let y = self.height as f64 - (latitude - self.latitude_llcorner) / self.cell_size;
+        let (x, y) = self.cell_offsets(latitude, longitude);
+        let y = self.height as f64 - y;
 
         let fx = x.floor() as usize;
         let fy = y.floor() as usize;
@@ -131,62 +817,355 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> Raster<T, C> {
         Some([slice[0].into(), slice[1].into(), slice[2].into()])
     }
 
+    /// Returns a copy of this raster with `border` extra cells on every side, filled according to
+    /// `mode`. The lower left corner is moved so that existing cells keep their locations.
+    pub fn pad(&self, border: usize, mode: BorderMode) -> Raster<f64> {
+        let width = self.width + 2 * border;
+        let height = self.height + 2 * border;
+
+        let source_index = |i: isize, n: usize| -> Option<usize> {
+            let n = n as isize;
+            if i >= 0 && i < n {
+                return Some(i as usize);
+            }
+            match mode {
+                BorderMode::Zero => None,
+                BorderMode::Clamp => Some(i.max(0).min(n - 1) as usize),
+                BorderMode::Reflect => {
+                    let period = 2 * n;
+                    let i = ((i % period) + period) % period;
+                    Some(if i < n { i } else { period - 1 - i } as usize)
+                }
+                BorderMode::Wrap => Some(((i % n + n) % n) as usize),
+            }
+        };
+
+        let mut values = Vec::with_capacity(width * height * self.bands);
+        for y in 0..height {
+            let sy = source_index(y as isize - border as isize, self.height);
+            for x in 0..width {
+                let sx = source_index(x as isize - border as isize, self.width);
+                for band in 0..self.bands {
+                    values.push(match (sx, sy) {
+                        (Some(sx), Some(sy)) => {
+                            self.values[(sx + sy * self.width) * self.bands + band].into()
+                        }
+                        _ => 0.0,
+                    });
+                }
+            }
+        }
+
+        Raster {
+            width,
+            height,
+            latitude_llcorner: self.latitude_llcorner - border as f64 * self.cell_size,
+            longitude_llcorner: self.longitude_llcorner - border as f64 * self.cell_size,
+            ..self.with_values(values)
+        }
+    }
+
+    /// Computes a normal map from the heights in the first band, returning three bands per cell
+    /// with each component mapped from [-1, 1] to [0, 255]. Rows are assumed to run from north to
+    /// south.
+    pub fn to_normal_map(&self, space: NormalSpace) -> Raster<u8> {
+        let height =
+            |x: usize, y: usize| -> f64 { self.values[(x + y * self.width) * self.bands].into() };
+
+        let mut values = Vec::with_capacity(self.width * self.height * 3);
+        for y in 0..self.height {
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(self.height - 1));
+            let dy = (y1 - y0).max(1) as f64 * self.vertical_spacing();
+            let dx = self.horizontal_spacing(self.height - 1 - y);
+            let latitude = (self.latitude_llcorner
+                + self.cell_size * (self.height - 1 - y) as f64)
+                .to_radians();
+
+            for x in 0..self.width {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+                let dhdx = (height(x1, y) - height(x0, y)) / ((x1 - x0).max(1) as f64 * dx);
+                let dhdy = (height(x, y0) - height(x, y1)) / dy;
+
+                let n = cgmath::Vector3::new(-dhdx, -dhdy, 1.0).normalize();
+                let n = match space {
+                    NormalSpace::Tangent => n,
+                    NormalSpace::Object => {
+                        let longitude =
+                            (self.longitude_llcorner + self.cell_size * x as f64).to_radians();
+                        let (slat, clat) = latitude.sin_cos();
+                        let (slong, clong) = longitude.sin_cos();
+                        let east = cgmath::Vector3::new(-slong, clong, 0.0);
+                        let north = cgmath::Vector3::new(-slat * clong, -slat * slong, clat);
+                        let up = cgmath::Vector3::new(clat * clong, clat * slong, slat);
+                        east * n.x + north * n.y + up * n.z
+                    }
+                };
+
+                values.push(((n.x * 0.5 + 0.5) * 255.0).round() as u8);
+                values.push(((n.y * 0.5 + 0.5) * 255.0).round() as u8);
+                values.push(((n.z * 0.5 + 0.5) * 255.0).round() as u8);
+            }
+        }
+
+        Raster { bands: 3, ..self.with_values(values) }
+    }
+
+    /// Computes the magnitude of the height gradient of the first band using 3x3 Sobel operators,
+    /// in meters of rise per meter. Cells past the edges are clamped to the nearest edge cell.
+    pub fn sobel_magnitude(&self) -> Raster<f32> {
+        let height = |x: isize, y: isize| -> f64 {
+            let x = x.max(0).min(self.width as isize - 1) as usize;
+            let y = y.max(0).min(self.height as isize - 1) as usize;
+            self.values[(x + y * self.width) * self.bands].into()
+        };
+
+        let mut values = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height as isize {
+            // Each Sobel operator sums differences with total weight 8 across two cell spacings.
+            let dx = 8.0 * self.horizontal_spacing(self.height - 1 - y as usize);
+            let dy = 8.0 * self.vertical_spacing();
+            for x in 0..self.width as isize {
+                let gx = (height(x + 1, y - 1) + 2.0 * height(x + 1, y) + height(x + 1, y + 1))
+                    - (height(x - 1, y - 1) + 2.0 * height(x - 1, y) + height(x - 1, y + 1));
+                let gy = (height(x - 1, y + 1) + 2.0 * height(x, y + 1) + height(x + 1, y + 1))
+                    - (height(x - 1, y - 1) + 2.0 * height(x, y - 1) + height(x + 1, y - 1));
+                values.push(((gx / dx).powi(2) + (gy / dy).powi(2)).sqrt() as f32);
+            }
+        }
+
+        Raster { bands: 1, ..self.with_values(values) }
+    }
+
+    /// Computes Lambertian shading of the first band lit by a distant sun, with `sun_azimuth`
+    /// measured clockwise from north and `sun_altitude` above the horizon, both in degrees. Rows
+    /// are assumed to run from north to south.
+    pub fn hillshade(&self, sun_azimuth: f64, sun_altitude: f64) -> Raster<u8> {
+        let height =
+            |x: usize, y: usize| -> f64 { self.values[(x + y * self.width) * self.bands].into() };
+
+        let (saz, caz) = sun_azimuth.to_radians().sin_cos();
+        let (salt, calt) = sun_altitude.to_radians().sin_cos();
+        let sun = cgmath::Vector3::new(saz * calt, caz * calt, salt);
+
+        let mut values = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(self.height - 1));
+            let dy = (y1 - y0).max(1) as f64 * self.vertical_spacing();
+            let dx = self.horizontal_spacing(self.height - 1 - y);
+
+            for x in 0..self.width {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+                let dhdx = (height(x1, y) - height(x0, y)) / ((x1 - x0).max(1) as f64 * dx);
+                let dhdy = (height(x, y0) - height(x, y1)) / dy;
+
+                let n = cgmath::Vector3::new(-dhdx, -dhdy, 1.0).normalize();
+                values.push((n.dot(sun).max(0.0) * 255.0).round() as u8);
+            }
+        }
+
+        Raster { bands: 1, ..self.with_values(values) }
+    }
+
     pub fn ambient_occlusion(&self) -> Raster<u8> {
-        // See: https://nothings.org/gamedev/horizon
+        self.ambient_occlusion_with_directions(OcclusionDirections::Four)
+    }
 
-        assert_eq!(self.bands, 1);
-        let mut output = Raster {
-            width: self.width,
-            height: self.height,
+    /// Like `ambient_occlusion`, but returns unquantized values from 0.0 (fully occluded) to 1.0
+    /// (open sky). Useful when the result is combined with other lighting, where the steps
+    /// between `u8` levels would show up as banding.
+    pub fn ambient_occlusion_f32(&self) -> Raster<f32> {
+        self.occlusion_sweep(None, OcclusionDirections::Four).0
+    }
+
+    /// Like `ambient_occlusion`, but sweeps the horizon along `directions` rather than just the
+    /// four axis aligned ones.
+    pub fn ambient_occlusion_with_directions(&self, directions: OcclusionDirections) -> Raster<u8> {
+        self.ambient_occlusion_hull(None, directions).0
+    }
+
+    /// Like `ambient_occlusion`, but terrain more than `max_distance` meters away from a cell is
+    /// not considered when computing its occlusion. This bounds the length of the horizon kept
+    /// during each sweep, at the cost of missing occlusion from large, distant features.
+    pub fn ambient_occlusion_within(&self, max_distance: Option<f64>) -> Raster<u8> {
+        self.ambient_occlusion_hull(max_distance, OcclusionDirections::Four).0
+    }
+
+    /// Computes ambient occlusion, also returning the largest number of points that were ever
+    /// held in the horizon hull during a single sweep.
+    fn ambient_occlusion_hull(
+        &self,
+        max_distance: Option<f64>,
+        directions: OcclusionDirections,
+    ) -> (Raster<u8>, usize) {
+        let (occlusion, max_hull_len) = self.occlusion_sweep(max_distance, directions);
+        let output = Raster {
             bands: 1,
-            cell_size: self.cell_size,
-            latitude_llcorner: self.latitude_llcorner,
-            longitude_llcorner: self.longitude_llcorner,
-            values: vec![0; self.width * self.height],
+            ..occlusion.with_values(
+                occlusion.values.iter().map(|&o| (o * 255.0).min(255.0) as u8).collect(),
+            )
         };
+        (output, max_hull_len)
+    }
 
-        let mut walk =
-            |mut x: usize, mut y: usize, dx: isize, dy: isize, steps: usize, step_size: f64| {
-                let mut hull = Vec::new();
-                for i in 0..(steps as isize) {
-                    let h: f64 = self.values[x + y * self.width].into();
-                    if hull.is_empty() {
-                        hull.push((-1, h));
-                    }
+    /// Unquantized version of `ambient_occlusion_hull`, with values in [0, 1].
+    fn occlusion_sweep(
+        &self,
+        max_distance: Option<f64>,
+        directions: OcclusionDirections,
+    ) -> (Raster<f32>, usize) {
+        // See: https://nothings.org/gamedev/horizon
 
-                    while hull.len() >= 2 {
-                        let (i1, h1) = hull[hull.len() - 1];
-                        let (i2, h2) = hull[hull.len() - 2];
-                        if ((h1 - h) * (i - i2) as f64) < ((h2 - h) * (i - i1) as f64) {
-                            hull.pop();
-                        } else {
-                            break;
-                        }
+        assert_eq!(self.bands, 1);
+        let steps = directions.steps();
+
+        let (width, height) = (self.width as isize, self.height as isize);
+        let in_bounds = |x: isize, y: isize| x >= 0 && x < width && y >= 0 && y < height;
+
+        let mut occlusion = vec![0.0; self.width * self.height];
+        let mut max_hull_len = 0;
+
+        let mut walk = |mut x: isize, mut y: isize, dx: isize, dy: isize| {
+            let step_size = ((dx as f64 * self.horizontal_spacing(y as usize)).powi(2)
+                + (dy as f64 * self.vertical_spacing()).powi(2))
+            .sqrt();
+            let mut hull = VecDeque::new();
+            let mut i = 0;
+            while in_bounds(x, y) {
+                let index = (x + y * width) as usize;
+                let h: f64 = self.values[index].into();
+                if hull.is_empty() {
+                    hull.push_back((-1, h));
+                }
+
+                if let Some(max_distance) = max_distance {
+                    while hull.len() >= 2 && (i - hull[0].0) as f64 * step_size > max_distance {
+                        hull.pop_front();
                     }
+                }
 
+                while hull.len() >= 2 {
                     let (i1, h1) = hull[hull.len() - 1];
-                    let slope = (h1 - h) / ((i - i1) as f64 * step_size);
-                    let occlusion: f64 = 1.0 - (slope.atan() / (0.5 * PI)).max(0.0);
+                    let (i2, h2) = hull[hull.len() - 2];
+                    if ((h1 - h) * (i - i2) as f64) < ((h2 - h) * (i - i1) as f64) {
+                        hull.pop_back();
+                    } else {
+                        break;
+                    }
+                }
 
-                    hull.push((i, h));
-                    output.values[x + y * self.width] += (occlusion * 63.75) as u8;
-                    x = (x as isize + dx) as usize;
-                    y = (y as isize + dy) as usize;
+                let (i1, h1) = hull[hull.len() - 1];
+                let slope = (h1 - h) / ((i - i1) as f64 * step_size);
+                occlusion[index] += 1.0 - (slope.atan() / (0.5 * PI)).max(0.0);
+
+                hull.push_back((i, h));
+                max_hull_len = max_hull_len.max(hull.len());
+                x += dx;
+                y += dy;
+                i += 1;
+            }
+        };
+
+        // Every cell lies on exactly one line in each direction. Lines start at the cells whose
+        // predecessor is off the edge of the raster.
+        for &(dx, dy) in steps {
+            for y in 0..height {
+                for x in 0..width {
+                    if !in_bounds(x - dx, y - dy) {
+                        walk(x, y, dx, dy);
+                    }
                 }
-            };
+            }
+        }
 
-        for x in 0..self.width {
-            let spacing = self.horizontal_spacing(x);
-            walk(x, 0, 0, 1, self.height, spacing);
-            walk(x, self.height - 1, 0, -1, self.height, spacing);
+        // Normalize so the result stays in range for any number of directions.
+        let scale = 1.0 / steps.len() as f64;
+        let output = Raster {
+            bands: 1,
+            ..self.with_values(occlusion.into_iter().map(|o| (o * scale).min(1.0) as f32).collect())
+        };
+        (output, max_hull_len)
+    }
+}
+
+/// Pyramid of conservative height bounds over a single band raster. Each cell of a level stores
+/// the minimum and maximum of the (up to) four cells below it, so the height range of any region
+/// can be bounded by looking at only a handful of cells.
+pub struct MinMaxPyramid {
+    /// Levels from full resolution (level 0) down to a single cell. Each is stored row-major
+    /// along with its width and height.
+    levels: Vec<(usize, usize, Vec<(f32, f32)>)>,
+}
+impl MinMaxPyramid {
+    pub fn new<T: Into<f64> + Copy, C: Deref<Target = [T]>>(raster: &Raster<T, C>) -> Self {
+        assert_eq!(raster.bands, 1);
+        assert!(raster.width > 0 && raster.height > 0);
+
+        let base = raster
+            .values
+            .iter()
+            .map(|&v| {
+                let v = v.into() as f32;
+                (v, v)
+            })
+            .collect();
+        let mut levels = vec![(raster.width, raster.height, base)];
+
+        while levels.last().map(|&(w, h, _)| w > 1 || h > 1).unwrap() {
+            let (width, height, ref cells) = *levels.last().unwrap();
+            let (w, h) = ((width + 1) / 2, (height + 1) / 2);
+            let mut level = Vec::with_capacity(w * h);
+            for y in 0..h {
+                for x in 0..w {
+                    let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+                    for cy in (2 * y)..(2 * y + 2).min(height) {
+                        for cx in (2 * x)..(2 * x + 2).min(width) {
+                            let (min, max) = cells[cx + cy * width];
+                            range = (range.0.min(min), range.1.max(max));
+                        }
+                    }
+                    level.push(range);
+                }
+            }
+            levels.push((w, h, level));
         }
-        for y in 0..self.height {
-            let spacing = self.vertical_spacing();
-            walk(0, y, 1, 0, self.width, spacing);
-            walk(self.width - 1, y, -1, 0, self.width, spacing);
+
+        Self { levels }
+    }
+
+    /// Number of levels, including the full resolution one.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns the `(min, max)` height of cell `(x, y)` of `level`.
+    pub fn cell(&self, level: usize, x: usize, y: usize) -> (f32, f32) {
+        let (width, height, ref cells) = self.levels[level];
+        assert!(x < width && y < height);
+        cells[x + y * width]
+    }
+
+    /// Returns bounds on the heights within a region given in full resolution cells. The result
+    /// is conservative: it always contains the true range, but may be wider.
+    pub fn height_range(&self, x: Range<usize>, y: Range<usize>) -> (f32, f32) {
+        assert!(x.start < x.end && y.start < y.end);
+        assert!(x.end <= self.levels[0].0 && y.end <= self.levels[0].1);
+
+        // Use the finest level at which the region spans at most two cells in each direction.
+        let mut level = 0;
+        while (x.end - 1) >> level > (x.start >> level) + 1
+            || (y.end - 1) >> level > (y.start >> level) + 1
+        {
+            level += 1;
         }
 
-        output
+        let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+        for cy in (y.start >> level)..=((y.end - 1) >> level) {
+            for cx in (x.start >> level)..=((x.end - 1) >> level) {
+                let (min, max) = self.cell(level, cx, cy);
+                range = (range.0.min(min), range.1.max(max));
+            }
+        }
+        range
     }
 }
 
@@ -201,6 +1180,18 @@ pub(crate) trait RasterSource {
     ) -> Option<Raster<Self::Type, Self::Container>>;
     fn bands(&self) -> usize;
 
+    /// Like `load`, but distinguishes tiles that don't exist, which are `Ok(None)`, from tiles that
+    /// exist but couldn't be loaded, such as when a download times out or a file fails to parse.
+    /// `RasterCache` logs the latter and doesn't record them as holes, so they're retried.
+    fn try_load(
+        &self,
+        context: &mut AssetLoadContext,
+        latitude: i16,
+        longitude: i16,
+    ) -> Result<Option<Raster<Self::Type, Self::Container>>, Error> {
+        Ok(self.load(context, latitude, longitude))
+    }
+
     /// Degrees of latitude and longitude covered by each raster.
     fn raster_size(&self) -> i16 {
         1
@@ -232,12 +1223,27 @@ impl<T: Into<f64> + Copy + 'static> RasterSource for AmbientOcclusionSource<T> {
 
 pub(crate) struct RasterCache<T: Into<f64> + Copy, C: Deref<Target = [T]>> {
     source: Box<dyn RasterSource<Type = T, Container = C>>,
-    holes: HashSet<(i16, i16)>,
+    /// Tiles that failed to load, along with when they did.
+    holes: HashMap<(i16, i16), Instant>,
+    /// How long to wait before retrying a tile that failed to load. If `None`, failed tiles are
+    /// never retried.
+    hole_ttl: Option<Duration>,
     rasters: LruCache<(i16, i16), Raster<T, C>>,
 }
 impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> RasterCache<T, C> {
     pub fn new(source: Box<dyn RasterSource<Type = T, Container = C>>, size: usize) -> Self {
-        Self { source, holes: HashSet::new(), rasters: LruCache::new(size) }
+        Self { source, holes: HashMap::new(), hole_ttl: None, rasters: LruCache::new(size) }
+    }
+    /// Sets how long tiles that failed to load are remembered before being retried. This also
+    /// applies to tiles that already failed, so a TTL of zero retries all of them on their next
+    /// use.
+    pub fn set_hole_ttl(&mut self, ttl: Option<Duration>) {
+        self.hole_ttl = ttl;
+    }
+    /// Changes how many rasters the cache holds, evicting the least recently used ones if it
+    /// currently contains more than `size`.
+    pub fn set_capacity(&mut self, size: usize) {
+        self.rasters.set_capacity(size);
     }
     pub fn get(
         &mut self,
@@ -245,21 +1251,29 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> RasterCache<T, C> {
         latitude: i16,
         longitude: i16,
     ) -> Option<&mut Raster<T, C>> {
-        let rs = self.source.raster_size();
-        let key = (latitude - (latitude % rs + rs) % rs, longitude - (longitude % rs + rs) % rs);
-        if self.holes.contains(&key) {
-            return None;
+        let key = self.tile_key(latitude, longitude);
+        if let Some(failed) = self.holes.get(&key) {
+            match self.hole_ttl {
+                Some(ttl) if failed.elapsed() >= ttl => {
+                    self.holes.remove(&key);
+                }
+                _ => return None,
+            }
         }
         if self.rasters.contains_key(&key) {
             return self.rasters.get_mut(&key);
         }
-        match self.source.load(context, key.0, key.1) {
-            Some(raster) => {
+        match self.source.try_load(context, key.0, key.1) {
+            Ok(Some(raster)) => {
                 self.rasters.insert(key, raster);
                 return self.rasters.get_mut(&key);
             }
-            None => {
-                self.holes.insert(key);
+            Ok(None) => {
+                self.holes.insert(key, Instant::now());
+                None
+            }
+            Err(e) => {
+                log::warn!("failed to load raster at {:?}: {}", key, e);
                 None
             }
         }
@@ -270,9 +1284,118 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> RasterCache<T, C> {
         latitude: f64,
         longitude: f64,
         band: usize,
+    ) -> Option<f64> {
+        let key = self.tile_key(latitude.floor() as i16, longitude.floor() as i16);
+        let raster = self.get(context, key.0, key.1)?;
+        let (x, y) = raster.cell_offsets(latitude, longitude);
+        let y = (raster.height - 1) as f64 - y;
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (fx, fy) = (x.floor() as usize, y.floor() as usize);
+        if fx + 1 < raster.width && fy + 1 < raster.height {
+            return raster.interpolate(latitude, longitude, band);
+        }
+
+        // The sample needs cells from the rasters to the east and/or south. Their positions are
+        // computed in this raster's grid, which the neighbors are assumed to continue.
+        let (width, height, cell_size) = (raster.width, raster.height, raster.cell_size);
+        let corner = (raster.latitude_llcorner, raster.longitude_llcorner);
+        let rs = self.source.raster_size();
+        let mut h = [[0.0; 2]; 2];
+        let mut complete = true;
+        'cells: for (dx, column) in h.iter_mut().enumerate() {
+            for (dy, value) in column.iter_mut().enumerate() {
+                let (cx, cy) = (fx + dx, fy + dy);
+                let tile = (
+                    if cy >= height { key.0 - rs } else { key.0 },
+                    if cx >= width { key.1 + rs } else { key.1 },
+                );
+                let cell_latitude = corner.0 + (height as f64 - 1.0 - cy as f64) * cell_size;
+                let cell_longitude = corner.1 + cx as f64 * cell_size;
+                match self
+                    .get(context, tile.0, tile.1)
+                    .and_then(|r| r.interpolate_nearest(cell_latitude, cell_longitude, band))
+                {
+                    Some(v) => *value = v,
+                    None => {
+                        complete = false;
+                        break 'cells;
+                    }
+                }
+            }
+        }
+        if !complete {
+            // A neighbor has no data, as along coastlines and the edges of a dataset, so clamp to
+            // this raster the way `Raster::interpolate` does.
+            return self.get(context, key.0, key.1)?.interpolate(latitude, longitude, band);
+        }
+
+        let (tx, ty) = (x - fx as f64, y - fy as f64);
+        let h0 = h[0][0] + (h[0][1] - h[0][0]) * ty;
+        let h1 = h[1][0] + (h[1][1] - h[1][0]) * ty;
+        Some(h0 + (h1 - h0) * tx)
+    }
+    /// Same as calling `interpolate` on each of `points` (latitude, longitude), but groups the
+    /// points by the raster containing them so that points in the interior of a raster share a
+    /// single lookup, however they are ordered. Points along the south and east edges also need
+    /// the neighboring rasters, which may be loaded again if the cache is too small to hold them.
+    /// Results are in the order of `points`.
+    pub fn interpolate_many(
+        &mut self,
+        context: &mut AssetLoadContext,
+        points: &[(f64, f64)],
+        band: usize,
+    ) -> Vec<Option<f64>> {
+        let mut groups: BTreeMap<(i16, i16), Vec<usize>> = BTreeMap::new();
+        for (i, &(latitude, longitude)) in points.iter().enumerate() {
+            let key = self.tile_key(latitude.floor() as i16, longitude.floor() as i16);
+            groups.entry(key).or_default().push(i);
+        }
+
+        let mut results = vec![None; points.len()];
+        for (key, indices) in groups {
+            // Points near the south and east edges also need the neighboring rasters, so they go
+            // through `interpolate` once the rest of the group is done.
+            let mut edge = Vec::new();
+            if let Some(raster) = self.get(context, key.0, key.1) {
+                for i in indices {
+                    let (latitude, longitude) = points[i];
+                    let (x, y) = raster.cell_offsets(latitude, longitude);
+                    let y = (raster.height - 1) as f64 - y;
+                    if x >= 0.0
+                        && y >= 0.0
+                        && (x.floor() as usize) + 1 < raster.width
+                        && (y.floor() as usize) + 1 < raster.height
+                    {
+                        results[i] = raster.interpolate(latitude, longitude, band);
+                    } else {
+                        edge.push(i);
+                    }
+                }
+            }
+            for i in edge {
+                results[i] = self.interpolate(context, points[i].0, points[i].1, band);
+            }
+        }
+        results
+    }
+    /// Returns the key of the raster containing the given whole degree of latitude and longitude.
+    fn tile_key(&self, latitude: i16, longitude: i16) -> (i16, i16) {
+        let rs = self.source.raster_size();
+        (latitude - (latitude % rs + rs) % rs, longitude - (longitude % rs + rs) % rs)
+    }
+    /// Same as `interpolate` but lets the caller choose the interpolation kernel.
+    pub fn interpolate_with(
+        &mut self,
+        context: &mut AssetLoadContext,
+        latitude: f64,
+        longitude: f64,
+        band: usize,
+        kernel: Kernel,
     ) -> Option<f64> {
         self.get(context, latitude.floor() as i16, longitude.floor() as i16)
-            .and_then(|raster| raster.interpolate(latitude, longitude, band))
+            .and_then(|raster| raster.interpolate_with(latitude, longitude, band, kernel))
     }
     pub fn nearest3(
         &mut self,
@@ -288,7 +1411,49 @@ impl<T: Into<f64> + Copy, C: Deref<Target = [T]>> RasterCache<T, C> {
     }
     /// Returns the approximate spacing of the rasters in meters, if known.
     pub fn spacing(&self) -> Option<f64> {
-        self.rasters.iter().next().map(|r| r.1.cell_size.to_radians() * coordinates::PLANET_RADIUS)
+        self.rasters.iter().next().map(|r| r.1.vertical_spacing())
+    }
+}
+
+/// Identifies which of the caches in a `CompositeRasterCache` provided a value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct SourceTag(pub &'static str);
+
+/// A stack of elevation caches that are consulted in order, so that lower priority sources fill in
+/// wherever higher priority ones have no coverage.
+pub(crate) struct CompositeRasterCache {
+    caches: Vec<(SourceTag, RasterCache<f32, Vec<f32>>)>,
+}
+impl CompositeRasterCache {
+    /// Creates a composite from caches listed from highest to lowest priority.
+    pub fn new(caches: Vec<(SourceTag, RasterCache<f32, Vec<f32>>)>) -> Self {
+        Self { caches }
+    }
+    /// Returns the elevation at each of `points` (latitude, longitude) along with which source
+    /// answered the query, loading each raster only once. See `RasterCache::interpolate_many`.
+    pub fn get_elevations_with_source(
+        &mut self,
+        context: &mut AssetLoadContext,
+        points: &[(f64, f64)],
+    ) -> Vec<Option<(f32, SourceTag)>> {
+        let mut results = vec![None; points.len()];
+        let mut remaining: Vec<usize> = (0..points.len()).collect();
+        for (tag, cache) in &mut self.caches {
+            if remaining.is_empty() {
+                break;
+            }
+            let queries: Vec<(f64, f64)> = remaining.iter().map(|&i| points[i]).collect();
+            let elevations = cache.interpolate_many(context, &queries, 0);
+            let mut missing = Vec::new();
+            for (i, elevation) in remaining.into_iter().zip(elevations) {
+                match elevation {
+                    Some(e) => results[i] = Some((e as f32, *tag)),
+                    None => missing.push(i),
+                }
+            }
+            remaining = missing;
+        }
+        results
     }
 }
 
@@ -297,13 +1462,15 @@ pub(crate) struct GlobalRaster<T: Into<f64> + Copy, C: Index<usize, Output = T>
     pub width: usize,
     pub height: usize,
     pub bands: usize,
+    /// Radius in meters of the body the raster covers.
+    pub planet_radius: f64,
     pub values: C,
 }
 impl<T: Into<f64> + Copy, C: Index<usize, Output = T>> GlobalRaster<T, C> {
     /// Returns the approximate grid spacing in meters.
     pub fn spacing(&self) -> f64 {
-        let sx = 2.0 * PI * coordinates::PLANET_RADIUS / self.width as f64;
-        let sy = PI * coordinates::PLANET_RADIUS / self.height as f64;
+        let sx = 2.0 * PI * self.planet_radius / self.width as f64;
+        let sy = PI * self.planet_radius / self.height as f64;
         sx.min(sy)
     }
 
@@ -315,7 +1482,10 @@ impl<T: Into<f64> + Copy, C: Index<usize, Output = T>> GlobalRaster<T, C> {
 
     pub fn interpolate(&self, latitude: f64, longitude: f64, band: usize) -> f64 {
         assert!(latitude >= -90.0 && latitude <= 90.0);
-        assert!(longitude >= -180.0 && latitude <= 180.0);
+        assert!(longitude.is_finite());
+
+        // The raster is periodic in x, so bring longitudes outside [-180, 180) back into range.
+        let longitude = (longitude + 180.0).rem_euclid(360.0) - 180.0;
 
         let x = (longitude + 180.0) / 360.0 * self.width as f64 - 0.5;
         let y = (90.0 - latitude) / 180.0 * self.height as f64 - 0.5;
@@ -332,3 +1502,857 @@ impl<T: Into<f64> + Copy, C: Index<usize, Output = T>> GlobalRaster<T, C> {
         h0 + (h1 - h0) * (x - fx as f64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::AssetLoadContextBuf;
+    use std::cell::Cell;
+
+    #[test]
+    fn interpolation_kernels() {
+        // Values increase by one per cell from west to east.
+        let raster = Raster::new(
+            8,
+            8,
+            1,
+            1.0,
+            0.0,
+            0.0,
+            (0..64).map(|i| (i % 8) as f32).collect::<Vec<f32>>(),
+        );
+
+        let sample =
+            |longitude, kernel| raster.interpolate_with(4.0, longitude, 0, kernel).unwrap();
+
+        assert_eq!(sample(3.1, Kernel::Nearest), sample(3.4, Kernel::Nearest));
+        assert_eq!(sample(3.4, Kernel::Nearest), 3.0);
+        assert_eq!(sample(3.6, Kernel::Nearest), 4.0);
+
+        assert!((sample(3.25, Kernel::Bilinear) - 3.25).abs() < 1e-9);
+        assert!((sample(3.75, Kernel::Bilinear) - 3.75).abs() < 1e-9);
+        assert!((sample(3.25, Kernel::Bicubic) - 3.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nearest_categorical() {
+        // Five bands of class ids, where band `b` of cell `x` holds `10 * b + x`.
+        let raster = Raster::new(
+            4,
+            4,
+            5,
+            1.0,
+            0.0,
+            0.0,
+            (0..80).map(|i| (10 * (i % 5) + (i / 5) % 4) as u8).collect::<Vec<u8>>(),
+        );
+
+        assert_eq!(raster.nearest(2.0, 1.4, 4), Some(41.0));
+        assert_eq!(raster.nearest(2.0, 1.6, 4), Some(42.0));
+        assert_eq!(raster.nearest(2.0, 3.4, 0), Some(3.0));
+        assert_eq!(raster.nearest(2.0, -0.1, 0), None);
+        assert_eq!(raster.nearest(4.5, 1.0, 0), None);
+    }
+
+    #[test]
+    fn bicubic_on_quadratic() {
+        let surface = |x: f64, y: f64| 0.5 * x * x + 0.25 * y * y - x * y;
+        let raster = Raster::new(
+            16,
+            16,
+            1,
+            1.0,
+            0.0,
+            0.0,
+            (0..256).map(|i| surface((i % 16) as f64, (i / 16) as f64)).collect::<Vec<_>>(),
+        );
+
+        let (mut bilinear_error, mut bicubic_error) = (0.0, 0.0);
+        for i in 0..40 {
+            let (x, y) = (2.0 + i as f64 * 0.29, 3.0 + i as f64 * 0.23);
+            let (latitude, longitude) = (15.0 - y, x);
+            let expected = surface(x, y);
+            let bilinear = raster.interpolate(latitude, longitude, 0).unwrap();
+            let bicubic = raster.interpolate_bicubic(latitude, longitude, 0).unwrap();
+            bilinear_error += (bilinear - expected).abs();
+            bicubic_error += (bicubic - expected).abs();
+        }
+        assert!(bicubic_error < bilinear_error / 10.0);
+
+        // Samples near the edges clamp the window, and only points off the raster are rejected.
+        assert!(raster.interpolate_bicubic(15.0, 15.5, 0).is_some());
+        assert!(raster.interpolate_bicubic(0.5, 0.0, 0).is_some());
+        assert_eq!(raster.interpolate_bicubic(8.0, -0.5, 0), None);
+        assert_eq!(raster.interpolate_bicubic(8.0, 16.5, 0), None);
+    }
+
+    #[test]
+    fn pad_reflect() {
+        let raster = Raster::new(2, 2, 1, 0.5, 10.0, 20.0, vec![1u8, 2, 3, 4]);
+
+        let padded = raster.pad(1, BorderMode::Reflect);
+        assert_eq!((padded.width, padded.height), (4, 4));
+        assert_eq!((padded.latitude_llcorner, padded.longitude_llcorner), (9.5, 19.5));
+        #[rustfmt::skip]
+        let expected = vec![
+            1.0, 1.0, 2.0, 2.0,
+            1.0, 1.0, 2.0, 2.0,
+            3.0, 3.0, 4.0, 4.0,
+            3.0, 3.0, 4.0, 4.0,
+        ];
+        assert_eq!(padded.values, expected);
+
+        let padded = raster.pad(1, BorderMode::Zero);
+        assert_eq!(&padded.values[4..8], &[0.0, 1.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn cell_area() {
+        let raster = Raster::new(1, 121, 1, 0.5, 0.0, 0.0, vec![0.0f32; 121]);
+
+        let equator = raster.cell_area_m2(0);
+        let sixty = raster.cell_area_m2(120);
+        assert!((sixty / equator - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn web_mercator() {
+        // Rows are 1km apart in projected meters, with 60N a row and a half above the bottom edge.
+        let raster = Raster {
+            projection: Projection::WebMercator,
+            ..Raster::new(
+                4,
+                8,
+                1,
+                1000.0,
+                8399737.89 - 1500.0,
+                0.0,
+                (0..32).map(|i| (7 - i / 4) as f32).collect::<Vec<_>>(),
+            )
+        };
+
+        assert!((raster.interpolate(60.0, 0.001, 0).unwrap() - 1.5).abs() < 1e-5);
+        assert!((raster.interpolate(60.01, 0.001, 0).unwrap() - 3.7267).abs() < 1e-3);
+        assert_eq!(raster.interpolate(60.0, -0.001, 0), None);
+
+        // Projected meters shrink to half their length on the ground at 60N.
+        let latitude = Projection::WebMercator.latitude(raster.latitude_llcorner + 1500.0);
+        assert!((latitude - 60.0).abs() < 1e-6);
+        assert!((raster.horizontal_spacing(1) - 500.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn utm() {
+        let north = Projection::Utm { zone: 32, north: true };
+        let (easting, northing) = north.project(60.0, 9.0);
+        assert!((easting - 500000.0).abs() < 1e-6);
+        assert!((northing - 6651411.19).abs() < 0.01);
+
+        // Points either side of the central meridian mirror each other.
+        let (east, _) = north.project(60.0, 10.0);
+        let (west, _) = north.project(60.0, 8.0);
+        assert!((east - 500000.0 - (500000.0 - west)).abs() < 1e-6);
+
+        let south = Projection::Utm { zone: 32, north: false };
+        assert!((south.project(-60.0, 9.0).1 - (10000000.0 - 6651411.19)).abs() < 0.01);
+    }
+
+    #[test]
+    fn planet_radius() {
+        let mut raster = Raster::new(
+            16,
+            16,
+            1,
+            0.01,
+            0.0,
+            0.0,
+            (0..256).map(|i| ((i % 16) * (i / 16)) as f32 * 20.0).collect(),
+        );
+        let earth = raster.vertical_spacing();
+        let earth_occlusion = raster.ambient_occlusion();
+
+        // The same heights spread over the Moon's much smaller cells are steeper.
+        raster.planet_radius = 1737400.0;
+        let ratio = 1737400.0 / coordinates::PLANET_RADIUS;
+        assert!((raster.vertical_spacing() / earth - ratio).abs() < 1e-9);
+        assert_ne!(raster.ambient_occlusion().values, earth_occlusion.values);
+    }
+
+    #[test]
+    fn normal_map_spaces() {
+        // A slope rising one meter eastward for every meter traveled, centered on lat=0, long=0.
+        let mut raster = Raster::new(3, 3, 1, 0.001, -0.001, -0.001, vec![0.0f32; 9]);
+        let spacing = raster.vertical_spacing();
+        for y in 0..3 {
+            for x in 0..3 {
+                raster.values[x + y * 3] = (x as f64 * spacing) as f32;
+            }
+        }
+
+        let close = |a: &[u8], b: [u8; 3]| {
+            a.iter().zip(&b).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 1)
+        };
+
+        // Tilted away from east: (-1, 0, 1) / sqrt(2).
+        let tangent = raster.to_normal_map(NormalSpace::Tangent);
+        assert!(close(&tangent.values[12..15], [37, 128, 218]));
+
+        // At lat=0, long=0 east is +Y and up is +X, so the normal is (1, -1, 0) / sqrt(2).
+        let object = raster.to_normal_map(NormalSpace::Object);
+        assert!(close(&object.values[12..15], [218, 37, 128]));
+    }
+
+    #[test]
+    fn band_view() {
+        let raster = Raster::new(2, 1, 3, 1.0, 0.0, 0.0, vec![1u8, 2, 3, 4, 5, 6]);
+
+        let band = raster.band_view(1);
+        assert_eq!(band.bands, 1);
+        assert_eq!(band.values, vec![2, 5]);
+    }
+
+    #[test]
+    fn ambient_occlusion_within() {
+        let raster = Raster::new(
+            32,
+            32,
+            1,
+            1.0 / 3600.0,
+            0.0,
+            0.0,
+            (0..32 * 32)
+                .map(|i| {
+                    let (x, y) = ((i % 32) as f64, (i / 32) as f64);
+                    20.0 * (x * 0.7).sin() * (y * 0.5).cos() + 3.0 * x
+                })
+                .collect::<Vec<f64>>(),
+        );
+
+        let (unbounded, unbounded_hull_len) =
+            raster.ambient_occlusion_hull(None, OcclusionDirections::Four);
+        let (bounded, bounded_hull_len) =
+            raster.ambient_occlusion_hull(Some(300.0), OcclusionDirections::Four);
+
+        assert!(bounded_hull_len < unbounded_hull_len);
+        for (a, b) in unbounded.values.iter().zip(bounded.values.iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn ambient_occlusion_directions() {
+        const ALL_DIRECTIONS: [OcclusionDirections; 3] =
+            [OcclusionDirections::Four, OcclusionDirections::Eight, OcclusionDirections::Sixteen];
+        let raster = |values: Vec<f64>| Raster::new(16, 16, 1, 1.0 / 3600.0, 0.0, 0.0, values);
+
+        // Flat terrain is unoccluded no matter how many directions are sampled.
+        let flat = raster(vec![0.0; 256]);
+        for &directions in &ALL_DIRECTIONS {
+            let ao = flat.ambient_occlusion_with_directions(directions);
+            assert!(ao.values.iter().all(|&v| v == 255));
+        }
+        let default = flat.ambient_occlusion();
+        assert_eq!(
+            default.values,
+            flat.ambient_occlusion_with_directions(OcclusionDirections::Four).values
+        );
+
+        // The bottom of a bowl is occluded from every direction.
+        let bowl = raster(
+            (0..256)
+                .map(|i| {
+                    let (x, y) = ((i % 16) as f64 - 7.5, (i / 16) as f64 - 7.5);
+                    10.0 * (x * x + y * y)
+                })
+                .collect(),
+        );
+        for &directions in &ALL_DIRECTIONS {
+            let ao = bowl.ambient_occlusion_with_directions(directions);
+            assert!(ao.values[7 + 7 * 16] < 255);
+            assert!(ao.values[7 + 7 * 16] > 0);
+        }
+    }
+
+    #[test]
+    fn ambient_occlusion_f32() {
+        let raster = Raster::new(
+            16,
+            16,
+            1,
+            1.0 / 3600.0,
+            0.0,
+            0.0,
+            (0..256)
+                .map(|i| {
+                    let (x, y) = ((i % 16) as f64 - 7.5, (i / 16) as f64 - 7.5);
+                    0.5 * (x * x + y * y)
+                })
+                .collect::<Vec<f64>>(),
+        );
+
+        let ao = raster.ambient_occlusion_f32();
+        let quantized = raster.ambient_occlusion();
+        assert!(ao.values.iter().all(|&v| v >= 0.0 && v <= 1.0));
+        assert!(ao.values[7 + 7 * 16] < 1.0);
+        for (&a, &q) in ao.values.iter().zip(&quantized.values) {
+            assert_eq!((a * 255.0) as u8, q);
+        }
+
+        // Values between quantization levels are kept.
+        assert!(ao.values.iter().any(|&v| (v * 255.0).fract() != 0.0));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let raster = Raster::new(
+            3,
+            2,
+            2,
+            0.25,
+            -12.5,
+            100.0,
+            (0..12).map(|i| i as f32 * 1.5 - 4.0).collect::<Vec<f32>>(),
+        );
+
+        let (mut header, mut data) = (Vec::new(), Vec::new());
+        raster.write_to(&mut header, &mut data).unwrap();
+        let read = Raster::<f32>::read_from(&header[..], &data[..]).unwrap();
+        assert_eq!((read.width, read.height, read.bands), (3, 2, 2));
+        assert_eq!(read.cell_size, 0.25);
+        assert_eq!((read.latitude_llcorner, read.longitude_llcorner), (-12.5, 100.0));
+        assert_eq!(read.values, raster.values);
+
+        // Rasters of other planets keep their radius.
+        let moon = Raster { planet_radius: 1737.4e3, ..raster.clone() };
+        let mut moon_header = Vec::new();
+        moon.write_to(&mut moon_header, Vec::new()).unwrap();
+        let read = Raster::<f32>::read_from(&moon_header[..], &data[..]).unwrap();
+        assert_eq!(read.planet_radius, 1737.4e3);
+
+        // The header is what `MMappedAsset` stores in the .hdr file, and the values are raw little
+        // endian floats like its .data file.
+        assert_eq!(header, bincode::serialize(&raster.mmapped_header()).unwrap());
+        assert_eq!(data.len(), 12 * 4);
+        assert_eq!(&data[4..8], &(-2.5f32).to_le_bytes());
+
+        // Truncated data is an error rather than a short raster, and so is a header whose size
+        // overflows.
+        assert!(Raster::<f32>::read_from(&header[..], &data[..data.len() - 1]).is_err());
+        let mut huge = raster.mmapped_header();
+        huge.width = usize::MAX / 2;
+        let huge = bincode::serialize(&huge).unwrap();
+        assert!(Raster::<f32>::read_from(&huge[..], &data[..]).is_err());
+
+        // Rasters that the header can't describe are rejected.
+        let projected = Raster { projection: Projection::WebMercator, ..raster.clone() };
+        assert!(projected.write_to(Vec::new(), Vec::new()).is_err());
+        let masked = Raster { nodata: Some(BitVec::from_elem(6, false)), ..raster };
+        assert!(masked.write_to(Vec::new(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn transpose_and_flip() {
+        let raster = Raster::new(3, 2, 2, 0.5, 10.0, 20.0, (0..12).collect::<Vec<u8>>());
+
+        let transposed = raster.transpose();
+        assert_eq!((transposed.width, transposed.height), (2, 3));
+        assert_eq!(transposed.latitude_llcorner, 9.5);
+        assert_eq!(&transposed.values[..4], &[0, 1, 6, 7]);
+        let restored = transposed.transpose();
+        assert_eq!(restored.values, raster.values);
+        assert_eq!((restored.width, restored.height), (3, 2));
+        assert_eq!((restored.latitude_llcorner, restored.longitude_llcorner), (10.0, 20.0));
+
+        // Flips cover the same area, so the corners don't move.
+        let flipped = raster.flip_vertical();
+        assert_eq!(&flipped.values[..2], &[6, 7]);
+        assert_eq!((flipped.latitude_llcorner, flipped.longitude_llcorner), (10.0, 20.0));
+        assert_eq!(flipped.flip_vertical().values, raster.values);
+
+        let flipped = raster.flip_horizontal();
+        assert_eq!(&flipped.values[..2], &[4, 5]);
+        assert_eq!((flipped.latitude_llcorner, flipped.longitude_llcorner), (10.0, 20.0));
+        assert_eq!(flipped.flip_horizontal().values, raster.values);
+
+        // The nodata mask moves along with the values.
+        let mut masked = raster.band_view(1);
+        masked.nodata = Some((0..6).map(|i| i == 2).collect());
+        let mask = masked.transpose().flip_horizontal().nodata.unwrap();
+        assert_eq!(mask.iter().position(|b| b), Some(5));
+    }
+
+    #[test]
+    fn mosaic() {
+        let tile = |latitude: f64, longitude: f64, values: Vec<u8>| {
+            Raster::new(2, 2, 1, 1.0, latitude, longitude, values)
+        };
+
+        // Two tiles side by side, and a third further north leaving a one row gap.
+        let tiles = [
+            tile(0.0, 0.0, vec![1, 2, 3, 4]),
+            tile(0.0, 2.0, vec![5; 4]),
+            tile(3.0, 0.0, vec![7; 4]),
+        ];
+        let mosaic = Raster::mosaic(&tiles, 0).unwrap();
+        assert_eq!((mosaic.width, mosaic.height), (4, 5));
+        assert_eq!((mosaic.latitude_llcorner, mosaic.longitude_llcorner), (0.0, 0.0));
+        #[rustfmt::skip]
+        let expected = vec![
+            7, 7, 0, 0,
+            7, 7, 0, 0,
+            0, 0, 0, 0,
+            1, 2, 5, 5,
+            3, 4, 5, 5,
+        ];
+        assert_eq!(mosaic.values, expected);
+        assert_eq!(mosaic.interpolate(0.0, 1.0, 0), Some(4.0));
+        assert_eq!(mosaic.void_cells, 8);
+        assert!(mosaic.nodata.is_none());
+
+        // Overlapping cells come from the last tile, and gaps are marked as nodata.
+        let mut masked = tile(0.0, 1.0, vec![9; 4]);
+        masked.nodata = Some(BitVec::from_elem(4, false));
+        let mosaic = Raster::mosaic(&[tiles[0].clone(), masked, tile(1.0, 3.0, vec![8; 4])], 0);
+        let mosaic = mosaic.unwrap();
+        assert_eq!(mosaic.width, 5);
+        assert_eq!(mosaic.values, vec![0, 0, 0, 8, 8, 1, 9, 9, 8, 8, 3, 9, 9, 0, 0]);
+        assert_eq!(mosaic.void_cells, 5);
+
+        // Voids are counted once per cell of the result, so ones hidden by a later tile or shared
+        // by overlapping tiles don't add up.
+        let void_at = |raster: &Raster<u8>, cell: usize| {
+            let mut nodata = BitVec::from_elem(4, false);
+            nodata.set(cell, true);
+            Raster { void_cells: 1, nodata: Some(nodata), ..raster.clone() }
+        };
+        let hidden = void_at(&tiles[0], 3);
+        let shared = void_at(&tile(0.0, 1.0, vec![9; 4]), 0);
+        let mosaic = Raster::mosaic(&[hidden, shared.clone(), shared], 0).unwrap();
+        assert_eq!(mosaic.void_cells, 1);
+        assert_eq!(mosaic.nodata.unwrap().iter().position(|void| void), Some(1));
+
+        let mut coarse = tile(0.0, 2.0, vec![0; 4]);
+        coarse.cell_size = 0.5;
+        assert!(Raster::mosaic(&[tiles[0].clone(), coarse], 0).is_err());
+        assert!(Raster::mosaic(&[tiles[0].clone(), tile(0.0, 2.5, vec![0; 4])], 0).is_err());
+        assert!(Raster::<u8>::mosaic(&[], 0).is_err());
+    }
+
+    /// Source that provides flat rasters of a fixed elevation over a set of tiles.
+    struct FlatSource {
+        tiles: Vec<(i16, i16)>,
+        elevation: f32,
+    }
+    impl RasterSource for FlatSource {
+        type Type = f32;
+        type Container = Vec<f32>;
+        fn load(
+            &self,
+            _context: &mut AssetLoadContext,
+            latitude: i16,
+            longitude: i16,
+        ) -> Option<Raster<f32>> {
+            if !self.tiles.contains(&(latitude, longitude)) {
+                return None;
+            }
+            Some(Raster::new(
+                4,
+                4,
+                1,
+                1.0 / 3.0,
+                latitude as f64,
+                longitude as f64,
+                vec![self.elevation; 16],
+            ))
+        }
+        fn bands(&self) -> usize {
+            1
+        }
+    }
+
+    /// Source whose rasters don't overlap: each covers its tile from the north edge down to one
+    /// cell short of the south edge, and from the west edge to one cell short of the east edge.
+    /// Values are `longitude + 10 * latitude`.
+    struct GradientSource {
+        tiles: Vec<(i16, i16)>,
+    }
+    impl RasterSource for GradientSource {
+        type Type = f32;
+        type Container = Vec<f32>;
+        fn load(
+            &self,
+            _context: &mut AssetLoadContext,
+            latitude: i16,
+            longitude: i16,
+        ) -> Option<Raster<f32>> {
+            if !self.tiles.contains(&(latitude, longitude)) {
+                return None;
+            }
+            let cell_size = 0.25;
+            let latitude_llcorner = latitude as f64 + cell_size;
+            let longitude_llcorner = longitude as f64;
+            Some(Raster::new(
+                4,
+                4,
+                1,
+                cell_size,
+                latitude_llcorner,
+                longitude_llcorner,
+                (0..16)
+                    .map(|i| {
+                        let x = longitude_llcorner + (i % 4) as f64 * cell_size;
+                        let y = latitude_llcorner + (3 - i / 4) as f64 * cell_size;
+                        (x + 10.0 * y) as f32
+                    })
+                    .collect(),
+            ))
+        }
+        fn bands(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn interpolate_across_tiles() {
+        let source = GradientSource { tiles: vec![(10, 20), (10, 21), (9, 20), (9, 21)] };
+        let mut cache = RasterCache::new(Box::new(source), 4);
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+
+        let mut sample = |latitude, longitude| {
+            let value = cache.interpolate(&mut context, latitude, longitude, 0).unwrap();
+            assert!((value - (longitude + 10.0 * latitude)).abs() < 1e-4);
+        };
+        sample(10.5, 20.5);
+        sample(10.5, 20.9);
+        sample(10.1, 20.5);
+        sample(10.1, 20.9);
+
+        // Samples that need a missing neighbor are clamped to the edge of their own tile.
+        let source = GradientSource { tiles: vec![(10, 20), (10, 21)] };
+        let mut cache = RasterCache::new(Box::new(source), 4);
+        assert!(cache.interpolate(&mut context, 10.5, 20.9, 0).is_some());
+        let value = cache.interpolate(&mut context, 10.1, 20.5, 0).unwrap();
+        assert!((value - (20.5 + 10.0 * 10.25)).abs() < 1e-4);
+        let value = cache.interpolate(&mut context, 10.1, 20.9, 0).unwrap();
+        assert!((value - (20.75 + 10.0 * 10.25)).abs() < 1e-4);
+
+        // The same goes for a tile with no east neighbor at all.
+        let source = GradientSource { tiles: vec![(10, 20)] };
+        let mut cache = RasterCache::new(Box::new(source), 4);
+        let value = cache.interpolate(&mut context, 10.5, 20.9, 0).unwrap();
+        assert!((value - (20.75 + 10.0 * 10.5)).abs() < 1e-4);
+        assert_eq!(cache.interpolate(&mut context, 10.5, 21.5, 0), None);
+    }
+
+    #[test]
+    fn interpolate_many() {
+        /// Counts how many rasters `GradientSource` is asked for.
+        struct CountingSource(GradientSource, Rc<Cell<usize>>);
+        impl RasterSource for CountingSource {
+            type Type = f32;
+            type Container = Vec<f32>;
+            fn load(
+                &self,
+                context: &mut AssetLoadContext,
+                latitude: i16,
+                longitude: i16,
+            ) -> Option<Raster<f32>> {
+                self.1.set(self.1.get() + 1);
+                self.0.load(context, latitude, longitude)
+            }
+            fn bands(&self) -> usize {
+                1
+            }
+        }
+
+        let loads = Rc::new(Cell::new(0));
+        let source = GradientSource { tiles: vec![(10, 20), (10, 21)] };
+        let mut cache = RasterCache::new(Box::new(CountingSource(source, loads.clone())), 1);
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+
+        // Alternate between the two tiles, which would reload a raster for every point if they
+        // were sampled one at a time.
+        let points: Vec<_> = (0..8).map(|i| (10.5, 20.5 + (i % 2) as f64)).collect();
+        let values = cache.interpolate_many(&mut context, &points, 0);
+        assert_eq!(loads.get(), 2);
+        for (&(latitude, longitude), value) in points.iter().zip(values) {
+            assert!((value.unwrap() - (longitude + 10.0 * latitude)).abs() < 1e-4);
+        }
+
+        // Points that need a missing neighbor are clamped to their own tile, while those outside
+        // any tile come back as `None`.
+        let points = [(10.1, 20.5), (12.5, 20.5), (10.5, 20.9)];
+        let values = cache.interpolate_many(&mut context, &points, 0);
+        assert!((values[0].unwrap() - (20.5 + 10.0 * 10.25)).abs() < 1e-4);
+        assert_eq!(values[1], None);
+        assert!(values[2].is_some());
+    }
+
+    #[test]
+    fn composite_source_tags() {
+        let primary = FlatSource { tiles: vec![(10, 20)], elevation: 100.0 };
+        let secondary = FlatSource { tiles: vec![(10, 20), (11, 20)], elevation: 50.0 };
+        let mut composite = CompositeRasterCache::new(vec![
+            (SourceTag("primary"), RasterCache::new(Box::new(primary), 4)),
+            (SourceTag("secondary"), RasterCache::new(Box::new(secondary), 4)),
+        ]);
+
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+        let points = [(10.5, 20.5), (11.5, 20.5), (12.5, 20.5)];
+        assert_eq!(
+            composite.get_elevations_with_source(&mut context, &points),
+            vec![Some((100.0, SourceTag("primary"))), Some((50.0, SourceTag("secondary"))), None]
+        );
+    }
+
+    #[test]
+    fn shrink_raster_cache() {
+        let source = FlatSource { tiles: (0..4).map(|i| (0, i)).collect(), elevation: 0.0 };
+        let mut cache = RasterCache::new(Box::new(source), 4);
+
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+        for i in 0..4 {
+            assert!(cache.get(&mut context, 0, i).is_some());
+        }
+        // Touch the oldest raster so that it becomes the most recently used.
+        cache.get(&mut context, 0, 0);
+
+        cache.set_capacity(2);
+        assert_eq!(cache.rasters.len(), 2);
+        assert!(cache.rasters.contains_key(&(0, 0)));
+        assert!(cache.rasters.contains_key(&(0, 3)));
+    }
+
+    /// Source that fails to load a fixed number of times before succeeding.
+    struct FlakySource {
+        inner: FlatSource,
+        failures: Cell<usize>,
+        /// Whether the failures are reported as transient errors rather than missing tiles.
+        transient: bool,
+    }
+    impl RasterSource for FlakySource {
+        type Type = f32;
+        type Container = Vec<f32>;
+        fn load(
+            &self,
+            context: &mut AssetLoadContext,
+            latitude: i16,
+            longitude: i16,
+        ) -> Option<Raster<f32>> {
+            self.try_load(context, latitude, longitude).ok().flatten()
+        }
+        fn try_load(
+            &self,
+            context: &mut AssetLoadContext,
+            latitude: i16,
+            longitude: i16,
+        ) -> Result<Option<Raster<f32>>, Error> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                if self.transient {
+                    return Err(anyhow::anyhow!("timed out"));
+                }
+                return Ok(None);
+            }
+            Ok(self.inner.load(context, latitude, longitude))
+        }
+        fn bands(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn retry_holes() {
+        let flaky = |failures| FlakySource {
+            inner: FlatSource { tiles: vec![(0, 0)], elevation: 10.0 },
+            failures: Cell::new(failures),
+            transient: false,
+        };
+        let mut context = AssetLoadContextBuf::new();
+        let mut context = context.context("", 1);
+
+        // By default a failed tile stays missing until the TTL is changed.
+        let mut cache = RasterCache::new(Box::new(flaky(1)), 4);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+        cache.set_hole_ttl(Some(Duration::from_secs(0)));
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), Some(10.0));
+
+        // With a TTL, holes expire on their own.
+        let mut cache = RasterCache::new(Box::new(flaky(1)), 4);
+        cache.set_hole_ttl(Some(Duration::from_secs(0)));
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), Some(10.0));
+
+        let mut cache = RasterCache::new(Box::new(flaky(1)), 4);
+        cache.set_hole_ttl(Some(Duration::from_secs(3600)));
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+
+        // Transient failures are retried on the next request regardless.
+        let mut cache =
+            RasterCache::new(Box::new(FlakySource { transient: true, ..flaky(1) }), 4);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), None);
+        assert_eq!(cache.interpolate(&mut context, 0.5, 0.5, 0), Some(10.0));
+    }
+
+    #[test]
+    fn min_max_pyramid() {
+        let raster = Raster::new(
+            8,
+            6,
+            1,
+            1.0,
+            0.0,
+            0.0,
+            (0..48).map(|i| (i % 8 + i / 8) as f32).collect::<Vec<f32>>(),
+        );
+        let pyramid = MinMaxPyramid::new(&raster);
+
+        assert_eq!(pyramid.levels(), 4);
+        assert_eq!(pyramid.cell(3, 0, 0), (0.0, 12.0));
+        assert_eq!(pyramid.cell(1, 0, 0), (0.0, 2.0));
+        assert_eq!(pyramid.cell(2, 1, 1), (8.0, 12.0));
+
+        assert_eq!(pyramid.height_range(0..8, 0..6), (0.0, 12.0));
+        assert_eq!(pyramid.height_range(2..4, 2..4), (4.0, 6.0));
+        let (min, max) = pyramid.height_range(1..3, 0..1);
+        assert!(min <= 1.0 && max >= 2.0 && max - min < 12.0);
+    }
+
+    #[test]
+    fn sobel_step_edge() {
+        // Cells in the western half are at 0 meters and those in the eastern half at 100 meters.
+        let raster = Raster::new(
+            8,
+            8,
+            1,
+            1.0 / 3600.0,
+            0.0,
+            0.0,
+            (0..64).map(|i| if i % 8 < 4 { 0.0 } else { 100.0 }).collect::<Vec<f32>>(),
+        );
+        let magnitude = raster.sobel_magnitude();
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let m = magnitude.values[x + y * 8];
+                if x == 3 || x == 4 {
+                    assert!(m > 1.0);
+                } else {
+                    assert!(m.abs() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resample() {
+        let raster = Raster::new(
+            65,
+            65,
+            1,
+            1.0 / 64.0,
+            37.0,
+            -122.0,
+            (0..65 * 65)
+                .map(|i| {
+                    let (x, y) = ((i % 65) as f32, (i / 65) as f32);
+                    100.0 * (0.1 * x).sin() * (0.08 * y).cos()
+                })
+                .collect::<Vec<f32>>(),
+        );
+
+        let same = raster.resample(65, 65).unwrap();
+        assert_eq!(same.cell_size, raster.cell_size);
+        assert!(same.values.iter().zip(&raster.values).all(|(&a, &b)| a == b as f64));
+
+        let half = raster.resample(33, 33).unwrap();
+        assert_eq!(half.cell_size, 2.0 / 64.0);
+        assert_eq!(half.latitude_llcorner, 37.0);
+        assert_eq!(half.longitude_llcorner, -122.0);
+
+        let round_trip = half.resample(65, 65).unwrap();
+        for (&a, &b) in round_trip.values.iter().zip(&raster.values) {
+            assert!((a - b as f64).abs() < 0.5, "{} vs {}", a, b);
+        }
+
+        assert!(raster.resample(33, 17).is_err());
+        assert!(raster.resample(1, 1).is_err());
+    }
+
+    #[test]
+    fn hillshade() {
+        // Heights change by 20 meters per row, so slopes are roughly 33 degrees.
+        let slope = |f: fn(usize) -> f32| {
+            Raster::new(
+                4,
+                4,
+                1,
+                1.0 / 3600.0,
+                45.0,
+                0.0,
+                (0..16).map(|i| f(i / 4)).collect::<Vec<f32>>(),
+            )
+        };
+        let mean = |r: Raster<u8>| r.values.iter().map(|&v| v as f64).sum::<f64>() / 16.0;
+
+        // Rows run north to south, so the south facing slope is highest in the first row.
+        let south_facing = slope(|y| 20.0 * (3 - y) as f32);
+        let north_facing = slope(|y| 20.0 * y as f32);
+        assert!(mean(south_facing.hillshade(0.0, 30.0)) < mean(north_facing.hillshade(0.0, 30.0)));
+
+        let flat = slope(|_| 100.0).hillshade(0.0, 30.0);
+        assert!(flat.values.iter().all(|&v| v == flat.values[0]));
+        assert!(flat.values[0] == 127 || flat.values[0] == 128);
+    }
+
+    #[test]
+    fn affine_warp() {
+        let raster = Raster::new(3, 2, 1, 1.0, 0.0, 0.0, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let identity = raster.affine_warp([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]], 3, 2, -1.0).unwrap();
+        assert_eq!(identity.values, raster.values);
+
+        // Rotate by 90 degrees, shifting so the result lands in the positive quadrant.
+        let rotated = raster.affine_warp([[0.0, -1.0, 1.0], [1.0, 0.0, 0.0]], 2, 3, -1.0).unwrap();
+        assert_eq!(rotated.values, raster.transpose().flip_horizontal().values);
+
+        let shifted = raster.affine_warp([[1.0, 0.0, 0.5], [0.0, 1.0, 0.0]], 3, 2, -1.0).unwrap();
+        assert_eq!(shifted.values, vec![-1.0, 1.5, 2.5, -1.0, 4.5, 5.5]);
+
+        let singular = raster.affine_warp([[1.0, 2.0, 0.0], [2.0, 4.0, 0.0]], 3, 2, -1.0);
+        assert!(singular.is_err());
+        let nan = raster.affine_warp([[f64::NAN, 0.0, 0.0], [0.0, 1.0, 0.0]], 3, 2, -1.0);
+        assert!(nan.is_err());
+    }
+
+    #[test]
+    fn global_interpolate_wraps_longitude() {
+        // Values increase by one per cell from west to east.
+        let raster: GlobalRaster<f32> = GlobalRaster {
+            width: 8,
+            height: 4,
+            bands: 1,
+            planet_radius: coordinates::PLANET_RADIUS,
+            values: (0..32).map(|i| (i % 8) as f32).collect(),
+        };
+
+        let x = 359.9 / 360.0 * 8.0 - 0.5;
+        let expected = 7.0 - 7.0 * (x - 7.0);
+        assert!((raster.interpolate(0.0, 179.9, 0) - expected).abs() < 1e-9);
+
+        assert_eq!(raster.interpolate(0.0, 180.0, 0), raster.interpolate(0.0, -180.0, 0));
+        assert_eq!(raster.interpolate(0.0, 180.0, 0), 3.5);
+
+        // Between the last column and the first, wrapped around the antimeridian.
+        let expected = -7.0 * (5.0 / 360.0 * 8.0 - 0.5);
+        assert!((raster.interpolate(0.0, 185.0, 0) - expected).abs() < 1e-9);
+        let wrapped = raster.interpolate(0.0, 185.0, 0) - raster.interpolate(0.0, -175.0, 0);
+        assert!(wrapped.abs() < 1e-9);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::mapfile::{MapFile, TileState};
-use crate::terrain::quadtree::VNode;
+use crate::terrain::quadtree::{PriorityFn, VNode};
+use anyhow::{anyhow, Error};
 use cgmath::Point3;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::ops::{Index, IndexMut};
@@ -74,6 +76,9 @@ impl Priority {
         assert!(value.is_finite());
         Priority(value)
     }
+    pub fn scaled(self, factor: f32) -> Self {
+        Self::from_f32(self.0 * factor)
+    }
 }
 impl Eq for Priority {}
 impl Ord for Priority {
@@ -82,8 +87,11 @@ impl Ord for Priority {
     }
 }
 
+/// Number of variants of `LayerType`.
+pub(crate) const NUM_LAYERS: usize = 5;
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub(crate) enum LayerType {
+pub enum LayerType {
     Displacements = 0,
     Albedo = 1,
     Roughness = 2,
@@ -138,6 +146,58 @@ pub(crate) struct LayerParams {
     pub texture_format: TextureFormat,
 }
 
+/// Reads the tiles for `pending` (layer, slot and node) from `mapfile`. Reads for all layers happen
+/// in parallel since decoding tiles from disk is the slow part of filling the cache. Returns the
+/// slot and data of each tile grouped by layer, in the order they were given, or an error if any
+/// of the tiles couldn't be read.
+pub(crate) fn read_tiles(
+    mapfile: &MapFile,
+    pending: Vec<(LayerType, usize, VNode)>,
+) -> Result<VecMap<Vec<(usize, Vec<u8>)>>, Error> {
+    read_tiles_with(pending, |ty, node| mapfile.read_tile(ty, node))
+}
+
+/// Like `read_tiles`, but reads each tile with `read`.
+pub fn read_tiles_with<F>(
+    pending: Vec<(LayerType, usize, VNode)>,
+    read: F,
+) -> Result<VecMap<Vec<(usize, Vec<u8>)>>, Error>
+where
+    F: Fn(LayerType, VNode) -> Option<Vec<u8>> + Sync,
+{
+    let tiles = pending
+        .into_par_iter()
+        .map(|(ty, slot, node)| {
+            let data =
+                read(ty, node).ok_or_else(|| anyhow!("failed to read {:?} tile {:?}", ty, node))?;
+            Ok((ty, slot, data))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut grouped: VecMap<Vec<_>> = VecMap::new();
+    for (ty, slot, data) in tiles {
+        grouped.entry(ty.index()).or_insert_with(Vec::new).push((slot, data));
+    }
+    Ok(grouped)
+}
+
+/// Checks that every entry of `layers` is stored under the index of its own layer type, so that
+/// indexing by `LayerType` and `LayerType::from_index` can't go out of range later.
+fn validate_layers(layers: &VecMap<LayerParams>) -> Result<(), Error> {
+    let invalid: Vec<String> = layers
+        .iter()
+        .filter(|(i, params)| *i >= NUM_LAYERS || params.layer_type.index() != *i)
+        .map(|(i, params)| format!("{} ({:?})", i, params.layer_type))
+        .collect();
+    if !invalid.is_empty() {
+        return Err(anyhow!("invalid layer indices: {}", invalid.join(", ")));
+    }
+    Ok(())
+}
+
+/// Number of slots in the cache when no memory budget is given.
+pub(crate) const DEFAULT_SIZE: usize = 512;
+
 struct Entry {
     priority: Priority,
     node: VNode,
@@ -145,6 +205,18 @@ struct Entry {
     generated: u32,
 }
 
+/// Counts of how requests for tiles were satisfied since the cache was created or the counts were
+/// last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Requests for nodes that already had a slot.
+    pub hits: u64,
+    /// Nodes that were given a slot, whether it was free or taken from another node.
+    pub misses: u64,
+    /// Nodes that lost their slot to a higher priority one.
+    pub evictions: u64,
+}
+
 pub(crate) struct TileCache {
     size: usize,
     slots: Vec<Entry>,
@@ -157,40 +229,90 @@ pub(crate) struct TileCache {
 
     /// Resolution of each tile in this cache.
     layers: VecMap<LayerParams>,
+
+    stats: CacheStats,
 }
 impl TileCache {
-    pub fn new(layers: VecMap<LayerParams>, size: usize) -> Self {
-        Self {
+    /// Creates a cache with room for `size` tiles of each layer. Fails if `layers` has entries
+    /// under an index that doesn't match their layer type.
+    pub fn new(layers: VecMap<LayerParams>, size: usize) -> Result<Self, Error> {
+        validate_layers(&layers)?;
+        Ok(Self {
             size,
             slots: Vec::new(),
             reverse: HashMap::new(),
             missing: Vec::new(),
             min_priority: Priority::none(),
             layers,
+            stats: CacheStats::default(),
+        })
+    }
+
+    /// Creates a cache with as many slots as fit in `memory_budget` bytes of GPU memory. Every
+    /// slot holds one tile of each layer, so the budget is divided by the combined size of a
+    /// tile from all layers. Fails if not even a single slot fits.
+    pub fn with_memory_budget(
+        layers: VecMap<LayerParams>,
+        memory_budget: usize,
+    ) -> Result<Self, Error> {
+        let bytes_per_slot = Self::bytes_per_slot(&layers);
+        let size = memory_budget / bytes_per_slot.max(1);
+        if size == 0 {
+            return Err(anyhow!(
+                "memory budget of {} bytes is smaller than a single {} byte slot",
+                memory_budget,
+                bytes_per_slot
+            ));
         }
+        Self::new(layers, size)
     }
 
-    pub fn update_priorities(&mut self, camera_cspace: Point3<f64>) {
+    /// Bytes of GPU memory taken by a single slot, which is one tile from each of `layers`.
+    pub fn bytes_per_slot(layers: &VecMap<LayerParams>) -> usize {
+        layers
+            .values()
+            .map(|layer| {
+                let block_size = layer.texture_format.block_size();
+                let blocks = (layer.texture_resolution / block_size) as usize;
+                blocks * blocks * layer.texture_format.bytes_per_block()
+            })
+            .sum()
+    }
+
+    /// Memory budget that results in a cache with `DEFAULT_SIZE` slots for `layers`.
+    pub fn default_memory_budget(layers: &VecMap<LayerParams>) -> usize {
+        DEFAULT_SIZE * Self::bytes_per_slot(layers)
+    }
+
+    pub fn update_priorities(&mut self, camera_cspace: Point3<f64>, priority_fn: &PriorityFn) {
         for entry in &mut self.slots {
-            entry.priority = entry.node.priority(camera_cspace);
+            entry.priority = priority_fn(&entry.node, camera_cspace);
         }
 
         self.min_priority = self.slots.iter().map(|s| s.priority).min().unwrap_or(Priority::none());
     }
 
     pub fn add_missing(&mut self, element: (Priority, VNode)) {
-        if !self.reverse.contains_key(&element.1)
-            && (element.0 > self.min_priority || self.slots.len() < self.size)
-        {
+        if self.reverse.contains_key(&element.1) {
+            self.stats.hits += 1;
+        } else if element.0 > self.min_priority || self.slots.len() < self.size {
             self.missing.push(element);
         }
     }
 
-    fn process_missing(&mut self) {
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    pub(crate) fn process_missing(&mut self) {
         // Find slots for missing entries.
         self.missing.sort();
         while !self.missing.is_empty() && self.slots.len() < self.size {
             let m = self.missing.pop().unwrap();
+            self.stats.misses += 1;
             self.reverse.insert(m.1, self.slots.len());
             self.slots.push(Entry { priority: m.0, node: m.1, valid: 0, generated: 0 });
         }
@@ -220,6 +342,8 @@ impl TileCache {
                     }
                 }
 
+                self.stats.misses += 1;
+                self.stats.evictions += 1;
                 self.reverse.remove(&self.slots[index].node);
                 self.reverse.insert(m.1, index);
                 self.slots[index] = Entry { priority: m.0, node: m.1, valid: 0, generated: 0 };
@@ -232,14 +356,14 @@ impl TileCache {
         self.missing.clear();
     }
 
-    pub(crate) fn upload_tiles(
+    /// Assigns slots to missing nodes, then finds the entries whose `ty` layer isn't resident.
+    /// Returns the slots and nodes whose tiles can be read from `mapfile`, along with the nodes
+    /// that have to be generated instead.
+    pub(crate) fn find_pending(
         &mut self,
-        device: &wgpu::Device,
-        encoder: &mut wgpu::CommandEncoder,
-        texture: &wgpu::Texture,
-        mapfile: &mut MapFile,
+        mapfile: &MapFile,
         ty: LayerType,
-    ) -> Vec<VNode> {
+    ) -> (Vec<(usize, VNode)>, Vec<VNode>) {
         self.process_missing();
 
         // Figure out which entries need to be uploaded
@@ -268,8 +392,21 @@ impl TileCache {
                 TileState::MissingBase => unreachable!(),
             }
         }
-        if pending_uploads.is_empty() {
-            return pending_generate;
+        (pending_uploads, pending_generate)
+    }
+
+    /// Copies the contents of `tiles` (slots and the data to put in them) into the `ty` layer of
+    /// the cache.
+    pub(crate) fn upload_tiles(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        ty: LayerType,
+        tiles: Vec<(usize, Vec<u8>)>,
+    ) {
+        if tiles.is_empty() {
+            return;
         }
 
         let resolution = self.resolution(ty) as usize;
@@ -277,10 +414,10 @@ impl TileCache {
         let bytes_per_block = self.layers[ty].texture_format.bytes_per_block();
         let row_bytes = resolution_blocks * bytes_per_block;
         let row_pitch = (row_bytes + 255) & !255;
-        let tiles = pending_uploads.len();
+        let num_tiles = tiles.len();
 
         let buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            size: (row_pitch * resolution_blocks * tiles) as u64,
+            size: (row_pitch * resolution_blocks * num_tiles) as u64,
             usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::MAP_WRITE,
             label: None,
             mapped_at_creation: true,
@@ -288,8 +425,7 @@ impl TileCache {
         let mut buffer_view = buffer.slice(..).get_mapped_range_mut();
 
         let mut i = 0;
-        for upload in &pending_uploads {
-            let data = mapfile.read_tile(ty, upload.1).unwrap();
+        for (_, data) in &tiles {
             for row in 0..resolution_blocks {
                 buffer_view[i..][..row_bytes]
                     .copy_from_slice(&data[row * row_bytes..][..row_bytes]);
@@ -299,7 +435,7 @@ impl TileCache {
 
         drop(buffer_view);
         buffer.unmap();
-        for (index, (slot, _)) in pending_uploads.drain(..).enumerate() {
+        for (index, &(slot, _)) in tiles.iter().enumerate() {
             encoder.copy_buffer_to_texture(
                 wgpu::BufferCopyView {
                     buffer: &buffer,
@@ -318,8 +454,6 @@ impl TileCache {
             );
             self.slots[slot].valid |= ty.bit_mask();
         }
-
-        pending_generate
     }
 
     pub fn make_cache_textures(&self, device: &wgpu::Device) -> VecMap<wgpu::Texture> {
@@ -423,3 +557,119 @@ impl TileCache {
     //     self.slots.iter().filter(|s| s.priority >= Priority::cutoff() && s.valid != 0).count()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(layer_type: LayerType) -> LayerParams {
+        LayerParams {
+            layer_type,
+            texture_resolution: 65,
+            texture_border_size: 0,
+            texture_format: TextureFormat::R32F,
+        }
+    }
+
+    #[test]
+    fn invalid_layer_index() {
+        let mut layers = VecMap::new();
+        layers.insert(LayerType::Heightmaps.index(), params(LayerType::Heightmaps));
+        assert!(TileCache::new(layers.clone(), 16).is_ok());
+
+        layers.insert(7, params(LayerType::Normals));
+        let error = TileCache::new(layers, 16).err().unwrap();
+        assert!(error.to_string().contains("7 (Normals)"));
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut layers = VecMap::new();
+        layers.insert(LayerType::Heightmaps.index(), params(LayerType::Heightmaps));
+        layers.insert(
+            LayerType::Normals.index(),
+            LayerParams {
+                layer_type: LayerType::Normals,
+                texture_resolution: 512,
+                texture_border_size: 2,
+                texture_format: TextureFormat::BC5,
+            },
+        );
+        let bytes_per_slot = 65 * 65 * 4 + 128 * 128 * 16;
+        assert_eq!(TileCache::bytes_per_slot(&layers), bytes_per_slot);
+
+        let budget = TileCache::default_memory_budget(&layers);
+        let cache = TileCache::with_memory_budget(layers.clone(), budget).unwrap();
+        assert_eq!(cache.size, DEFAULT_SIZE);
+
+        let cache = TileCache::with_memory_budget(layers.clone(), 3 * bytes_per_slot - 1).unwrap();
+        assert_eq!(cache.size, 2);
+        assert!(TileCache::with_memory_budget(layers, bytes_per_slot - 1).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let mut layers = VecMap::new();
+        layers.insert(LayerType::Heightmaps.index(), params(LayerType::Heightmaps));
+        let mut cache = TileCache::new(layers, 2).unwrap();
+
+        let mut nodes = Vec::new();
+        VNode::breadth_first(|node| {
+            nodes.push(node);
+            false
+        });
+
+        cache.add_missing((Priority::from_f32(2.0), nodes[0]));
+        cache.add_missing((Priority::from_f32(3.0), nodes[1]));
+        cache.process_missing();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2, evictions: 0 });
+
+        cache.add_missing((Priority::from_f32(2.0), nodes[0]));
+        cache.add_missing((Priority::from_f32(6.0), nodes[2]));
+        cache.process_missing();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3, evictions: 1 });
+        assert_eq!(cache.get_slot(nodes[2]), Some(0));
+
+        cache.reset_stats();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    /// Tiles for every node in the first levels of the quadtree, in each of the three layers that
+    /// are read from disk.
+    fn cold_cache_pending() -> Vec<(LayerType, usize, VNode)> {
+        let mut nodes = Vec::new();
+        VNode::breadth_first(|node| {
+            nodes.push(node);
+            node.level() < 2
+        });
+        let mut pending = Vec::new();
+        for &ty in &[LayerType::Heightmaps, LayerType::Normals, LayerType::Albedo] {
+            pending.extend(nodes.iter().enumerate().map(|(slot, &node)| (ty, slot, node)));
+        }
+        pending
+    }
+
+    #[test]
+    fn read_tiles_grouped() {
+        let pending = cold_cache_pending();
+        let nodes = pending.len() / 3;
+        let tiles = read_tiles_with(pending.clone(), |ty, _| Some(vec![ty as u8])).unwrap();
+        assert_eq!(tiles.len(), 3);
+        assert_eq!(tiles[LayerType::Normals.index()].len(), nodes);
+        assert_eq!(tiles[LayerType::Normals.index()][2], (2, vec![LayerType::Normals as u8]));
+
+        let missing = pending[5].2;
+        let error = read_tiles_with(
+            pending,
+            |_, node| {
+                if node == missing {
+                    None
+                } else {
+                    Some(Vec::new())
+                }
+            },
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("failed to read"));
+    }
+}